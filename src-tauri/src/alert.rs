@@ -0,0 +1,569 @@
+use crate::events::{Event, EventKind};
+use crate::stats::{self, StatsStore, Summary};
+use crate::{now_ms, AppState, Target};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter};
+
+/// Key of the rule list in the history database's `settings` table.
+pub const RULES_KEY: &str = "alert_rules";
+/// How often every rule is evaluated against every target.
+const EVAL_INTERVAL: Duration = Duration::from_secs(1);
+
+fn default_rule_window_ms() -> u64 {
+    60 * 1000
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    /// 0 to 1.
+    SuccessRate,
+    AvgMs,
+    P50Ms,
+    P90Ms,
+    P99Ms,
+    /// Failed probes since the last success; ignores `window_ms`.
+    ConsecutiveFailures,
+    /// Failed addresses in the latest result of an `address_policy: "all"`
+    /// target; ignores `window_ms`.
+    FailedMembers,
+}
+
+impl Metric {
+    fn value(self, summary: &Summary) -> Option<f64> {
+        match self {
+            Metric::SuccessRate => summary.success_rate,
+            Metric::AvgMs => summary.avg_ms,
+            Metric::P50Ms => summary.p50_ms,
+            Metric::P90Ms => summary.p90_ms,
+            Metric::P99Ms => summary.p99_ms,
+            Metric::ConsecutiveFailures => Some(summary.consecutive_failures as f64),
+            Metric::FailedMembers => summary.failed_members.map(f64::from),
+        }
+    }
+
+    /// Whether the metric is computed over the rule's `window_ms`.
+    fn windowed(self) -> bool {
+        !matches!(self, Metric::ConsecutiveFailures | Metric::FailedMembers)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Comparison {
+    #[serde(rename = ">")]
+    Above,
+    #[serde(rename = ">=")]
+    AtLeast,
+    #[serde(rename = "<")]
+    Below,
+    #[serde(rename = "<=")]
+    AtMost,
+}
+
+impl Comparison {
+    fn holds(self, value: f64, threshold: f64) -> bool {
+        match self {
+            Comparison::Above => value > threshold,
+            Comparison::AtLeast => value >= threshold,
+            Comparison::Below => value < threshold,
+            Comparison::AtMost => value <= threshold,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    #[default]
+    Warning,
+    Critical,
+}
+
+/// "`metric` `op` `threshold` over `window_ms`, for `for_ms`", applied to
+/// each of `targets` (all targets when empty).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AlertRule {
+    pub id: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub targets: Vec<String>,
+    pub metric: Metric,
+    pub op: Comparison,
+    pub threshold: f64,
+    /// The window `metric` is computed over.
+    #[serde(default = "default_rule_window_ms")]
+    pub window_ms: u64,
+    /// How long the condition has to hold before the alert fires.
+    #[serde(default)]
+    pub for_ms: u64,
+    #[serde(default)]
+    pub severity: Severity,
+    /// A firing alert resolves once `metric op resolve_threshold` no longer
+    /// holds; defaults to `threshold`. Set it apart from `threshold` so a
+    /// value hovering around it doesn't fire and resolve over and over.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolve_threshold: Option<f64>,
+    /// How long the resolve condition has to hold.
+    #[serde(default)]
+    pub resolve_for_ms: u64,
+}
+
+impl AlertRule {
+    fn applies_to(&self, target: &Target) -> bool {
+        self.targets.is_empty() || self.targets.contains(&target.id)
+    }
+
+    fn check(&self) -> Result<(), String> {
+        if self.id.trim().is_empty() {
+            return Err("id: required".into());
+        }
+        if !self.threshold.is_finite() || self.resolve_threshold.is_some_and(|t| !t.is_finite()) {
+            return Err("threshold: must be a number".into());
+        }
+        if self.metric.windowed()
+            && !(stats::MIN_WINDOW_MS..=stats::MAX_WINDOW_MS).contains(&self.window_ms)
+        {
+            return Err(format!(
+                "window_ms: must be between {} and {}",
+                stats::MIN_WINDOW_MS,
+                stats::MAX_WINDOW_MS
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// One rule firing for one target.
+#[derive(Debug, Clone, Serialize)]
+pub struct Alert {
+    pub rule_id: String,
+    pub rule_name: String,
+    pub target_id: String,
+    pub severity: Severity,
+    pub metric: Metric,
+    /// The metric when the alert fired, or when it resolved.
+    pub value: f64,
+    pub threshold: f64,
+    /// When the condition started to hold.
+    pub started: u64,
+    pub fired: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resolved: Option<u64>,
+}
+
+impl Alert {
+    fn resolve(mut self, value: Option<f64>, now: u64) -> Alert {
+        self.value = value.unwrap_or(self.value);
+        self.resolved = Some(now);
+        self
+    }
+}
+
+pub enum AlertChange {
+    Firing(Alert),
+    Resolved(Alert),
+}
+
+enum Phase {
+    /// The condition holds, but not for `for_ms` yet.
+    Pending { since: u64 },
+    Firing {
+        alert: Alert,
+        /// When the resolve condition started to hold.
+        resolving_since: Option<u64>,
+    },
+}
+
+/// Evaluates the rules against the rolling stats and tracks each
+/// (rule, target) pair from pending to firing to resolved.
+#[derive(Default)]
+pub struct AlertEngine {
+    rules: Vec<AlertRule>,
+    phases: HashMap<(String, String), Phase>,
+}
+
+impl AlertEngine {
+    pub fn rules(&self) -> &[AlertRule] {
+        &self.rules
+    }
+
+    /// Replaces the rules. Alerts of rules that were removed or changed are
+    /// resolved and returned.
+    pub fn set_rules(&mut self, rules: Vec<AlertRule>) -> Vec<AlertChange> {
+        let kept: HashSet<&str> = rules
+            .iter()
+            .filter(|rule| self.rules.contains(rule))
+            .map(|rule| rule.id.as_str())
+            .collect();
+        let mut changes = Vec::new();
+        let now = now_ms();
+        self.phases.retain(|(rule_id, _), phase| {
+            let keep = kept.contains(rule_id.as_str());
+            if let (false, Phase::Firing { alert, .. }) = (keep, &*phase) {
+                changes.push(AlertChange::Resolved(alert.clone().resolve(None, now)));
+            }
+            keep
+        });
+        self.rules = rules;
+        changes
+    }
+
+    /// How far back the rules look, so the stats store keeps enough results.
+    pub fn keep_ms(&self) -> u64 {
+        self.rules.iter().map(|r| r.window_ms).max().unwrap_or(0)
+    }
+
+    pub fn firing(&self) -> Vec<Alert> {
+        self.phases
+            .values()
+            .filter_map(|phase| match phase {
+                Phase::Firing { alert, .. } => Some(alert.clone()),
+                Phase::Pending { .. } => None,
+            })
+            .collect()
+    }
+
+    /// Advances every (rule, target) pair to `now`.
+    pub fn evaluate(
+        &mut self,
+        targets: &[Target],
+        stats: &StatsStore,
+        now: u64,
+    ) -> Vec<AlertChange> {
+        let mut changes = Vec::new();
+
+        // Alerts for targets that are gone resolve; pending ones are dropped.
+        self.phases.retain(|(_, target_id), phase| {
+            let keep = targets.iter().any(|t| &t.id == target_id);
+            if let (false, Phase::Firing { alert, .. }) = (keep, &*phase) {
+                changes.push(AlertChange::Resolved(alert.clone().resolve(None, now)));
+            }
+            keep
+        });
+
+        for rule in &self.rules {
+            for target in targets.iter().filter(|t| rule.applies_to(t)) {
+                // Without data (e.g. no successes for a latency metric) the
+                // alert stays where it is.
+                let Some(value) = rule
+                    .metric
+                    .value(&stats.summary(&target.id, rule.window_ms))
+                else {
+                    continue;
+                };
+                let key = (rule.id.clone(), target.id.clone());
+                let phase = self.phases.remove(&key);
+                let next = match phase {
+                    Some(Phase::Firing {
+                        alert,
+                        resolving_since,
+                    }) => {
+                        let threshold = rule.resolve_threshold.unwrap_or(rule.threshold);
+                        let since = resolving_since.unwrap_or(now);
+                        if rule.op.holds(value, threshold) {
+                            Some(Phase::Firing {
+                                alert,
+                                resolving_since: None,
+                            })
+                        } else if now.saturating_sub(since) >= rule.resolve_for_ms {
+                            changes.push(AlertChange::Resolved(alert.resolve(Some(value), now)));
+                            None
+                        } else {
+                            Some(Phase::Firing {
+                                alert,
+                                resolving_since: Some(since),
+                            })
+                        }
+                    }
+                    pending => {
+                        let since = match pending {
+                            Some(Phase::Pending { since }) => since,
+                            _ => now,
+                        };
+                        if !rule.op.holds(value, rule.threshold) {
+                            None
+                        } else if now.saturating_sub(since) >= rule.for_ms {
+                            let alert = Alert {
+                                rule_id: rule.id.clone(),
+                                rule_name: rule.name.clone(),
+                                target_id: target.id.clone(),
+                                severity: rule.severity,
+                                metric: rule.metric,
+                                value,
+                                threshold: rule.threshold,
+                                started: since,
+                                fired: now,
+                                resolved: None,
+                            };
+                            changes.push(AlertChange::Firing(alert.clone()));
+                            Some(Phase::Firing {
+                                alert,
+                                resolving_since: None,
+                            })
+                        } else {
+                            Some(Phase::Pending { since })
+                        }
+                    }
+                };
+                if let Some(next) = next {
+                    self.phases.insert(key, next);
+                }
+            }
+        }
+        changes
+    }
+}
+
+/// Checks a rule list before it replaces the current one.
+pub fn check_rules(rules: &[AlertRule]) -> Result<(), String> {
+    let mut ids = HashSet::new();
+    for rule in rules {
+        rule.check()
+            .map_err(|e| format!("invalid_rule: {}: {}", rule.id, e))?;
+        if !ids.insert(rule.id.as_str()) {
+            return Err(format!("invalid_rule: {}: id: duplicate", rule.id));
+        }
+    }
+    Ok(())
+}
+
+/// Emits `alert:firing` / `alert:resolved` and logs them.
+pub fn publish(app: &AppHandle, state: &AppState, changes: Vec<AlertChange>) {
+    for change in changes {
+        let (timestamp, target_id, kind) = match change {
+            AlertChange::Firing(alert) => {
+                let _ = app.emit("alert:firing", &alert);
+                (
+                    alert.fired,
+                    alert.target_id.clone(),
+                    EventKind::AlertFiring(alert),
+                )
+            }
+            AlertChange::Resolved(alert) => {
+                let _ = app.emit("alert:resolved", &alert);
+                (
+                    alert.resolved.unwrap_or(alert.fired),
+                    alert.target_id.clone(),
+                    EventKind::AlertResolved(alert),
+                )
+            }
+        };
+        if let Some(history) = state.history.get() {
+            history.record_event(&Event {
+                timestamp,
+                target_id,
+                kind,
+            });
+        }
+    }
+}
+
+/// Evaluates the rules every `EVAL_INTERVAL`.
+pub fn start_alert_loop(app: AppHandle, state: Arc<AppState>) {
+    tauri::async_runtime::spawn(async move {
+        let mut ticker = tokio::time::interval(EVAL_INTERVAL);
+        loop {
+            ticker.tick().await;
+            let targets = state.targets.read().unwrap().clone();
+            let changes = {
+                let stats = state.stats.lock().unwrap();
+                state
+                    .alerts
+                    .lock()
+                    .unwrap()
+                    .evaluate(&targets, &stats, now_ms())
+            };
+            publish(&app, &state, changes);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ProbeResult;
+    use serde_json::json;
+
+    fn target() -> Target {
+        serde_json::from_value(
+            json!({ "id": "t", "name": "T", "host": "example.com", "port": 443 }),
+        )
+        .unwrap()
+    }
+
+    fn rule(rule: serde_json::Value) -> AlertRule {
+        let mut base =
+            json!({ "id": "r", "metric": "consecutive_failures", "op": ">=", "threshold": 1 });
+        base.as_object_mut()
+            .unwrap()
+            .extend(rule.as_object().unwrap().clone());
+        serde_json::from_value(base).unwrap()
+    }
+
+    fn engine(rules: Vec<AlertRule>) -> (AlertEngine, StatsStore) {
+        let mut engine = AlertEngine::default();
+        engine.set_rules(rules);
+        let mut stats = StatsStore::default();
+        stats.set_keep_ms(engine.keep_ms());
+        (engine, stats)
+    }
+
+    fn record(stats: &mut StatsStore, results: &[bool]) {
+        for &ok in results {
+            stats.record(&ProbeResult {
+                id: "t".into(),
+                ok,
+                latency_ms: 10.0,
+                timestamp: now_ms(),
+                ..Default::default()
+            });
+        }
+    }
+
+    fn fired(changes: &[AlertChange]) -> Option<&Alert> {
+        match changes {
+            [AlertChange::Firing(alert)] => Some(alert),
+            _ => None,
+        }
+    }
+
+    fn resolved(changes: &[AlertChange]) -> Option<&Alert> {
+        match changes {
+            [AlertChange::Resolved(alert)] => Some(alert),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn fires_once_the_condition_held_for_for_ms() {
+        let (mut engine, mut stats) =
+            engine(vec![rule(json!({ "threshold": 2, "for_ms": 10_000 }))]);
+        let targets = [target()];
+        record(&mut stats, &[false, false]);
+        assert!(engine.evaluate(&targets, &stats, 1_000).is_empty());
+        assert!(engine.evaluate(&targets, &stats, 10_999).is_empty());
+        assert!(engine.firing().is_empty());
+
+        let changes = engine.evaluate(&targets, &stats, 11_000);
+        let alert = fired(&changes).expect("not fired");
+        assert_eq!(
+            (alert.started, alert.fired, alert.value),
+            (1_000, 11_000, 2.0)
+        );
+        assert_eq!(engine.firing().len(), 1);
+        assert!(engine.evaluate(&targets, &stats, 12_000).is_empty());
+    }
+
+    #[test]
+    fn pending_resets_when_the_condition_stops_holding() {
+        let (mut engine, mut stats) = engine(vec![rule(json!({ "for_ms": 10_000 }))]);
+        let targets = [target()];
+        record(&mut stats, &[false]);
+        engine.evaluate(&targets, &stats, 0);
+        record(&mut stats, &[true]);
+        engine.evaluate(&targets, &stats, 5_000);
+        record(&mut stats, &[false]);
+        assert!(engine.evaluate(&targets, &stats, 10_000).is_empty());
+        assert!(fired(&engine.evaluate(&targets, &stats, 20_000)).is_some());
+    }
+
+    #[test]
+    fn resolve_threshold_keeps_the_alert_firing() {
+        let (mut engine, mut stats) = engine(vec![rule(json!({
+            "metric": "success_rate",
+            "op": "<",
+            "threshold": 0.5,
+            "resolve_threshold": 0.9,
+        }))]);
+        let targets = [target()];
+        record(&mut stats, &[false; 4]);
+        assert!(fired(&engine.evaluate(&targets, &stats, 0)).is_some());
+
+        // 0.5 is past `threshold` but not `resolve_threshold`.
+        record(&mut stats, &[true; 4]);
+        assert!(engine.evaluate(&targets, &stats, 1_000).is_empty());
+
+        record(&mut stats, &[true; 36]);
+        let changes = engine.evaluate(&targets, &stats, 2_000);
+        let alert = resolved(&changes).expect("not resolved");
+        assert_eq!(alert.resolved, Some(2_000));
+        assert!((alert.value - 40.0 / 44.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn resolves_once_clear_for_resolve_for_ms() {
+        let (mut engine, mut stats) = engine(vec![rule(json!({ "resolve_for_ms": 5_000 }))]);
+        let targets = [target()];
+        record(&mut stats, &[false]);
+        assert!(fired(&engine.evaluate(&targets, &stats, 0)).is_some());
+
+        record(&mut stats, &[true]);
+        assert!(engine.evaluate(&targets, &stats, 1_000).is_empty());
+        // Failing again restarts the resolve clock.
+        record(&mut stats, &[false]);
+        assert!(engine.evaluate(&targets, &stats, 2_000).is_empty());
+        record(&mut stats, &[true]);
+        assert!(engine.evaluate(&targets, &stats, 3_000).is_empty());
+        assert!(engine.evaluate(&targets, &stats, 7_999).is_empty());
+        assert!(resolved(&engine.evaluate(&targets, &stats, 8_000)).is_some());
+        assert!(engine.firing().is_empty());
+    }
+
+    #[test]
+    fn failed_members_come_from_the_latest_result() {
+        let (mut engine, mut stats) = engine(vec![rule(json!({ "metric": "failed_members" }))]);
+        let targets = [target()];
+        let result = |failed_members| ProbeResult {
+            id: "t".into(),
+            ok: true,
+            timestamp: now_ms(),
+            failed_members,
+            ..Default::default()
+        };
+        stats.record(&result(None));
+        assert!(engine.evaluate(&targets, &stats, 0).is_empty());
+        stats.record(&result(Some(1)));
+        let changes = engine.evaluate(&targets, &stats, 1_000);
+        assert_eq!(fired(&changes).expect("not fired").value, 1.0);
+        stats.record(&result(Some(0)));
+        assert!(resolved(&engine.evaluate(&targets, &stats, 2_000)).is_some());
+    }
+
+    #[test]
+    fn removed_target_resolves() {
+        let (mut engine, mut stats) = engine(vec![rule(json!({}))]);
+        record(&mut stats, &[false]);
+        assert!(fired(&engine.evaluate(&[target()], &stats, 0)).is_some());
+        let changes = engine.evaluate(&[], &stats, 1_000);
+        assert_eq!(
+            resolved(&changes).expect("not resolved").resolved,
+            Some(1_000)
+        );
+        assert!(engine.firing().is_empty());
+    }
+
+    #[test]
+    fn set_rules_resolves_changed_rules_only() {
+        let kept = rule(json!({ "id": "kept" }));
+        let changed = rule(json!({ "id": "changed" }));
+        let (mut engine, mut stats) = engine(vec![kept.clone(), changed.clone()]);
+        record(&mut stats, &[false]);
+        assert_eq!(engine.evaluate(&[target()], &stats, 0).len(), 2);
+
+        let changes = engine.set_rules(vec![
+            kept,
+            AlertRule {
+                threshold: 5.0,
+                ..changed
+            },
+        ]);
+        assert_eq!(resolved(&changes).expect("not resolved").rule_id, "changed");
+        let firing: Vec<_> = engine.firing().into_iter().map(|a| a.rule_id).collect();
+        assert_eq!(firing, ["kept"]);
+    }
+}
@@ -0,0 +1,242 @@
+use crate::{elapsed_ms, ErrorKind, ProbeError, ProbeResult};
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::timeout_at;
+
+const MAX_BANNER_BYTES: usize = 4096;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BannerSettings {
+    /// Line to send before reading (e.g. "EHLO monitor").
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub banner_send: Option<String>,
+    /// Regex the first line from the server must match.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub banner_expect: Option<String>,
+}
+
+/// Reads up to the first newline. A server that sends a partial line and
+/// then closes still counts as having sent that line.
+async fn read_line(stream: &mut TcpStream) -> Result<String, ProbeError> {
+    let mut line = Vec::new();
+    let mut chunk = [0u8; 512];
+    loop {
+        let n = stream
+            .read(&mut chunk)
+            .await
+            .map_err(|e| ProbeError::io("banner_error", e))?;
+        if n == 0 {
+            if line.is_empty() {
+                return Err(ProbeError::new(
+                    ErrorKind::ProtocolError,
+                    "banner_error: connection closed before banner",
+                ));
+            }
+            break;
+        }
+        line.extend_from_slice(&chunk[..n]);
+        if let Some(pos) = line.iter().position(|&b| b == b'\n') {
+            line.truncate(pos);
+            break;
+        }
+        if line.len() >= MAX_BANNER_BYTES {
+            line.truncate(MAX_BANNER_BYTES);
+            break;
+        }
+    }
+    Ok(String::from_utf8_lossy(&line).trim_end().to_string())
+}
+
+/// Optionally sends `banner_send` on an established connection, then reads
+/// the first line the server returns and checks it against `banner_expect`
+/// (a regex). Time to banner is measured from the completed connect, time
+/// to first byte from the sent line (or the connect, if nothing is sent).
+pub async fn banner_probe(
+    mut stream: TcpStream,
+    banner: &BannerSettings,
+    start: Instant,
+    deadline: tokio::time::Instant,
+) -> ProbeResult {
+    let connected = Instant::now();
+    let mut asked = connected;
+    let fail = |error: ProbeError| ProbeResult {
+        ok: false,
+        latency_ms: elapsed_ms(start),
+        error: Some(error),
+        ..Default::default()
+    };
+
+    let expect = match banner.banner_expect.as_deref().filter(|s| !s.is_empty()) {
+        Some(pattern) => match regex::Regex::new(pattern) {
+            Ok(re) => Some(re),
+            Err(e) => return fail(ProbeError::invalid(format!("invalid_regex: {}", e))),
+        },
+        None => None,
+    };
+
+    if let Some(send) = banner.banner_send.as_deref().filter(|s| !s.is_empty()) {
+        let mut line = send.to_string();
+        if !line.ends_with('\n') {
+            line.push_str("\r\n");
+        }
+        let sent = match timeout_at(deadline, stream.write_all(line.as_bytes())).await {
+            Ok(r) => r.map_err(|e| ProbeError::io("send_error", e)),
+            Err(_) => Err(ProbeError::timeout()),
+        };
+        if let Err(e) = sent {
+            return fail(e);
+        }
+        asked = Instant::now();
+    }
+
+    let line = match timeout_at(deadline, read_line(&mut stream)).await {
+        Ok(Ok(l)) => l,
+        Ok(Err(e)) => return fail(e),
+        Err(_) => return fail(ProbeError::timeout()),
+    };
+    let first_byte_ms = elapsed_ms(asked);
+    let banner_ms = elapsed_ms(connected);
+    let latency_ms = elapsed_ms(start);
+
+    let error = match &expect {
+        Some(re) if !re.is_match(&line) => Some(ProbeError::new(
+            ErrorKind::ProtocolMismatch,
+            format!("banner_mismatch: {}", line),
+        )),
+        _ => None,
+    };
+
+    ProbeResult {
+        ok: error.is_none(),
+        latency_ms,
+        error,
+        first_byte_ms: Some(first_byte_ms),
+        banner_ms: Some(banner_ms),
+        banner: Some(line),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    /// Accepts one connection, reads `read` bytes if asked to, writes `reply`
+    /// and holds the connection open for `hold`. Passes on what it read.
+    fn serve(read: usize, reply: &'static [u8], hold: Duration) -> (u16, mpsc::Receiver<Vec<u8>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut received = vec![0u8; read];
+            stream.read_exact(&mut received).unwrap();
+            let _ = tx.send(received);
+            stream.write_all(reply).unwrap();
+            thread::sleep(hold);
+        });
+        (port, rx)
+    }
+
+    async fn probe(port: u16, banner: BannerSettings, timeout: Duration) -> ProbeResult {
+        let start = Instant::now();
+        let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        banner_probe(
+            stream,
+            &banner,
+            start,
+            tokio::time::Instant::now() + timeout,
+        )
+        .await
+    }
+
+    fn settings(send: Option<&str>, expect: Option<&str>) -> BannerSettings {
+        BannerSettings {
+            banner_send: send.map(String::from),
+            banner_expect: expect.map(String::from),
+        }
+    }
+
+    const LONG: Duration = Duration::from_secs(5);
+    const SHORT: Duration = Duration::from_millis(300);
+
+    #[tokio::test]
+    async fn reads_the_first_line() {
+        let (port, _) = serve(0, b"220 mail.example.com ESMTP\r\n250 more\r\n", SHORT);
+        let result = probe(port, settings(None, Some("^220 ")), LONG).await;
+        assert!(result.ok, "{:?}", result.error);
+        assert_eq!(result.banner.as_deref(), Some("220 mail.example.com ESMTP"));
+        assert!(result.banner_ms.is_some() && result.first_byte_ms.is_some());
+    }
+
+    #[tokio::test]
+    async fn sends_a_line_first() {
+        let (port, received) = serve(14, b"250 hello\n", SHORT);
+        let result = probe(port, settings(Some("EHLO monitor"), None), LONG).await;
+        assert!(result.ok, "{:?}", result.error);
+        assert_eq!(received.recv().unwrap(), b"EHLO monitor\r\n");
+        assert_eq!(result.banner.as_deref(), Some("250 hello"));
+    }
+
+    #[tokio::test]
+    async fn mismatch_fails_with_the_line() {
+        let (port, _) = serve(0, b"SSH-2.0-OpenSSH_9.6\r\n", SHORT);
+        let result = probe(port, settings(None, Some("^220")), LONG).await;
+        let error = result.error.unwrap();
+        assert_eq!(error.kind, ErrorKind::ProtocolMismatch);
+        assert_eq!(error.detail, "banner_mismatch: SSH-2.0-OpenSSH_9.6");
+        assert_eq!(result.banner.as_deref(), Some("SSH-2.0-OpenSSH_9.6"));
+    }
+
+    #[tokio::test]
+    async fn partial_line_counts_once_the_peer_closes() {
+        let (port, _) = serve(0, b"220 no newline", Duration::ZERO);
+        let result = probe(port, settings(None, None), LONG).await;
+        assert!(result.ok, "{:?}", result.error);
+        assert_eq!(result.banner.as_deref(), Some("220 no newline"));
+    }
+
+    #[tokio::test]
+    async fn partial_line_left_open_runs_to_the_deadline() {
+        let (port, _) = serve(0, b"220 no newline", LONG);
+        let start = Instant::now();
+        let result = probe(port, settings(None, None), SHORT).await;
+        assert!(start.elapsed() >= SHORT);
+        assert_eq!(result.error.unwrap().kind, ErrorKind::Timeout);
+        assert_eq!(result.banner, None);
+    }
+
+    #[tokio::test]
+    async fn long_line_is_cut_off() {
+        static LONG_LINE: [u8; MAX_BANNER_BYTES + 100] = [b'x'; MAX_BANNER_BYTES + 100];
+        let (port, _) = serve(0, &LONG_LINE, SHORT);
+        let result = probe(port, settings(None, None), LONG).await;
+        assert_eq!(result.banner.unwrap().len(), MAX_BANNER_BYTES);
+    }
+
+    #[tokio::test]
+    async fn silent_peer_times_out() {
+        let (port, _) = serve(0, b"", LONG);
+        let result = probe(port, settings(None, None), SHORT).await;
+        assert_eq!(result.error.unwrap().kind, ErrorKind::Timeout);
+    }
+
+    #[tokio::test]
+    async fn peer_closing_without_a_banner_fails() {
+        let (port, _) = serve(0, b"", Duration::ZERO);
+        let result = probe(port, settings(None, None), LONG).await;
+        let error = result.error.unwrap();
+        assert_eq!(error.kind, ErrorKind::ProtocolError);
+        assert_eq!(
+            error.detail,
+            "banner_error: connection closed before banner"
+        );
+    }
+}
@@ -0,0 +1,509 @@
+use crate::net::Bind;
+use crate::{elapsed_ms, now_ms, ErrorKind, ProbeError, ProbeResult};
+use hickory_proto::op::{Message, MessageType, OpCode, Query, ResponseCode};
+use hickory_proto::rr::{Name, RData, Record, RecordType};
+use serde::{Deserialize, Serialize};
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const MAX_UDP_RESPONSE: usize = 4096;
+/// How long `lookup` still waits for the other address family once one has
+/// answered.
+const FAMILY_GRACE: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DnsSettings {
+    /// Name to query; the target's `host`/`port` is the resolver to ask.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dns_name: Option<String>,
+    /// A (default), AAAA, CNAME, MX or TXT.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dns_record_type: Option<String>,
+    /// Values that must all appear in the answer set.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dns_expect: Vec<String>,
+}
+
+fn next_query_id() -> u16 {
+    static COUNTER: AtomicU16 = AtomicU16::new(0);
+    let seed = (now_ms() as u16).rotate_left(7);
+    seed ^ COUNTER.fetch_add(1, Ordering::Relaxed)
+}
+
+pub fn parse_record_type(name: Option<&str>) -> Result<RecordType, ProbeError> {
+    match name.map(str::to_ascii_uppercase).as_deref() {
+        None | Some("") | Some("A") => Ok(RecordType::A),
+        Some("AAAA") => Ok(RecordType::AAAA),
+        Some("CNAME") => Ok(RecordType::CNAME),
+        Some("MX") => Ok(RecordType::MX),
+        Some("TXT") => Ok(RecordType::TXT),
+        Some(other) => Err(ProbeError::invalid(format!(
+            "invalid_record_type: {}",
+            other
+        ))),
+    }
+}
+
+fn rcode_name(code: ResponseCode) -> String {
+    match code {
+        ResponseCode::NoError => "NOERROR".into(),
+        ResponseCode::FormErr => "FORMERR".into(),
+        ResponseCode::ServFail => "SERVFAIL".into(),
+        ResponseCode::NXDomain => "NXDOMAIN".into(),
+        ResponseCode::NotImp => "NOTIMP".into(),
+        ResponseCode::Refused => "REFUSED".into(),
+        other => format!("RCODE{}", u16::from(other)),
+    }
+}
+
+/// Renders an answer the way a user would write it in `dns_expect`:
+/// addresses as-is, names without the trailing dot, MX as "pref exchange",
+/// TXT as the concatenated strings.
+fn render_answer(record: &Record) -> Option<String> {
+    let rendered = match record.data()? {
+        RData::A(a) => a.to_string(),
+        RData::AAAA(a) => a.to_string(),
+        RData::CNAME(name) => name.to_string().trim_end_matches('.').to_string(),
+        RData::MX(mx) => format!(
+            "{} {}",
+            mx.preference(),
+            mx.exchange().to_string().trim_end_matches('.')
+        ),
+        RData::TXT(txt) => txt
+            .txt_data()
+            .iter()
+            .map(|part| String::from_utf8_lossy(part).into_owned())
+            .collect(),
+        other => other.to_string(),
+    };
+    Some(rendered)
+}
+
+fn answer_matches(answer: &str, expected: &str) -> bool {
+    answer.eq_ignore_ascii_case(expected.trim().trim_end_matches('.'))
+}
+
+fn dns_timeout() -> ProbeError {
+    ProbeError::new(ErrorKind::DnsTimeout, "dns_timeout")
+}
+
+fn dns_error(detail: impl std::fmt::Display) -> ProbeError {
+    ProbeError::new(ErrorKind::DnsFailure, format!("dns_error: {}", detail))
+}
+
+fn io_error(e: io::Error) -> ProbeError {
+    match e.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => dns_timeout(),
+        io::ErrorKind::ConnectionRefused => {
+            ProbeError::new(ErrorKind::ConnectRefused, format!("dns_error: {}", e))
+        }
+        io::ErrorKind::HostUnreachable | io::ErrorKind::NetworkUnreachable => {
+            ProbeError::new(ErrorKind::HostUnreachable, format!("dns_error: {}", e))
+        }
+        _ => dns_error(e),
+    }
+}
+
+fn remaining(deadline: Instant) -> Result<Duration, ProbeError> {
+    deadline
+        .checked_duration_since(Instant::now())
+        .filter(|d| !d.is_zero())
+        .ok_or_else(dns_timeout)
+}
+
+fn query_udp(
+    server: SocketAddr,
+    query: &[u8],
+    id: u16,
+    bind: &Bind,
+    deadline: Instant,
+) -> Result<Message, ProbeError> {
+    let sock = UdpSocket::bind(bind.local(server)).map_err(ProbeError::bind)?;
+    bind.device(SockRef::from(&sock))?;
+    sock.connect(server).map_err(io_error)?;
+    sock.send(query).map_err(io_error)?;
+
+    let mut buf = [0u8; MAX_UDP_RESPONSE];
+    loop {
+        sock.set_read_timeout(Some(remaining(deadline)?))
+            .map_err(io_error)?;
+        let n = sock.recv(&mut buf).map_err(io_error)?;
+        // Ignore stray datagrams that don't answer our query.
+        if let Ok(msg) = Message::from_vec(&buf[..n]) {
+            if msg.id() == id && msg.message_type() == MessageType::Response {
+                return Ok(msg);
+            }
+        }
+    }
+}
+
+fn query_tcp(
+    server: SocketAddr,
+    query: &[u8],
+    id: u16,
+    bind: &Bind,
+    deadline: Instant,
+) -> Result<Message, ProbeError> {
+    let sock = Socket::new(
+        Domain::for_address(server),
+        Type::STREAM,
+        Some(Protocol::TCP),
+    )
+    .map_err(io_error)?;
+    bind.apply(SockRef::from(&sock), server)?;
+    sock.connect_timeout(&server.into(), remaining(deadline)?)
+        .map_err(io_error)?;
+    let mut stream = TcpStream::from(sock);
+    let left = remaining(deadline)?;
+    stream
+        .set_read_timeout(Some(left))
+        .and_then(|_| stream.set_write_timeout(Some(left)))
+        .map_err(io_error)?;
+
+    let mut framed = Vec::with_capacity(query.len() + 2);
+    framed.extend_from_slice(&(query.len() as u16).to_be_bytes());
+    framed.extend_from_slice(query);
+    stream.write_all(&framed).map_err(io_error)?;
+
+    let mut len = [0u8; 2];
+    stream.read_exact(&mut len).map_err(io_error)?;
+    let mut buf = vec![0u8; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut buf).map_err(io_error)?;
+    let msg = Message::from_vec(&buf)
+        .map_err(|e| ProbeError::new(ErrorKind::ProtocolError, format!("dns_error: {}", e)))?;
+    if msg.id() != id {
+        return Err(ProbeError::new(
+            ErrorKind::ProtocolError,
+            "dns_error: response id mismatch",
+        ));
+    }
+    Ok(msg)
+}
+
+/// Sends one query to `server` over UDP, retrying over TCP when the answer
+/// is truncated.
+fn ask(
+    server: SocketAddr,
+    name: Name,
+    record_type: RecordType,
+    bind: &Bind,
+    deadline: Instant,
+) -> Result<Message, ProbeError> {
+    let id = next_query_id();
+    let mut msg = Message::new();
+    msg.set_id(id)
+        .set_message_type(MessageType::Query)
+        .set_op_code(OpCode::Query)
+        .set_recursion_desired(true)
+        .add_query(Query::query(name, record_type));
+    let query = msg
+        .to_vec()
+        .map_err(|e| ProbeError::invalid(format!("invalid_query: {}", e)))?;
+
+    let response = query_udp(server, &query, id, bind, deadline)?;
+    if response.truncated() {
+        return query_tcp(server, &query, id, bind, deadline);
+    }
+    Ok(response)
+}
+
+/// The addresses of one family `server` gives for `name`.
+fn lookup_family(
+    server: SocketAddr,
+    name: Name,
+    record_type: RecordType,
+    bind: &Bind,
+    deadline: Instant,
+) -> Result<Vec<IpAddr>, ProbeError> {
+    let response = ask(server, name, record_type, bind, deadline)?;
+    match response.response_code() {
+        ResponseCode::NoError => {}
+        ResponseCode::NXDomain => {
+            return Err(ProbeError::new(
+                ErrorKind::DnsNxDomain,
+                "dns_rcode: NXDOMAIN",
+            ))
+        }
+        code => {
+            return Err(ProbeError::new(
+                ErrorKind::DnsFailure,
+                format!("dns_rcode: {}", rcode_name(code)),
+            ))
+        }
+    }
+    Ok(response
+        .answers()
+        .iter()
+        .filter_map(|record| match record.data()? {
+            RData::A(a) => Some(IpAddr::V4(a.0)),
+            RData::AAAA(a) => Some(IpAddr::V6(a.0)),
+            _ => None,
+        })
+        .collect())
+}
+
+/// Resolves `host` to its A and AAAA addresses by asking `server`, for
+/// targets that don't use the system resolver. Both are asked at once, and
+/// either family's addresses are enough: resolvers that choke on AAAA
+/// queries are common. Once one family has answered, the other only gets
+/// `FAMILY_GRACE` more, so a silently dropped query doesn't eat the time
+/// the probe needs to connect.
+pub fn lookup(
+    server: SocketAddr,
+    host: &str,
+    bind: &Bind,
+    deadline: Instant,
+) -> Result<Vec<IpAddr>, ProbeError> {
+    let name =
+        Name::from_utf8(host).map_err(|e| ProbeError::invalid(format!("invalid_host: {}", e)))?;
+    let (tx, rx) = mpsc::channel();
+    for record_type in [RecordType::A, RecordType::AAAA] {
+        let (tx, name, bind) = (tx.clone(), name.clone(), bind.clone());
+        // Not scoped: a query left behind runs out on its own at `deadline`.
+        thread::spawn(move || {
+            let _ = tx.send((
+                record_type,
+                lookup_family(server, name, record_type, &bind, deadline),
+            ));
+        });
+    }
+    drop(tx);
+
+    let (mut v4, mut v6) = (None, None);
+    while v4.is_none() || v6.is_none() {
+        let answered = [&v4, &v6]
+            .into_iter()
+            .flatten()
+            .any(|r: &Result<Vec<IpAddr>, ProbeError>| matches!(r, Ok(ips) if !ips.is_empty()));
+        let mut wait = deadline.saturating_duration_since(Instant::now());
+        if answered {
+            wait = wait.min(FAMILY_GRACE);
+        }
+        match rx.recv_timeout(wait) {
+            Ok((RecordType::A, result)) => v4 = Some(result),
+            Ok((_, result)) => v6 = Some(result),
+            Err(_) => break,
+        }
+    }
+    let (v4, v6) = (
+        v4.unwrap_or_else(|| Err(dns_timeout())),
+        v6.unwrap_or_else(|| Err(dns_timeout())),
+    );
+    match (v4, v6) {
+        (Ok(mut v4), Ok(v6)) => {
+            v4.extend(v6);
+            if v4.is_empty() {
+                return Err(ProbeError::new(ErrorKind::DnsFailure, "dns_failed"));
+            }
+            Ok(v4)
+        }
+        // With nothing from the other family, its error says more than
+        // an empty answer.
+        (Ok(ips), Err(e)) | (Err(e), Ok(ips)) if ips.is_empty() => Err(e),
+        (Ok(ips), Err(_)) | (Err(_), Ok(ips)) => Ok(ips),
+        (Err(e), Err(_)) => Err(e),
+    }
+}
+
+/// Sends a real query to the resolver at `server` (UDP, retried over TCP
+/// when the answer is truncated) and checks the rcode and answer set.
+pub fn dns_probe(
+    server: SocketAddr,
+    dns: &DnsSettings,
+    bind: &Bind,
+    start: Instant,
+    deadline: Instant,
+) -> ProbeResult {
+    let fail = |error: ProbeError| ProbeResult {
+        ok: false,
+        latency_ms: elapsed_ms(start),
+        error: Some(error),
+        ..Default::default()
+    };
+
+    let record_type = match parse_record_type(dns.dns_record_type.as_deref()) {
+        Ok(t) => t,
+        Err(e) => return fail(e),
+    };
+    let qname = match dns.dns_name.as_deref().map(str::trim) {
+        Some(n) if !n.is_empty() => n,
+        _ => return fail(ProbeError::invalid("invalid_query: dns_name is required")),
+    };
+    let name = match Name::from_utf8(qname) {
+        Ok(n) => n,
+        Err(e) => return fail(ProbeError::invalid(format!("invalid_query: {}", e))),
+    };
+
+    let asked = Instant::now();
+    let response = match ask(server, name, record_type, bind, deadline) {
+        Ok(r) => r,
+        Err(e) => return fail(e),
+    };
+    let first_byte_ms = elapsed_ms(asked);
+    let latency_ms = elapsed_ms(start);
+
+    let rcode = rcode_name(response.response_code());
+    let answers: Vec<String> = response
+        .answers()
+        .iter()
+        .filter_map(render_answer)
+        .collect();
+
+    let error = match response.response_code() {
+        ResponseCode::NoError if answers.is_empty() => Some(ProbeError::new(
+            ErrorKind::ProtocolMismatch,
+            "dns_no_answer",
+        )),
+        ResponseCode::NoError => dns
+            .dns_expect
+            .iter()
+            .find(|expected| !answers.iter().any(|a| answer_matches(a, expected)))
+            .map(|missing| {
+                ProbeError::new(
+                    ErrorKind::ProtocolMismatch,
+                    format!("dns_unexpected_answer: missing {}", missing),
+                )
+            }),
+        ResponseCode::NXDomain => Some(ProbeError::new(
+            ErrorKind::DnsNxDomain,
+            format!("dns_rcode: {}", rcode),
+        )),
+        _ => Some(ProbeError::new(
+            ErrorKind::DnsFailure,
+            format!("dns_rcode: {}", rcode),
+        )),
+    };
+
+    ProbeResult {
+        ok: error.is_none(),
+        latency_ms,
+        error,
+        first_byte_ms: Some(first_byte_ms),
+        dns_rcode: Some(rcode),
+        dns_answers: answers,
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::rr::rdata::{A, AAAA};
+
+    enum Reply {
+        Answer(&'static [&'static str]),
+        Rcode(ResponseCode),
+        Silent,
+    }
+
+    /// A resolver on localhost answering A queries with `a` and AAAA
+    /// queries with `aaaa`.
+    fn serve(a: Reply, aaaa: Reply) -> SocketAddr {
+        let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = sock.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            while let Ok((n, from)) = sock.recv_from(&mut buf) {
+                let query = Message::from_vec(&buf[..n]).unwrap();
+                let question = query.queries()[0].clone();
+                let reply = match question.query_type() {
+                    RecordType::A => &a,
+                    _ => &aaaa,
+                };
+                let mut response = Message::new();
+                response
+                    .set_id(query.id())
+                    .set_message_type(MessageType::Response)
+                    .add_query(question.clone());
+                match reply {
+                    Reply::Answer(ips) => {
+                        for ip in ips.iter().map(|ip| ip.parse().unwrap()) {
+                            let data = match ip {
+                                IpAddr::V4(ip) => RData::A(A(ip)),
+                                IpAddr::V6(ip) => RData::AAAA(AAAA(ip)),
+                            };
+                            response.add_answer(Record::from_rdata(
+                                question.name().clone(),
+                                60,
+                                data,
+                            ));
+                        }
+                    }
+                    Reply::Rcode(code) => {
+                        response.set_response_code(*code);
+                    }
+                    Reply::Silent => continue,
+                }
+                let _ = sock.send_to(&response.to_vec().unwrap(), from);
+            }
+        });
+        addr
+    }
+
+    fn resolve(server: SocketAddr) -> Result<Vec<IpAddr>, ProbeError> {
+        let deadline = Instant::now() + Duration::from_millis(300);
+        lookup(server, "example.com", &Bind::default(), deadline)
+    }
+
+    fn ips(list: &[&str]) -> Vec<IpAddr> {
+        list.iter().map(|ip| ip.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn combines_both_families() {
+        let server = serve(
+            Reply::Answer(&["192.0.2.1", "192.0.2.2"]),
+            Reply::Answer(&["2001:db8::1"]),
+        );
+        assert_eq!(
+            resolve(server).unwrap(),
+            ips(&["192.0.2.1", "192.0.2.2", "2001:db8::1"])
+        );
+    }
+
+    #[test]
+    fn one_failing_family_keeps_the_other() {
+        let server = serve(
+            Reply::Answer(&["192.0.2.1"]),
+            Reply::Rcode(ResponseCode::ServFail),
+        );
+        assert_eq!(resolve(server).unwrap(), ips(&["192.0.2.1"]));
+        let server = serve(Reply::Silent, Reply::Answer(&["2001:db8::1"]));
+        assert_eq!(resolve(server).unwrap(), ips(&["2001:db8::1"]));
+    }
+
+    #[test]
+    fn silent_family_does_not_hold_up_the_other() {
+        let server = serve(Reply::Answer(&["192.0.2.1"]), Reply::Silent);
+        let start = Instant::now();
+        let deadline = start + Duration::from_secs(2);
+        let ips = lookup(server, "example.com", &Bind::default(), deadline).unwrap();
+        assert_eq!(ips, self::ips(&["192.0.2.1"]));
+        assert!(
+            start.elapsed() < Duration::from_millis(500),
+            "{:?}",
+            start.elapsed()
+        );
+    }
+
+    #[test]
+    fn fails_when_neither_family_has_addresses() {
+        let server = serve(Reply::Answer(&[]), Reply::Answer(&[]));
+        assert_eq!(resolve(server).unwrap_err().detail, "dns_failed");
+        // An empty answer defers to the other family's error.
+        let server = serve(Reply::Answer(&[]), Reply::Rcode(ResponseCode::Refused));
+        assert_eq!(resolve(server).unwrap_err().detail, "dns_rcode: REFUSED");
+        let server = serve(
+            Reply::Rcode(ResponseCode::NXDomain),
+            Reply::Rcode(ResponseCode::NXDomain),
+        );
+        assert_eq!(resolve(server).unwrap_err().kind, ErrorKind::DnsNxDomain);
+        let server = serve(Reply::Silent, Reply::Silent);
+        assert_eq!(resolve(server).unwrap_err().kind, ErrorKind::DnsTimeout);
+    }
+}
@@ -0,0 +1,652 @@
+use crate::events::{Event, EventKind};
+use crate::history::History;
+use crate::notify::{format_duration, Details};
+use crate::{now_ms, secret, AppState, Target};
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Key of the email settings in the `settings` table.
+pub const SETTINGS_KEY: &str = "email";
+/// Secret store key of the SMTP password, which is kept out of the
+/// `settings` table.
+const PASSWORD_SECRET: &str = "email.password";
+/// Longest batching delay accepted.
+const MAX_BATCH_MS: u64 = 60 * 60 * 1000;
+/// Attempts per digest, the first one included.
+const MAX_ATTEMPTS: u32 = 3;
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+const SMTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+fn default_batch_ms() -> u64 {
+    60 * 1000
+}
+
+/// How the connection to the SMTP server is secured.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Security {
+    /// Plain connection upgraded with STARTTLS, which the server must offer.
+    #[default]
+    Starttls,
+    /// TLS from the start ("SMTPS").
+    Tls,
+    /// No encryption; only for relays on localhost or a trusted network.
+    None,
+}
+
+impl Security {
+    fn default_port(self) -> u16 {
+        match self {
+            Security::Starttls => 587,
+            Security::Tls => 465,
+            Security::None => 25,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmailSettings {
+    #[serde(default)]
+    pub enabled: bool,
+    /// The SMTP server.
+    #[serde(default)]
+    pub host: String,
+    /// Defaults to 587, 465 or 25 depending on `security`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub security: Security,
+    /// No authentication when empty.
+    #[serde(default)]
+    pub username: String,
+    /// Kept in the secret store; masked when sent to the webview.
+    #[serde(default)]
+    pub password: String,
+    /// E.g. "Connection Pulse <pulse@example.com>".
+    #[serde(default)]
+    pub from: String,
+    #[serde(default)]
+    pub to: Vec<String>,
+    /// Only these targets; all targets with `notify` set when empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub targets: Vec<String>,
+    /// Notices are collected for this long after the first one and then
+    /// sent as one digest.
+    #[serde(default = "default_batch_ms")]
+    pub batch_ms: u64,
+}
+
+impl Default for EmailSettings {
+    fn default() -> EmailSettings {
+        EmailSettings {
+            enabled: false,
+            host: String::new(),
+            port: None,
+            security: Security::default(),
+            username: String::new(),
+            password: String::new(),
+            from: String::new(),
+            to: Vec::new(),
+            targets: Vec::new(),
+            batch_ms: default_batch_ms(),
+        }
+    }
+}
+
+impl EmailSettings {
+    /// A copy for the webview, with the password masked.
+    pub fn redacted(&self) -> EmailSettings {
+        let mut settings = self.clone();
+        if !settings.password.is_empty() {
+            settings.password = secret::MASK.into();
+        }
+        settings
+    }
+
+    /// Takes `current`'s password when this one came back from the
+    /// webview empty or masked. Without a username there is none to keep.
+    pub fn keep_password(&mut self, current: &EmailSettings) {
+        if self.username.is_empty() {
+            self.password.clear();
+        } else if self.password.is_empty() || self.password == secret::MASK {
+            self.password = current.password.clone();
+        }
+    }
+
+    fn applies_to(&self, target: &Target) -> bool {
+        if self.targets.is_empty() {
+            target.notify
+        } else {
+            self.targets.contains(&target.id)
+        }
+    }
+
+    fn check(&self) -> Result<(), String> {
+        if self.host.trim().is_empty() {
+            return Err("host: required".into());
+        }
+        if self.port == Some(0) {
+            return Err("port: must be positive".into());
+        }
+        self.from
+            .parse::<Mailbox>()
+            .map_err(|e| format!("from: {}", e))?;
+        if self.to.is_empty() {
+            return Err("to: required".into());
+        }
+        for to in &self.to {
+            to.parse::<Mailbox>()
+                .map_err(|e| format!("to: {}: {}", to, e))?;
+        }
+        if self.batch_ms > MAX_BATCH_MS {
+            return Err(format!("batch_ms: must be at most {}", MAX_BATCH_MS));
+        }
+        Ok(())
+    }
+
+    fn transport(&self) -> Result<SmtpTransport, String> {
+        let builder = match self.security {
+            Security::Starttls => SmtpTransport::starttls_relay(&self.host),
+            Security::Tls => SmtpTransport::relay(&self.host),
+            Security::None => Ok(SmtpTransport::builder_dangerous(&self.host)),
+        }
+        .map_err(|e| format!("smtp_error: {}", e))?;
+        let mut builder = builder
+            .port(self.port.unwrap_or(self.security.default_port()))
+            .timeout(Some(SMTP_TIMEOUT));
+        if !self.username.is_empty() {
+            builder = builder.credentials(Credentials::new(
+                self.username.clone(),
+                self.password.clone(),
+            ));
+        }
+        Ok(builder.build())
+    }
+}
+
+/// A sent (or failed) digest, kept in the event log of each target in it.
+#[derive(Debug, Clone, Serialize)]
+pub struct Digest {
+    pub to: Vec<String>,
+    pub subject: String,
+    /// Notices in the digest, across all targets.
+    pub notices: usize,
+    pub ok: bool,
+    pub attempts: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Collects notices until their digest is due.
+#[derive(Default)]
+pub struct Mailer {
+    settings: EmailSettings,
+    pending: Vec<Details>,
+}
+
+impl Mailer {
+    pub fn settings(&self) -> &EmailSettings {
+        &self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: EmailSettings) {
+        self.settings = settings;
+    }
+}
+
+/// "Connection Pulse: web is down", or "Connection Pulse: 28 targets down,
+/// 2 recovered" for a digest.
+fn subject(batch: &[Details]) -> String {
+    if let [details] = batch {
+        let what = match details.event {
+            "incident_closed" => "is back up",
+            _ => "is down",
+        };
+        return format!("Connection Pulse: {} {}", details.target_name, what);
+    }
+    let down = batch
+        .iter()
+        .filter(|d| d.event != "incident_closed")
+        .count();
+    let parts: Vec<String> = [(down, "down"), (batch.len() - down, "recovered")]
+        .into_iter()
+        .filter(|(n, _)| *n > 0)
+        .enumerate()
+        .map(|(i, (n, what))| match (i, n) {
+            (0, 1) => format!("1 target {}", what),
+            (0, n) => format!("{} targets {}", n, what),
+            (_, n) => format!("{} {}", n, what),
+        })
+        .collect();
+    format!("Connection Pulse: {}", parts.join(", "))
+}
+
+fn body(batch: &[Details], now: u64) -> String {
+    let mut text = String::new();
+    let (down, up): (Vec<&Details>, Vec<&Details>) =
+        batch.iter().partition(|d| d.event != "incident_closed");
+    if !down.is_empty() {
+        let _ = writeln!(text, "Down ({}):\n", down.len());
+        for d in down {
+            let _ = writeln!(
+                text,
+                "  {} ({}:{}, {}) - down for {}: {}",
+                d.target_name,
+                d.host,
+                d.port,
+                d.probe_type,
+                format_duration(now.saturating_sub(d.started)),
+                d.error.as_deref().unwrap_or("failed"),
+            );
+        }
+        text.push('\n');
+    }
+    if !up.is_empty() {
+        let _ = writeln!(text, "Recovered ({}):\n", up.len());
+        for d in up {
+            let _ = writeln!(
+                text,
+                "  {} ({}:{}, {}) - was down for {}",
+                d.target_name,
+                d.host,
+                d.port,
+                d.probe_type,
+                d.duration.as_deref().unwrap_or("0s"),
+            );
+        }
+        text.push('\n');
+    }
+    text.push_str("-- \nSent by Connection Pulse\n");
+    text
+}
+
+fn message(settings: &EmailSettings, subject: &str, body: String) -> Result<Message, String> {
+    let mut builder = Message::builder()
+        .from(
+            settings
+                .from
+                .parse()
+                .map_err(|e| format!("invalid_address: {}", e))?,
+        )
+        .subject(subject)
+        .header(ContentType::TEXT_PLAIN);
+    for to in &settings.to {
+        builder = builder.to(to.parse().map_err(|e| format!("invalid_address: {}", e))?);
+    }
+    builder
+        .body(body)
+        .map_err(|e| format!("message_error: {}", e))
+}
+
+/// Sends `batch` as one message, retrying unless the server rejected it
+/// for good.
+async fn send(settings: &EmailSettings, batch: &[Details], max_attempts: u32) -> Digest {
+    let subject = subject(batch);
+    let mut digest = Digest {
+        to: settings.to.clone(),
+        subject: subject.clone(),
+        notices: batch.len(),
+        ok: false,
+        attempts: 0,
+        error: None,
+    };
+    let built = message(settings, &subject, body(batch, now_ms()))
+        .and_then(|message| Ok((message, settings.transport()?)));
+    let (message, transport) = match built {
+        Ok(built) => built,
+        Err(e) => {
+            digest.error = Some(e);
+            return digest;
+        }
+    };
+    let message = Arc::new(message);
+    loop {
+        digest.attempts += 1;
+        let (transport, message) = (transport.clone(), message.clone());
+        let retry = match tokio::task::spawn_blocking(move || transport.send(&message)).await {
+            Ok(Ok(_)) => {
+                digest.ok = true;
+                digest.error = None;
+                return digest;
+            }
+            Ok(Err(e)) => {
+                digest.error = Some(format!("smtp_error: {}", e));
+                !e.is_permanent()
+            }
+            Err(e) => {
+                digest.error = Some(format!("task_failed: {}", e));
+                false
+            }
+        };
+        if !retry || digest.attempts >= max_attempts {
+            return digest;
+        }
+        tokio::time::sleep(RETRY_DELAY).await;
+    }
+}
+
+/// Sends what has been collected, if email is still enabled.
+async fn flush(state: &AppState) {
+    let (settings, batch) = {
+        let mut mailer = state.mailer.lock().unwrap();
+        (mailer.settings.clone(), std::mem::take(&mut mailer.pending))
+    };
+    if batch.is_empty() || !settings.enabled {
+        return;
+    }
+    let digest = send(&settings, &batch, MAX_ATTEMPTS).await;
+    if !digest.ok {
+        eprintln!("email: {}", digest.error.as_deref().unwrap_or_default());
+    }
+    if let Some(history) = state.history.get() {
+        let timestamp = now_ms();
+        let mut targets: Vec<&str> = batch.iter().map(|d| d.target_id.as_str()).collect();
+        targets.sort_unstable();
+        targets.dedup();
+        for target_id in targets {
+            history.record_event(&Event {
+                timestamp,
+                target_id: target_id.to_string(),
+                kind: EventKind::EmailDigest(digest.clone()),
+            });
+        }
+    }
+}
+
+/// Queues `details` for the next digest. The first notice of a batch
+/// schedules it `batch_ms` later, so an outage hitting many targets at
+/// once ends up in a single message.
+pub fn dispatch(state: &Arc<AppState>, target: &Target, details: &Details) {
+    let mut mailer = state.mailer.lock().unwrap();
+    if !mailer.settings.enabled || !mailer.settings.applies_to(target) {
+        return;
+    }
+    mailer.pending.push(details.clone());
+    if mailer.pending.len() == 1 {
+        let state = state.clone();
+        let wait = Duration::from_millis(mailer.settings.batch_ms);
+        tauri::async_runtime::spawn(async move {
+            tokio::time::sleep(wait).await;
+            flush(&state).await;
+        });
+    }
+}
+
+/// Checks settings before they replace the current ones. Disabled
+/// settings may be incomplete.
+pub fn check_settings(settings: &EmailSettings) -> Result<(), String> {
+    if !settings.enabled {
+        return Ok(());
+    }
+    settings
+        .check()
+        .map_err(|e| format!("invalid_email_settings: {}", e))
+}
+
+/// Saves `settings`, with the password in the secret store rather than
+/// the history database. Blocking.
+pub fn save(history: Option<&History>, settings: &EmailSettings) -> Result<(), String> {
+    secret::set(PASSWORD_SECRET, &settings.password)?;
+    if let Some(history) = history {
+        let stored = EmailSettings {
+            password: String::new(),
+            ..settings.clone()
+        };
+        history
+            .set_setting(SETTINGS_KEY, &stored)
+            .map_err(|e| format!("history_error: {}", e))?;
+    }
+    Ok(())
+}
+
+/// The saved settings with their password. A password still in the
+/// database is moved to the secret store. Blocking.
+pub fn load(history: &History) -> Result<Option<EmailSettings>, String> {
+    let Some(mut settings) = history
+        .setting::<EmailSettings>(SETTINGS_KEY)
+        .map_err(|e| format!("history_error: {}", e))?
+    else {
+        return Ok(None);
+    };
+    if settings.password.is_empty() {
+        settings.password = secret::get(PASSWORD_SECRET)?.unwrap_or_default();
+    } else {
+        save(Some(history), &settings)?;
+    }
+    Ok(Some(settings))
+}
+
+/// Sends a message with made-up details right away, without retrying, so
+/// the server settings can be tried out.
+pub async fn test(settings: &EmailSettings) -> Result<Digest, String> {
+    settings
+        .check()
+        .map_err(|e| format!("invalid_email_settings: {}", e))?;
+    Ok(send(settings, &[Details::example()], 1).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::io::{BufRead, BufReader, Write as _};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+
+    fn settings(username: &str, password: &str) -> EmailSettings {
+        EmailSettings {
+            username: username.into(),
+            password: password.into(),
+            ..EmailSettings::default()
+        }
+    }
+
+    #[test]
+    fn redacted_masks_password() {
+        assert_eq!(settings("u", "hunter2").redacted().password, secret::MASK);
+        assert_eq!(settings("", "").redacted().password, "");
+    }
+
+    #[test]
+    fn keep_password_when_masked_or_empty() {
+        let current = settings("u", "hunter2");
+        for incoming in ["", secret::MASK] {
+            let mut next = settings("u", incoming);
+            next.keep_password(&current);
+            assert_eq!(next.password, "hunter2");
+        }
+        let mut next = settings("u", "changed");
+        next.keep_password(&current);
+        assert_eq!(next.password, "changed");
+        let mut next = settings("", secret::MASK);
+        next.keep_password(&current);
+        assert_eq!(next.password, "");
+    }
+
+    fn details(id: &str, event: &'static str, started: u64) -> Details {
+        let up = event == "incident_closed";
+        Details {
+            event,
+            target_id: id.into(),
+            target_name: id.into(),
+            host: format!("{}.example.com", id),
+            port: 443,
+            probe_type: "tcp".into(),
+            error_kind: None,
+            error: (!up).then(|| "connection refused".into()),
+            started,
+            ended: None,
+            duration_ms: None,
+            duration: up.then(|| "1m 5s".into()),
+            success_rate: None,
+            avg_ms: None,
+            p90_ms: None,
+        }
+    }
+
+    fn outage(down: usize, up: usize) -> Vec<Details> {
+        let down = (0..down).map(|i| details(&format!("down{}", i), "incident_opened", 0));
+        let up = (0..up).map(|i| details(&format!("up{}", i), "incident_closed", 0));
+        down.chain(up).collect()
+    }
+
+    #[test]
+    fn subject_names_a_lone_target() {
+        let down = details("web", "incident_opened", 0);
+        assert_eq!(subject(&[down]), "Connection Pulse: web is down");
+        let up = details("web", "incident_closed", 0);
+        assert_eq!(subject(&[up]), "Connection Pulse: web is back up");
+    }
+
+    #[test]
+    fn subject_counts_a_digest() {
+        assert_eq!(
+            subject(&outage(28, 2)),
+            "Connection Pulse: 28 targets down, 2 recovered"
+        );
+        assert_eq!(
+            subject(&outage(1, 1)),
+            "Connection Pulse: 1 target down, 1 recovered"
+        );
+        assert_eq!(
+            subject(&outage(0, 3)),
+            "Connection Pulse: 3 targets recovered"
+        );
+    }
+
+    #[test]
+    fn body_lists_down_and_recovered_targets() {
+        let batch = [
+            details("web", "incident_opened", 1_000),
+            details("db", "incident_opened", 61_000),
+            details("dns", "incident_closed", 0),
+        ];
+        let text = body(&batch, 191_000);
+        assert!(text.starts_with("Down (2):\n\n"), "{}", text);
+        assert!(text
+            .contains("  web (web.example.com:443, tcp) - down for 3m 10s: connection refused\n"));
+        assert!(text.contains("  db (db.example.com:443, tcp) - down for 2m 10s"));
+        assert!(text
+            .contains("Recovered (1):\n\n  dns (dns.example.com:443, tcp) - was down for 1m 5s\n"));
+        assert!(text.ends_with("-- \nSent by Connection Pulse\n"));
+    }
+
+    /// A plain SMTP server that accepts everything and passes on the data
+    /// of each message.
+    fn smtp_server() -> (u16, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { return };
+                let tx = tx.clone();
+                thread::spawn(move || {
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    let _ = stream.write_all(b"220 localhost ESMTP\r\n");
+                    let mut line = String::new();
+                    while reader.read_line(&mut line).unwrap_or(0) > 0 {
+                        let command = line.trim_end().to_ascii_uppercase();
+                        line.clear();
+                        let reply: &[u8] = if command == "DATA" {
+                            let _ = stream.write_all(b"354 go ahead\r\n");
+                            let mut data = String::new();
+                            while reader.read_line(&mut line).unwrap_or(0) > 0 && line != ".\r\n" {
+                                data.push_str(&line);
+                                line.clear();
+                            }
+                            line.clear();
+                            let _ = tx.send(data);
+                            b"250 queued\r\n"
+                        } else if command == "QUIT" {
+                            let _ = stream.write_all(b"221 bye\r\n");
+                            return;
+                        } else {
+                            b"250 ok\r\n"
+                        };
+                        let _ = stream.write_all(reply);
+                    }
+                });
+            }
+        });
+        (port, rx)
+    }
+
+    fn mail_state(port: u16, batch_ms: u64) -> Arc<AppState> {
+        let state = Arc::new(AppState::default());
+        state.mailer.lock().unwrap().set_settings(EmailSettings {
+            enabled: true,
+            host: "127.0.0.1".into(),
+            port: Some(port),
+            security: Security::None,
+            from: "pulse@example.com".into(),
+            to: vec!["ops@example.com".into()],
+            batch_ms,
+            ..EmailSettings::default()
+        });
+        state
+    }
+
+    fn target(id: &str) -> Target {
+        serde_json::from_value(
+            json!({ "id": id, "name": id, "host": "example.com", "port": 443, "notify": true }),
+        )
+        .unwrap()
+    }
+
+    fn subject_line(data: &str) -> &str {
+        data.lines()
+            .find_map(|line| line.strip_prefix("Subject: "))
+            .unwrap_or_default()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn dispatch_batches_notices_into_one_digest() {
+        let (port, messages) = smtp_server();
+        let state = mail_state(port, 300);
+        for (id, event) in [
+            ("web", "incident_opened"),
+            ("db", "incident_opened"),
+            ("dns", "incident_closed"),
+        ] {
+            dispatch(&state, &target(id), &details(id, event, now_ms()));
+        }
+        let data = messages.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(
+            subject_line(&data),
+            "Connection Pulse: 2 targets down, 1 recovered"
+        );
+        assert!(data.contains("Down (2):"), "{}", data);
+        assert!(data.contains("Recovered (1):"), "{}", data);
+        assert!(messages.recv_timeout(Duration::from_millis(500)).is_err());
+        assert!(state.mailer.lock().unwrap().pending.is_empty());
+
+        // The batch was sent, so this starts the next one.
+        dispatch(
+            &state,
+            &target("web"),
+            &details("web", "incident_closed", 0),
+        );
+        let data = messages.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(subject_line(&data), "Connection Pulse: web is back up");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn dispatch_skips_targets_the_settings_leave_out() {
+        let state = mail_state(1, 60_000);
+        let mut quiet = target("web");
+        quiet.notify = false;
+        dispatch(&state, &quiet, &details("web", "incident_opened", 0));
+        assert!(state.mailer.lock().unwrap().pending.is_empty());
+        state.mailer.lock().unwrap().settings.targets = vec!["web".into()];
+        dispatch(&state, &quiet, &details("web", "incident_opened", 0));
+        assert_eq!(state.mailer.lock().unwrap().pending.len(), 1);
+    }
+}
@@ -0,0 +1,127 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io;
+
+/// Broad failure categories, stable enough for the UI and exporters to
+/// group and colour on. The accompanying detail string carries the rest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    /// The target's settings can't be used (bad URL, regex, payload, ...).
+    InvalidConfig,
+    #[serde(rename = "dns_nxdomain")]
+    DnsNxDomain,
+    DnsTimeout,
+    /// Any other resolution failure, or a DNS probe's unexpected rcode.
+    DnsFailure,
+    /// The target's `bind_address` or `bind_interface` couldn't be used.
+    BindFailed,
+    ConnectRefused,
+    ConnectTimeout,
+    HostUnreachable,
+    /// UDP only: the host answered with ICMP port-unreachable.
+    PortUnreachable,
+    PermissionDenied,
+    /// Connected, but the exchange didn't finish within the timeout.
+    Timeout,
+    TlsHandshake,
+    CertExpired,
+    /// The server answered with a status outside the accepted set.
+    HttpStatus,
+    /// The server answered, but not with what the target expects.
+    ProtocolMismatch,
+    /// The server's answer couldn't be parsed, or it hung up early.
+    ProtocolError,
+    Io,
+    Internal,
+}
+
+/// A probe failure: what kind it was, plus human-readable detail.
+/// Serialized into `ProbeResult` as `error_kind` and `error`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProbeError {
+    #[serde(rename = "error_kind")]
+    pub kind: ErrorKind,
+    #[serde(rename = "error")]
+    pub detail: String,
+}
+
+impl ProbeError {
+    pub fn new(kind: ErrorKind, detail: impl Into<String>) -> ProbeError {
+        ProbeError {
+            kind,
+            detail: detail.into(),
+        }
+    }
+
+    pub fn invalid(detail: impl Into<String>) -> ProbeError {
+        ProbeError::new(ErrorKind::InvalidConfig, detail)
+    }
+
+    pub fn timeout() -> ProbeError {
+        ProbeError::new(ErrorKind::Timeout, "timeout")
+    }
+
+    /// Classifies a failed connect.
+    pub fn connect(e: io::Error) -> ProbeError {
+        match e.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+                ProbeError::new(ErrorKind::ConnectTimeout, "timeout")
+            }
+            io::ErrorKind::ConnectionRefused => {
+                ProbeError::new(ErrorKind::ConnectRefused, e.to_string())
+            }
+            io::ErrorKind::HostUnreachable | io::ErrorKind::NetworkUnreachable => {
+                ProbeError::new(ErrorKind::HostUnreachable, e.to_string())
+            }
+            io::ErrorKind::PermissionDenied => {
+                ProbeError::new(ErrorKind::PermissionDenied, e.to_string())
+            }
+            _ => ProbeError::new(ErrorKind::Io, e.to_string()),
+        }
+    }
+
+    pub fn bind(e: io::Error) -> ProbeError {
+        ProbeError::new(ErrorKind::BindFailed, format!("bind_failed: {}", e))
+    }
+
+    /// Classifies a failed read or write on an established connection;
+    /// `context` prefixes the detail (e.g. "recv_error").
+    pub fn io(context: &str, e: io::Error) -> ProbeError {
+        match e.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => ProbeError::timeout(),
+            io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted => {
+                ProbeError::new(ErrorKind::ProtocolError, format!("{}: {}", context, e))
+            }
+            _ => ProbeError::new(ErrorKind::Io, format!("{}: {}", context, e)),
+        }
+    }
+
+    /// Classifies a failed system (getaddrinfo) lookup.
+    pub fn resolve(e: io::Error) -> ProbeError {
+        if e.kind() == io::ErrorKind::TimedOut {
+            return ProbeError::new(ErrorKind::DnsTimeout, "dns_timeout");
+        }
+        // getaddrinfo errors carry no usable io::ErrorKind; "no such name"
+        // is only recognisable from the platform's message.
+        let message = e.to_string();
+        let lower = message.to_ascii_lowercase();
+        let kind = if lower.contains("not known")
+            || lower.contains("no such host")
+            || lower.contains("no address associated")
+        {
+            ErrorKind::DnsNxDomain
+        } else {
+            ErrorKind::DnsFailure
+        };
+        ProbeError::new(kind, format!("dns_error: {}", message))
+    }
+}
+
+impl fmt::Display for ProbeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.detail)
+    }
+}
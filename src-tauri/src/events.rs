@@ -0,0 +1,50 @@
+use crate::alert::Alert;
+use crate::email::Digest;
+use crate::hook::HookRun;
+use crate::incident::Incident;
+use crate::stats::Health;
+use crate::webhook::Delivery;
+use serde::Serialize;
+
+/// What happened, stored as the `kind` and `data` columns of the event log.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", content = "data", rename_all = "snake_case")]
+pub enum EventKind {
+    IncidentOpened(Incident),
+    IncidentClosed(Incident),
+    /// Weighted share of OK/failed changes over the last 21 results.
+    FlappingStarted {
+        change_rate: f64,
+    },
+    FlappingStopped {
+        change_rate: f64,
+    },
+    AlertFiring(Alert),
+    AlertResolved(Alert),
+    /// The target's health category settled on a new value.
+    Health {
+        from: Health,
+        to: Health,
+    },
+    WebhookDelivery(Delivery),
+    HookRun(HookRun),
+    EmailDigest(Digest),
+}
+
+/// One entry of the per-target event log.
+#[derive(Debug, Clone, Serialize)]
+pub struct Event {
+    pub timestamp: u64,
+    pub target_id: String,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+impl Event {
+    /// The `kind` tag and the `data` payload, as stored.
+    pub fn parts(&self) -> (String, String) {
+        let value = serde_json::to_value(&self.kind).unwrap_or_default();
+        let kind = value["kind"].as_str().unwrap_or_default().to_string();
+        (kind, value["data"].to_string())
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::VecDeque;
+
+/// Results the change rate is computed over, as in Nagios.
+const FLAP_SAMPLES: usize = 21;
+/// Change rate above which a target starts flapping.
+const FLAP_HIGH: f64 = 0.50;
+/// Change rate below which a flapping target stops.
+const FLAP_LOW: f64 = 0.25;
+/// Weight of the oldest and newest state change; recent changes count more.
+const OLDEST_WEIGHT: f64 = 0.8;
+const NEWEST_WEIGHT: f64 = 1.2;
+
+/// A flapping state change, with the change rate that caused it.
+#[derive(Debug, Clone, Copy)]
+pub enum Flap {
+    Started(f64),
+    Stopped(f64),
+}
+
+/// Nagios-style flap detection: the weighted share of OK/failed changes
+/// among the last `FLAP_SAMPLES` results, with separate start and stop
+/// thresholds.
+#[derive(Debug, Default)]
+pub struct FlapDetector {
+    states: VecDeque<bool>,
+    flapping: bool,
+}
+
+impl FlapDetector {
+    pub fn is_flapping(&self) -> bool {
+        self.flapping
+    }
+
+    /// Weighted fraction (0 to 1) of consecutive results that differ.
+    fn change_rate(&self) -> f64 {
+        let transitions = self.states.len().saturating_sub(1);
+        if transitions < 2 {
+            return 0.0;
+        }
+        let step = (NEWEST_WEIGHT - OLDEST_WEIGHT) / (transitions - 1) as f64;
+        let changes: f64 = (1..self.states.len())
+            .filter(|&i| self.states[i] != self.states[i - 1])
+            .map(|i| OLDEST_WEIGHT + step * (i - 1) as f64)
+            .sum();
+        changes / transitions as f64
+    }
+
+    pub fn observe(&mut self, ok: bool) -> Option<Flap> {
+        if self.states.len() == FLAP_SAMPLES {
+            self.states.pop_front();
+        }
+        self.states.push_back(ok);
+        if self.states.len() < FLAP_SAMPLES {
+            return None;
+        }
+
+        let rate = self.change_rate();
+        if !self.flapping && rate > FLAP_HIGH {
+            self.flapping = true;
+            Some(Flap::Started(rate))
+        } else if self.flapping && rate < FLAP_LOW {
+            self.flapping = false;
+            Some(Flap::Stopped(rate))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed(detector: &mut FlapDetector, states: impl IntoIterator<Item = bool>) -> Vec<Flap> {
+        states
+            .into_iter()
+            .filter_map(|ok| detector.observe(ok))
+            .collect()
+    }
+
+    #[test]
+    fn needs_a_full_window() {
+        let mut detector = FlapDetector::default();
+        let alternating = (0..FLAP_SAMPLES - 1).map(|i| i % 2 == 0);
+        assert!(feed(&mut detector, alternating).is_empty());
+        assert!(!detector.is_flapping());
+    }
+
+    #[test]
+    fn steady_results_never_flap() {
+        let mut detector = FlapDetector::default();
+        assert!(feed(&mut detector, std::iter::repeat_n(true, 50)).is_empty());
+        assert_eq!(detector.change_rate(), 0.0);
+    }
+
+    #[test]
+    fn recent_changes_weigh_more() {
+        let mut oldest = FlapDetector::default();
+        feed(&mut oldest, (0..FLAP_SAMPLES).map(|i| i != 0));
+        let mut newest = FlapDetector::default();
+        feed(
+            &mut newest,
+            (0..FLAP_SAMPLES).map(|i| i != FLAP_SAMPLES - 1),
+        );
+        let transitions = (FLAP_SAMPLES - 1) as f64;
+        assert!((oldest.change_rate() - OLDEST_WEIGHT / transitions).abs() < 1e-9);
+        assert!((newest.change_rate() - NEWEST_WEIGHT / transitions).abs() < 1e-9);
+    }
+
+    #[test]
+    fn starts_above_high_and_stops_below_low() {
+        let mut detector = FlapDetector::default();
+        let flaps = feed(&mut detector, (0..FLAP_SAMPLES).map(|i| i % 2 == 0));
+        assert!(matches!(flaps[..], [Flap::Started(rate)] if rate > FLAP_HIGH));
+        assert!(detector.is_flapping());
+
+        // Steady results push the changes out of the window; in between the
+        // rate drops below FLAP_HIGH without stopping.
+        let mut stopped = None;
+        for n in 1..=FLAP_SAMPLES {
+            let rate_before = detector.change_rate();
+            if let Some(flap) = detector.observe(true) {
+                stopped = Some((n, flap, rate_before));
+                break;
+            }
+        }
+        let (_, flap, rate_before) = stopped.expect("flapping never stopped");
+        assert!(matches!(flap, Flap::Stopped(rate) if rate < FLAP_LOW));
+        assert!(rate_before >= FLAP_LOW);
+        assert!(!detector.is_flapping());
+    }
+}
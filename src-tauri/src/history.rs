@@ -0,0 +1,487 @@
+use crate::events::Event;
+use crate::incident::Incident;
+use crate::rollup::{self, Bucket, Resolution, Retention};
+use crate::{now_ms, ProbeResult};
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+use tauri::{AppHandle, Manager};
+
+const FILE_NAME: &str = "history.sqlite3";
+/// Most results written in one transaction.
+const MAX_BATCH: usize = 256;
+/// How often raw results are rolled up and old data pruned.
+const ROLLUP_INTERVAL: Duration = Duration::from_secs(60);
+/// How long a connection waits for another one's write to finish.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The directory holding targets.json, detected the way the frontend does:
+/// next to the executable when a targets.json is there (portable mode),
+/// the app data directory otherwise.
+pub fn data_dir(app: &AppHandle) -> Option<PathBuf> {
+    if let Ok(dir) = app.path().resource_dir() {
+        if dir.join("targets.json").exists() {
+            return Some(dir);
+        }
+    }
+    app.path().app_data_dir().ok()
+}
+
+/// Brings the schema up to date, one `user_version` step at a time.
+fn migrate(conn: &Connection) -> rusqlite::Result<()> {
+    let version: i32 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if version < 1 {
+        conn.execute_batch(
+            "BEGIN;
+             CREATE TABLE results (
+                 id INTEGER PRIMARY KEY,
+                 target_id TEXT NOT NULL,
+                 timestamp INTEGER NOT NULL,
+                 ok INTEGER NOT NULL,
+                 latency_ms REAL NOT NULL,
+                 error_kind TEXT,
+                 result TEXT NOT NULL
+             );
+             CREATE INDEX results_target_time ON results (target_id, timestamp);
+             CREATE INDEX results_time ON results (timestamp);
+             PRAGMA user_version = 1;
+             COMMIT;",
+        )?;
+    }
+    if version < 2 {
+        conn.execute_batch("BEGIN;")?;
+        rollup::create_tables(conn)?;
+        conn.execute_batch("PRAGMA user_version = 2; COMMIT;")?;
+    }
+    if version < 3 {
+        conn.execute_batch(
+            "BEGIN;
+             CREATE TABLE incidents (
+                 target_id TEXT NOT NULL,
+                 started INTEGER NOT NULL,
+                 ended INTEGER,
+                 data TEXT NOT NULL,
+                 PRIMARY KEY (target_id, started)
+             );
+             CREATE INDEX incidents_started ON incidents (started);
+             CREATE TABLE events (
+                 id INTEGER PRIMARY KEY,
+                 timestamp INTEGER NOT NULL,
+                 target_id TEXT NOT NULL,
+                 kind TEXT NOT NULL,
+                 data TEXT NOT NULL
+             );
+             CREATE INDEX events_target_time ON events (target_id, timestamp);
+             CREATE INDEX events_time ON events (timestamp);
+             PRAGMA user_version = 3;
+             COMMIT;",
+        )?;
+    }
+    Ok(())
+}
+
+/// Incidents still open from the last run can't be closed by a recovery
+/// anymore; end them at their target's last stored result.
+fn close_stale_incidents(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "UPDATE incidents SET ended = MAX(started, COALESCE(
+             (SELECT MAX(timestamp) FROM results
+              WHERE results.target_id = incidents.target_id), started))
+         WHERE ended IS NULL;
+         UPDATE incidents SET data = json_set(data, '$.ended', ended,
+             '$.duration_ms', ended - started)
+         WHERE json_extract(data, '$.ended') IS NULL;",
+    )
+}
+
+/// Rows handed to the writer thread.
+enum Write {
+    Result(Box<ProbeResult>),
+    /// Inserted when opened, replaced when closed.
+    Incident(Incident),
+    Event(Event),
+}
+
+fn insert(conn: &mut Connection, batch: &[Write]) -> rusqlite::Result<()> {
+    let tx = conn.transaction()?;
+    for write in batch {
+        match write {
+            Write::Result(result) => insert_result(&tx, result)?,
+            Write::Incident(incident) => {
+                tx.prepare_cached(
+                    "INSERT OR REPLACE INTO incidents (target_id, started, ended, data)
+                     VALUES (?1, ?2, ?3, ?4)",
+                )?
+                .execute(params![
+                    incident.target_id,
+                    incident.started as i64,
+                    incident.ended.map(|t| t as i64),
+                    serde_json::to_string(incident).unwrap_or_default()
+                ])?;
+            }
+            Write::Event(event) => {
+                let (kind, data) = event.parts();
+                tx.prepare_cached(
+                    "INSERT INTO events (timestamp, target_id, kind, data)
+                     VALUES (?1, ?2, ?3, ?4)",
+                )?
+                .execute(params![
+                    event.timestamp as i64,
+                    event.target_id,
+                    kind,
+                    data
+                ])?;
+            }
+        }
+    }
+    tx.commit()
+}
+
+fn insert_result(conn: &Connection, result: &ProbeResult) -> rusqlite::Result<()> {
+    let error_kind = result
+        .error
+        .as_ref()
+        .and_then(|e| serde_json::to_value(e.kind).ok())
+        .and_then(|v| v.as_str().map(String::from));
+    let json = serde_json::to_string(result).unwrap_or_default();
+    conn.prepare_cached(
+        "INSERT INTO results (target_id, timestamp, ok, latency_ms, error_kind, result)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+    )?
+    .execute(params![
+        result.id,
+        result.timestamp as i64,
+        result.ok,
+        result.latency_ms,
+        error_kind,
+        json
+    ])?;
+    Ok(())
+}
+
+fn rollup_loop(mut conn: Connection) {
+    loop {
+        if let Err(e) = rollup::run(&mut conn, now_ms()) {
+            eprintln!("history: rollup failed: {}", e);
+        }
+        thread::sleep(ROLLUP_INTERVAL);
+    }
+}
+
+fn write_loop(mut conn: Connection, rx: Receiver<Write>) {
+    while let Ok(first) = rx.recv() {
+        let mut batch = vec![first];
+        batch.extend(rx.try_iter().take(MAX_BATCH - 1));
+        if let Err(e) = insert(&mut conn, &batch) {
+            eprintln!("history: dropped {} writes: {}", batch.len(), e);
+        }
+    }
+}
+
+/// Payload of `query_history`: raw results for short, recent ranges,
+/// rolled-up buckets otherwise.
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryPage {
+    pub resolution: Resolution,
+    /// Oldest first, in the same shape as `probe:update` payloads. Only
+    /// when `resolution` is `raw`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub results: Vec<Value>,
+    /// Oldest first. Buckets still within the last few minutes aren't
+    /// rolled up yet.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub buckets: Vec<Bucket>,
+}
+
+/// Every probe result, kept in SQLite. Writes are handed to a dedicated
+/// thread so the scheduler never waits on the disk; another one rolls raw
+/// results up into coarser buckets and applies the retention policy.
+pub struct History {
+    path: PathBuf,
+    writer: Sender<Write>,
+}
+
+impl History {
+    pub fn open(dir: &Path) -> Result<History, String> {
+        std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        let path = dir.join(FILE_NAME);
+        let conn = Connection::open(&path).map_err(|e| e.to_string())?;
+        conn.pragma_update(None, "journal_mode", "WAL")
+            .and_then(|_| conn.pragma_update(None, "synchronous", "NORMAL"))
+            .and_then(|_| conn.busy_timeout(BUSY_TIMEOUT))
+            .and_then(|_| migrate(&conn))
+            .and_then(|_| close_stale_incidents(&conn))
+            .map_err(|e| e.to_string())?;
+        let rollups = Self::connect(&path, OpenFlags::default()).map_err(|e| e.to_string())?;
+
+        let (writer, rx) = mpsc::channel();
+        thread::Builder::new()
+            .name("history-writer".into())
+            .spawn(move || write_loop(conn, rx))
+            .map_err(|e| e.to_string())?;
+        thread::Builder::new()
+            .name("history-rollup".into())
+            .spawn(move || rollup_loop(rollups))
+            .map_err(|e| e.to_string())?;
+        Ok(History { path, writer })
+    }
+
+    fn connect(path: &Path, flags: OpenFlags) -> rusqlite::Result<Connection> {
+        let conn = Connection::open_with_flags(path, flags)?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        Ok(conn)
+    }
+
+    pub fn record(&self, result: &ProbeResult) {
+        let _ = self.writer.send(Write::Result(Box::new(result.clone())));
+    }
+
+    pub fn record_incident(&self, incident: &Incident) {
+        let _ = self.writer.send(Write::Incident(incident.clone()));
+    }
+
+    pub fn record_event(&self, event: &Event) {
+        let _ = self.writer.send(Write::Event(event.clone()));
+    }
+
+    /// A target's history with `from <= timestamp <= to` (Unix ms), at the
+    /// finest resolution that still covers `from` and keeps the number of
+    /// points manageable.
+    pub fn query(&self, target_id: &str, from: u64, to: u64) -> rusqlite::Result<HistoryPage> {
+        let conn = Self::connect(&self.path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        let retention = rollup::load_retention(&conn)?;
+        let resolution = rollup::pick_resolution(from, to, now_ms(), &retention);
+        let (results, buckets) = match resolution {
+            Resolution::Raw => (raw_results(&conn, target_id, from, to)?, Vec::new()),
+            _ => (
+                Vec::new(),
+                rollup::query_buckets(&conn, resolution, target_id, from, to)?,
+            ),
+        };
+        Ok(HistoryPage {
+            resolution,
+            results,
+            buckets,
+        })
+    }
+
+    /// Incidents overlapping `from..=to` (Unix ms), for one target or all,
+    /// oldest first. Open incidents have no `ended`.
+    pub fn incidents(
+        &self,
+        target_id: Option<&str>,
+        from: u64,
+        to: u64,
+    ) -> rusqlite::Result<Vec<Value>> {
+        let conn = Self::connect(&self.path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        let mut stmt = conn.prepare(
+            "SELECT data FROM incidents
+             WHERE (?1 IS NULL OR target_id = ?1)
+               AND started <= ?3 AND (ended IS NULL OR ended >= ?2)
+             ORDER BY started",
+        )?;
+        let rows = stmt.query_map(params![target_id, from as i64, to as i64], |row| {
+            row.get::<_, String>(0)
+        })?;
+        rows.map(|json| json.map(|j| serde_json::from_str(&j).unwrap_or(Value::Null)))
+            .collect()
+    }
+
+    /// Event log entries with `from <= timestamp <= to`, for one target or
+    /// all, oldest first.
+    pub fn events(
+        &self,
+        target_id: Option<&str>,
+        from: u64,
+        to: u64,
+    ) -> rusqlite::Result<Vec<Value>> {
+        let conn = Self::connect(&self.path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        let mut stmt = conn.prepare(
+            "SELECT timestamp, target_id, kind, data FROM events
+             WHERE (?1 IS NULL OR target_id = ?1) AND timestamp BETWEEN ?2 AND ?3
+             ORDER BY timestamp, id",
+        )?;
+        let rows = stmt.query_map(params![target_id, from as i64, to as i64], |row| {
+            let data: String = row.get(3)?;
+            Ok(serde_json::json!({
+                "timestamp": row.get::<_, i64>(0)?,
+                "target_id": row.get::<_, String>(1)?,
+                "kind": row.get::<_, String>(2)?,
+                "data": serde_json::from_str::<Value>(&data).unwrap_or(Value::Null),
+            }))
+        })?;
+        rows.collect()
+    }
+
+    pub fn retention(&self) -> rusqlite::Result<Retention> {
+        let conn = Self::connect(&self.path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        rollup::load_retention(&conn)
+    }
+
+    /// Takes effect on the next rollup pass.
+    pub fn set_retention(&self, retention: &Retention) -> rusqlite::Result<()> {
+        self.set_setting(rollup::RETENTION_KEY, retention)
+    }
+
+    pub fn setting<T: DeserializeOwned>(&self, key: &str) -> rusqlite::Result<Option<T>> {
+        let conn = Self::connect(&self.path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        read_setting(&conn, key)
+    }
+
+    pub fn set_setting<T: Serialize>(&self, key: &str, value: &T) -> rusqlite::Result<()> {
+        let conn = Self::connect(&self.path, OpenFlags::default())?;
+        let value = serde_json::to_string(value).unwrap_or_default();
+        conn.execute(
+            "INSERT OR REPLACE INTO settings (key, value) VALUES (?1, ?2)",
+            params![key, value],
+        )?;
+        Ok(())
+    }
+}
+
+/// A JSON value from the `settings` table; `None` if it's missing or no
+/// longer parses as a `T`.
+pub fn read_setting<T: DeserializeOwned>(
+    conn: &Connection,
+    key: &str,
+) -> rusqlite::Result<Option<T>> {
+    let value: Option<String> = conn
+        .query_row(
+            "SELECT value FROM settings WHERE key = ?1",
+            params![key],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(value.and_then(|v| serde_json::from_str(&v).ok()))
+}
+
+fn raw_results(
+    conn: &Connection,
+    target_id: &str,
+    from: u64,
+    to: u64,
+) -> rusqlite::Result<Vec<Value>> {
+    let mut stmt = conn.prepare(
+        "SELECT result FROM results
+         WHERE target_id = ?1 AND timestamp BETWEEN ?2 AND ?3
+         ORDER BY timestamp",
+    )?;
+    let rows = stmt.query_map(params![target_id, from as i64, to as i64], |row| {
+        row.get::<_, String>(0)
+    })?;
+    rows.map(|json| json.map(|j| serde_json::from_str(&j).unwrap_or(Value::Null)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(conn: &Connection) -> i32 {
+        conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap()
+    }
+
+    fn tables(conn: &Connection) -> Vec<String> {
+        let mut stmt = conn
+            .prepare("SELECT name FROM sqlite_master WHERE type = 'table' ORDER BY name")
+            .unwrap();
+        let rows = stmt.query_map([], |row| row.get(0)).unwrap();
+        rows.map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn migrates_a_new_database() {
+        let conn = Connection::open_in_memory().unwrap();
+        migrate(&conn).unwrap();
+        assert_eq!(version(&conn), 3);
+        assert_eq!(
+            tables(&conn),
+            [
+                "events",
+                "incidents",
+                "results",
+                "rollup_state",
+                "rollups",
+                "settings"
+            ]
+        );
+        // Nothing left to do the second time.
+        migrate(&conn).unwrap();
+        assert_eq!(version(&conn), 3);
+    }
+
+    #[test]
+    fn migrates_a_version_1_database_keeping_results() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE results (
+                 id INTEGER PRIMARY KEY,
+                 target_id TEXT NOT NULL,
+                 timestamp INTEGER NOT NULL,
+                 ok INTEGER NOT NULL,
+                 latency_ms REAL NOT NULL,
+                 error_kind TEXT,
+                 result TEXT NOT NULL
+             );
+             INSERT INTO results (target_id, timestamp, ok, latency_ms, result)
+             VALUES ('a', 1000, 1, 12.5, '{}');
+             PRAGMA user_version = 1;",
+        )
+        .unwrap();
+        migrate(&conn).unwrap();
+        assert_eq!(version(&conn), 3);
+        assert_eq!(tables(&conn).len(), 6);
+        let count: i64 = conn
+            .query_row("SELECT COUNT(*) FROM results", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn closes_incidents_left_open_at_the_last_result() {
+        let conn = Connection::open_in_memory().unwrap();
+        migrate(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO results (target_id, timestamp, ok, latency_ms, result)
+             VALUES ('a', 5000, 0, 0, '{}'), ('a', 9000, 0, 0, '{}');
+             INSERT INTO incidents (target_id, started, ended, data) VALUES
+                 ('a', 4000, NULL, '{\"started\": 4000}'),
+                 ('b', 7000, NULL, '{\"started\": 7000}'),
+                 ('a', 1000, 2000, '{\"started\": 1000, \"ended\": 2000}');",
+        )
+        .unwrap();
+        close_stale_incidents(&conn).unwrap();
+
+        let mut stmt = conn
+            .prepare("SELECT target_id, ended, data FROM incidents ORDER BY started")
+            .unwrap();
+        let rows: Vec<(String, i64, Value)> = stmt
+            .query_map([], |row| {
+                let data: String = row.get(2)?;
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    serde_json::from_str(&data).unwrap(),
+                ))
+            })
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(rows[0].1, 2000);
+        assert_eq!(rows[0].2["ended"], 2000);
+        assert!(rows[0].2.get("duration_ms").is_none());
+        // Ends at its target's last result...
+        assert_eq!((rows[1].0.as_str(), rows[1].1), ("a", 9000));
+        assert_eq!(rows[1].2["ended"], 9000);
+        assert_eq!(rows[1].2["duration_ms"], 5000);
+        // ...or where it started, with no results to go by.
+        assert_eq!((rows[2].0.as_str(), rows[2].1), ("b", 7000));
+        assert_eq!(rows[2].2["duration_ms"], 0);
+    }
+}
@@ -0,0 +1,354 @@
+use crate::events::{Event, EventKind};
+use crate::notify::Details;
+use crate::{elapsed_ms, now_ms, AppState, Target};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::process::Command;
+
+/// Key of the hook list in the `settings` table.
+pub const SETTINGS_KEY: &str = "command_hooks";
+/// Output kept per stream; the rest is read and dropped.
+const MAX_OUTPUT: usize = 4096;
+/// How long output is still collected after a hook exits or is killed;
+/// something it started may hold the pipes open.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_hook_timeout_ms() -> u64 {
+    30_000
+}
+
+/// A program run when a target goes down or comes back. It is started
+/// directly, not through a shell, and gets the notice's details as `CP_*`
+/// environment variables.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandHook {
+    pub id: String,
+    /// A path, or a name looked up in `PATH`.
+    pub program: String,
+    /// Passed as they are.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    /// Only these targets; all targets with `notify` set when empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub targets: Vec<String>,
+    #[serde(default = "default_true")]
+    pub on_down: bool,
+    #[serde(default = "default_true")]
+    pub on_up: bool,
+    /// The program is killed after this long.
+    #[serde(default = "default_hook_timeout_ms")]
+    pub timeout_ms: u64,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+}
+
+impl CommandHook {
+    fn check(&self) -> Result<(), String> {
+        if self.id.trim().is_empty() {
+            return Err("id: required".into());
+        }
+        if self.program.trim().is_empty() {
+            return Err("program: required".into());
+        }
+        if self.program.contains('\0') || self.args.iter().any(|a| a.contains('\0')) {
+            return Err("args: must not contain NUL".into());
+        }
+        if self.timeout_ms == 0 {
+            return Err("timeout_ms: must be positive".into());
+        }
+        Ok(())
+    }
+
+    fn applies_to(&self, target: &Target, details: &Details) -> bool {
+        let event = match details.event {
+            "incident_opened" => self.on_down,
+            "incident_closed" => self.on_up,
+            _ => false,
+        };
+        let target = if self.targets.is_empty() {
+            target.notify
+        } else {
+            self.targets.contains(&target.id)
+        };
+        event && target
+    }
+}
+
+/// One run of a hook, kept in the event log.
+#[derive(Debug, Clone, Serialize)]
+pub struct HookRun {
+    pub hook_id: String,
+    pub program: String,
+    /// What triggered it, e.g. "incident_opened".
+    pub event: String,
+    /// Exited with status 0.
+    pub ok: bool,
+    /// Absent when the program didn't start, timed out or died of a signal.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    pub duration_ms: f64,
+    /// The first `MAX_OUTPUT` bytes of each stream.
+    pub stdout: String,
+    pub stderr: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// `CP_TARGET_ID`, `CP_HOST`, `CP_ERROR` and so on: one variable per
+/// field of `details`, empty when the field is.
+fn environment(details: &Details) -> Vec<(String, String)> {
+    details
+        .to_map()
+        .into_iter()
+        .map(|(name, value)| {
+            let value = match value {
+                Value::String(s) => s,
+                Value::Null => String::new(),
+                v => v.to_string(),
+            };
+            (format!("CP_{}", name.to_ascii_uppercase()), value)
+        })
+        .collect()
+}
+
+/// Reads `pipe` to the end into `kept`, up to `MAX_OUTPUT` bytes. Output
+/// lands in `kept` as it comes, so it's there even if the end never does.
+async fn read_capped(pipe: Option<impl AsyncRead + Unpin>, kept: Arc<Mutex<Vec<u8>>>) {
+    let Some(mut pipe) = pipe else {
+        return;
+    };
+    let mut buf = [0u8; 4096];
+    while let Ok(n) = pipe.read(&mut buf).await {
+        if n == 0 {
+            break;
+        }
+        let mut kept = kept.lock().unwrap();
+        let room = MAX_OUTPUT.saturating_sub(kept.len());
+        kept.extend_from_slice(&buf[..n.min(room)]);
+    }
+}
+
+async fn run(hook: &CommandHook, details: &Details) -> HookRun {
+    let start = Instant::now();
+    let mut run = HookRun {
+        hook_id: hook.id.clone(),
+        program: hook.program.clone(),
+        event: details.event.to_string(),
+        ok: false,
+        exit_code: None,
+        duration_ms: 0.0,
+        stdout: String::new(),
+        stderr: String::new(),
+        error: None,
+    };
+    let spawned = Command::new(&hook.program)
+        .args(&hook.args)
+        .envs(environment(details))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn();
+    let mut child = match spawned {
+        Ok(child) => child,
+        Err(e) => {
+            run.error = Some(format!("spawn_failed: {}", e));
+            return run;
+        }
+    };
+    let (stdout, stderr) = (Arc::default(), Arc::default());
+    let mut readers = tokio::spawn({
+        let (out, err) = (child.stdout.take(), child.stderr.take());
+        let (stdout, stderr) = (Arc::clone(&stdout), Arc::clone(&stderr));
+        async move { tokio::join!(read_capped(out, stdout), read_capped(err, stderr)) }
+    });
+
+    let timeout = Duration::from_millis(hook.timeout_ms);
+    match tokio::time::timeout(timeout, child.wait()).await {
+        Ok(Ok(status)) => {
+            run.ok = status.success();
+            run.exit_code = status.code();
+            if run.exit_code.is_none() {
+                run.error = Some("killed_by_signal".into());
+            }
+        }
+        Ok(Err(e)) => run.error = Some(format!("wait_failed: {}", e)),
+        Err(_) => {
+            let _ = child.kill().await;
+            run.error = Some(format!("timeout: killed after {} ms", hook.timeout_ms));
+        }
+    }
+    run.duration_ms = elapsed_ms(start);
+    if tokio::time::timeout(DRAIN_TIMEOUT, &mut readers)
+        .await
+        .is_err()
+    {
+        readers.abort();
+    }
+    let text = |kept: &Mutex<Vec<u8>>| String::from_utf8_lossy(&kept.lock().unwrap()).into_owned();
+    run.stdout = text(&stdout);
+    run.stderr = text(&stderr);
+    run
+}
+
+/// Checks a hook list before it replaces the current one.
+pub fn check_hooks(hooks: &[CommandHook]) -> Result<(), String> {
+    let mut ids = HashSet::new();
+    for hook in hooks {
+        hook.check()
+            .map_err(|e| format!("invalid_hook: {}: {}", hook.id, e))?;
+        if !ids.insert(hook.id.as_str()) {
+            return Err(format!("invalid_hook: {}: id: duplicate", hook.id));
+        }
+    }
+    Ok(())
+}
+
+/// Runs every enabled hook that covers `target` and the notice, in the
+/// background, and logs each run in the target's event log.
+pub fn dispatch(state: &Arc<AppState>, target: &Target, details: &Details) {
+    let hooks: Vec<CommandHook> = state
+        .hooks
+        .read()
+        .unwrap()
+        .iter()
+        .filter(|h| h.enabled && h.applies_to(target, details))
+        .cloned()
+        .collect();
+    for hook in hooks {
+        let state = state.clone();
+        let details = details.clone();
+        tauri::async_runtime::spawn(async move {
+            let run = run(&hook, &details).await;
+            if !run.ok {
+                eprintln!(
+                    "hook {}: {}",
+                    hook.id,
+                    run.error.as_deref().unwrap_or("failed")
+                );
+            }
+            if let Some(history) = state.history.get() {
+                history.record_event(&Event {
+                    timestamp: now_ms(),
+                    target_id: details.target_id,
+                    kind: EventKind::HookRun(run),
+                });
+            }
+        });
+    }
+}
+
+/// Runs `hook` once with made-up details, so it can be tried out.
+pub async fn test(hook: &CommandHook) -> Result<HookRun, String> {
+    hook.check()
+        .map_err(|e| format!("invalid_hook: {}: {}", hook.id, e))?;
+    Ok(run(hook, &Details::example()).await)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn hook(script: &str, timeout_ms: u64) -> CommandHook {
+        CommandHook {
+            id: "h".into(),
+            program: "sh".into(),
+            args: vec!["-c".into(), script.into()],
+            targets: Vec::new(),
+            on_down: true,
+            on_up: true,
+            timeout_ms,
+            enabled: true,
+        }
+    }
+
+    #[tokio::test]
+    async fn reports_exit_code_output_and_details() {
+        let script = r#"echo "$CP_EVENT $CP_HOST:$CP_PORT $CP_ERROR"; echo oops >&2; exit 3"#;
+        let done = run(&hook(script, 5_000), &Details::example()).await;
+        assert!(!done.ok);
+        assert_eq!(done.exit_code, Some(3));
+        assert_eq!(done.error, None);
+        assert_eq!(done.stdout, "test example.com:443 timeout\n");
+        assert_eq!(done.stderr, "oops\n");
+
+        let done = run(&hook("exit 0", 5_000), &Details::example()).await;
+        assert!(done.ok);
+        assert_eq!(done.exit_code, Some(0));
+    }
+
+    #[tokio::test]
+    async fn empty_fields_are_empty_variables() {
+        let mut details = Details::example();
+        details.error = None;
+        let script = r#"printf '%s|%s' "${CP_ERROR-unset}" "$CP_TARGET_ID""#;
+        let done = run(&hook(script, 5_000), &details).await;
+        assert_eq!(done.stdout, "|example");
+    }
+
+    #[tokio::test]
+    async fn output_is_capped() {
+        let script = "head -c 10000 /dev/zero | tr '\\0' x; head -c 5000 /dev/zero >&2";
+        let done = run(&hook(script, 5_000), &Details::example()).await;
+        assert!(done.ok, "{:?}", done.error);
+        assert_eq!(done.stdout, "x".repeat(MAX_OUTPUT));
+        assert_eq!(done.stderr.len(), MAX_OUTPUT);
+    }
+
+    #[tokio::test]
+    async fn kills_a_program_that_runs_too_long() {
+        let start = Instant::now();
+        let done = run(
+            &hook("echo started; exec sleep 10", 200),
+            &Details::example(),
+        )
+        .await;
+        assert!(
+            start.elapsed() < Duration::from_secs(3),
+            "{:?}",
+            start.elapsed()
+        );
+        assert!(!done.ok);
+        assert_eq!(done.exit_code, None);
+        assert_eq!(done.error.as_deref(), Some("timeout: killed after 200 ms"));
+        assert_eq!(done.stdout, "started\n");
+    }
+
+    #[tokio::test]
+    async fn missing_program_fails_to_spawn() {
+        let mut hook = hook("", 5_000);
+        hook.program = "/nonexistent/connection-pulse-hook".into();
+        let done = run(&hook, &Details::example()).await;
+        assert!(!done.ok);
+        assert!(done.error.unwrap().starts_with("spawn_failed: "));
+    }
+
+    #[tokio::test]
+    async fn target_fields_are_not_interpreted() {
+        let marker = std::env::temp_dir().join(format!("cp-hook-{}", std::process::id()));
+        let name = format!("web;rm -rf {0}; $(touch {0}) `touch {0}`", marker.display());
+        let mut details = Details::example();
+        details.target_name = name.clone();
+        let done = run(&hook(r#"printf '%s' "$CP_TARGET_NAME""#, 5_000), &details).await;
+        assert_eq!(done.stdout, name);
+        assert!(!marker.exists());
+
+        // Arguments are passed as they are, without a shell.
+        let hook = CommandHook {
+            program: "printf".into(),
+            args: vec!["%s".into(), "a;b $(id)".into()],
+            ..hook("", 5_000)
+        };
+        let done = run(&hook, &details).await;
+        assert_eq!(done.stdout, "a;b $(id)");
+    }
+}
@@ -0,0 +1,572 @@
+use crate::{elapsed_ms, tls, ErrorKind, ProbeError, ProbeResult, Target};
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::{Duration, Instant};
+use url::{Host, Position, Url};
+
+const MAX_HEADER_BYTES: usize = 64 * 1024;
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HttpSettings {
+    /// Full URL; defaults to http(s)://host:port/ when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// "GET" (default) or "HEAD".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http_method: Option<String>,
+    /// Accepted status codes; empty means any 2xx/3xx.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub expected_status: Vec<u16>,
+    /// Substring the response body must contain.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body_contains: Option<String>,
+    /// Regex the response body must match.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body_regex: Option<String>,
+}
+
+struct Response {
+    status: u16,
+    /// From the request being sent to the first byte of the response.
+    first_byte: Duration,
+    /// Handshake time, for https.
+    tls: Option<Duration>,
+    body: Vec<u8>,
+}
+
+/// Builds the URL to probe: the target's explicit `url`, or one derived from
+/// host/port (https on 443, plain http otherwise).
+pub fn target_url(target: &Target, http: &HttpSettings) -> Result<Url, ProbeError> {
+    let raw = match http.url.as_deref().map(str::trim) {
+        Some(u) if !u.is_empty() => u.to_string(),
+        _ => {
+            let scheme = if target.port == 443 { "https" } else { "http" };
+            if target.host.contains(':') {
+                format!("{}://[{}]:{}/", scheme, target.host, target.port)
+            } else {
+                format!("{}://{}:{}/", scheme, target.host, target.port)
+            }
+        }
+    };
+    let url = Url::parse(&raw).map_err(|e| ProbeError::invalid(format!("invalid_url: {}", e)))?;
+    match url.scheme() {
+        "http" | "https" => Ok(url),
+        other => Err(ProbeError::invalid(format!(
+            "invalid_url: unsupported scheme {}",
+            other
+        ))),
+    }
+}
+
+/// The host and port to connect to for `url`.
+pub fn endpoint(url: &Url) -> Result<(String, u16), ProbeError> {
+    let host = match url.host() {
+        Some(Host::Domain(d)) => d.to_string(),
+        Some(Host::Ipv4(a)) => a.to_string(),
+        Some(Host::Ipv6(a)) => a.to_string(),
+        None => return Err(ProbeError::invalid("invalid_url: missing host")),
+    };
+    Ok((host, url.port_or_known_default().unwrap_or(80)))
+}
+
+fn remaining(deadline: Instant) -> Result<Duration, ProbeError> {
+    deadline
+        .checked_duration_since(Instant::now())
+        .filter(|d| !d.is_zero())
+        .ok_or_else(ProbeError::timeout)
+}
+
+fn io_error(context: &str, e: io::Error) -> ProbeError {
+    match e
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<rustls::Error>())
+    {
+        Some(tls_err) => {
+            ProbeError::new(ErrorKind::TlsHandshake, format!("tls_error: {}", tls_err))
+        }
+        None => ProbeError::io(context, e),
+    }
+}
+
+fn protocol_error(detail: &str) -> ProbeError {
+    ProbeError::new(ErrorKind::ProtocolError, format!("http_error: {}", detail))
+}
+
+fn read_some<S: Read>(
+    stream: &mut S,
+    control: &TcpStream,
+    deadline: Instant,
+    buf: &mut [u8],
+) -> Result<usize, ProbeError> {
+    control
+        .set_read_timeout(Some(remaining(deadline)?))
+        .map_err(|e| io_error("recv_error", e))?;
+    match stream.read(buf) {
+        Ok(n) => Ok(n),
+        // Plenty of servers close without a TLS close_notify; treat it as EOF.
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(0),
+        Err(e) => Err(io_error("recv_error", e)),
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Walks chunked transfer framing, passing each chunk's payload to `on_chunk`.
+/// Returns true once the terminating zero-length chunk has been seen.
+fn walk_chunks(data: &[u8], mut on_chunk: impl FnMut(&[u8])) -> bool {
+    let mut pos = 0;
+    loop {
+        let Some(line_len) = find(&data[pos..], b"\r\n") else {
+            return false;
+        };
+        let size_line = String::from_utf8_lossy(&data[pos..pos + line_len]);
+        let size_hex = size_line.split(';').next().unwrap_or("").trim();
+        let Ok(size) = usize::from_str_radix(size_hex, 16) else {
+            return false;
+        };
+        pos += line_len + 2;
+        if size == 0 {
+            return true;
+        }
+        if data.len() < pos + size {
+            on_chunk(&data[pos..]);
+            return false;
+        }
+        on_chunk(&data[pos..pos + size]);
+        pos += size + 2;
+        if pos > data.len() {
+            return false;
+        }
+    }
+}
+
+fn exchange<S: Read + Write>(
+    stream: &mut S,
+    control: &TcpStream,
+    request: &[u8],
+    head_only: bool,
+    deadline: Instant,
+) -> Result<Response, ProbeError> {
+    stream
+        .write_all(request)
+        .and_then(|_| stream.flush())
+        .map_err(|e| io_error("send_error", e))?;
+    let sent = Instant::now();
+
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 8192];
+    let mut first_byte = None;
+    let header_end = loop {
+        let n = read_some(stream, control, deadline, &mut chunk)?;
+        if n == 0 {
+            return Err(protocol_error("connection closed before response headers"));
+        }
+        first_byte.get_or_insert_with(|| sent.elapsed());
+        buf.extend_from_slice(&chunk[..n]);
+        if let Some(pos) = find(&buf, b"\r\n\r\n") {
+            break pos + 4;
+        }
+        if buf.len() > MAX_HEADER_BYTES {
+            return Err(protocol_error("response headers too large"));
+        }
+    };
+
+    let head = String::from_utf8_lossy(&buf[..header_end]).into_owned();
+    let mut lines = head.split("\r\n");
+    let status_line = lines.next().unwrap_or("");
+    let status = status_line
+        .strip_prefix("HTTP/")
+        .and_then(|rest| rest.split_whitespace().nth(1))
+        .and_then(|code| code.parse::<u16>().ok())
+        .ok_or_else(|| protocol_error(&format!("malformed status line {:?}", status_line)))?;
+
+    let mut content_length = None;
+    let mut chunked = false;
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let name = name.trim();
+        if name.eq_ignore_ascii_case("content-length") {
+            content_length = value.trim().parse::<usize>().ok();
+        } else if name.eq_ignore_ascii_case("transfer-encoding") {
+            chunked = value.to_ascii_lowercase().contains("chunked");
+        }
+    }
+
+    let mut body = buf.split_off(header_end);
+    let has_body = !head_only && status >= 200 && status != 204 && status != 304;
+    if has_body {
+        loop {
+            if !chunked {
+                if let Some(len) = content_length {
+                    if body.len() >= len {
+                        body.truncate(len);
+                        break;
+                    }
+                }
+            } else if walk_chunks(&body, |_| {}) {
+                break;
+            }
+            if body.len() >= MAX_BODY_BYTES {
+                break;
+            }
+            let n = read_some(stream, control, deadline, &mut chunk)?;
+            if n == 0 {
+                break;
+            }
+            body.extend_from_slice(&chunk[..n]);
+        }
+        if chunked {
+            let mut decoded = Vec::with_capacity(body.len());
+            walk_chunks(&body, |c| decoded.extend_from_slice(c));
+            body = decoded;
+        }
+    } else {
+        body.clear();
+    }
+
+    Ok(Response {
+        status,
+        first_byte: first_byte.unwrap_or_else(|| sent.elapsed()),
+        tls: None,
+        body,
+    })
+}
+
+fn host_header(url: &Url) -> Result<String, ProbeError> {
+    let (host, _) = endpoint(url)?;
+    Ok(match (url.host_str(), url.port()) {
+        (Some(h), Some(p)) => format!("{}:{}", h, p),
+        (Some(h), None) => h.to_string(),
+        (None, _) => host,
+    })
+}
+
+/// Sends a complete request over `tcp`, through TLS for https URLs, and
+/// reads the response.
+fn send(
+    tcp: TcpStream,
+    url: &Url,
+    request: &[u8],
+    head_only: bool,
+    deadline: Instant,
+) -> Result<Response, ProbeError> {
+    let (host, _) = endpoint(url)?;
+    let mut tcp = tcp;
+    let control = tcp
+        .try_clone()
+        .map_err(|e| ProbeError::io("connect_error", e))?;
+    let left = remaining(deadline)?;
+    control
+        .set_write_timeout(Some(left))
+        .and_then(|_| control.set_read_timeout(Some(left)))
+        .map_err(|e| ProbeError::io("connect_error", e))?;
+
+    if url.scheme() == "https" {
+        // Handshake up front rather than inside the first write, so it can
+        // be timed on its own.
+        let mut conn = tls::client_connection(&host)?;
+        let handshake_start = Instant::now();
+        while conn.is_handshaking() {
+            conn.complete_io(&mut tcp)
+                .map_err(|e| io_error("tls_error", e))?;
+        }
+        let tls_time = handshake_start.elapsed();
+        let mut stream = rustls::StreamOwned::new(conn, tcp);
+        let resp = exchange(&mut stream, &control, request, head_only, deadline)?;
+        Ok(Response {
+            tls: Some(tls_time),
+            ..resp
+        })
+    } else {
+        exchange(&mut tcp, &control, request, head_only, deadline)
+    }
+}
+
+fn fetch(
+    tcp: TcpStream,
+    http: &HttpSettings,
+    url: &Url,
+    deadline: Instant,
+) -> Result<Response, ProbeError> {
+    let method = http
+        .http_method
+        .as_deref()
+        .map(str::to_ascii_uppercase)
+        .unwrap_or_else(|| "GET".to_string());
+    if method != "GET" && method != "HEAD" {
+        return Err(ProbeError::invalid(format!("invalid_method: {}", method)));
+    }
+
+    let request = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: ConnectionPulse/{}\r\nAccept: */*\r\nConnection: close\r\n\r\n",
+        method,
+        &url[Position::BeforePath..Position::AfterQuery],
+        host_header(url)?,
+        env!("CARGO_PKG_VERSION"),
+    );
+    send(tcp, url, request.as_bytes(), method == "HEAD", deadline)
+}
+
+/// POSTs `body` as JSON to `url`, at the first of `addrs` (its resolved
+/// host) that accepts a connection, with extra `headers`. Returns the
+/// response status. Blocking; used by notifiers rather than probes.
+pub fn post_json(
+    url: &Url,
+    addrs: &[SocketAddr],
+    headers: &[(String, String)],
+    body: &[u8],
+    deadline: Instant,
+) -> Result<u16, ProbeError> {
+    let mut last_error = ProbeError::new(ErrorKind::DnsFailure, "dns_failed");
+    let mut tcp = None;
+    for addr in addrs {
+        match TcpStream::connect_timeout(addr, remaining(deadline)?) {
+            Ok(stream) => {
+                tcp = Some(stream);
+                break;
+            }
+            Err(e) => last_error = ProbeError::connect(e),
+        }
+    }
+    let tcp = tcp.ok_or(last_error)?;
+
+    let mut request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: ConnectionPulse/{}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+        &url[Position::BeforePath..Position::AfterQuery],
+        host_header(url)?,
+        env!("CARGO_PKG_VERSION"),
+        body.len(),
+    );
+    for (name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name.trim(), value.trim()));
+    }
+    request.push_str("\r\n");
+    let mut request = request.into_bytes();
+    request.extend_from_slice(body);
+    send(tcp, url, &request, false, deadline).map(|resp| resp.status)
+}
+
+fn check_response(http: &HttpSettings, resp: &Response) -> Result<(), ProbeError> {
+    let status_ok = if http.expected_status.is_empty() {
+        (200..400).contains(&resp.status)
+    } else {
+        http.expected_status.contains(&resp.status)
+    };
+    if !status_ok {
+        return Err(ProbeError::new(
+            ErrorKind::HttpStatus,
+            format!("http_status: {}", resp.status),
+        ));
+    }
+
+    if let Some(needle) = http.body_contains.as_deref().filter(|s| !s.is_empty()) {
+        if find(&resp.body, needle.as_bytes()).is_none() {
+            return Err(ProbeError::new(
+                ErrorKind::ProtocolMismatch,
+                format!("body_mismatch: missing {:?}", needle),
+            ));
+        }
+    }
+
+    if let Some(pattern) = http.body_regex.as_deref().filter(|s| !s.is_empty()) {
+        let re = regex::bytes::Regex::new(pattern)
+            .map_err(|e| ProbeError::invalid(format!("invalid_regex: {}", e)))?;
+        if !re.is_match(&resp.body) {
+            return Err(ProbeError::new(
+                ErrorKind::ProtocolMismatch,
+                format!("body_mismatch: no match for /{}/", pattern),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Sends one request for `url` over an established connection and checks
+/// the response against the target's expectations.
+pub fn http_probe(
+    tcp: TcpStream,
+    url: &Url,
+    http: &HttpSettings,
+    start: Instant,
+    deadline: Instant,
+) -> ProbeResult {
+    let outcome = fetch(tcp, http, url, deadline);
+    let latency_ms = elapsed_ms(start);
+
+    match outcome {
+        Ok(resp) => {
+            let error = check_response(http, &resp).err();
+            ProbeResult {
+                ok: error.is_none(),
+                latency_ms,
+                error,
+                http_status: Some(resp.status),
+                first_byte_ms: Some(resp.first_byte.as_secs_f64() * 1000.0),
+                tls_ms: resp.tls.map(|d| d.as_secs_f64() * 1000.0),
+                ..Default::default()
+            }
+        }
+        Err(e) => ProbeResult {
+            ok: false,
+            latency_ms,
+            error: Some(e),
+            ..Default::default()
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn chunks(data: &[u8]) -> (bool, Vec<Vec<u8>>) {
+        let mut seen = Vec::new();
+        let done = walk_chunks(data, |c| seen.push(c.to_vec()));
+        (done, seen)
+    }
+
+    #[test]
+    fn walks_complete_chunks() {
+        let (done, seen) = chunks(b"4\r\nWiki\r\n5\r\npedia\r\n0\r\n\r\n");
+        assert!(done);
+        assert_eq!(seen, [b"Wiki".to_vec(), b"pedia".to_vec()]);
+    }
+
+    #[test]
+    fn walks_extensions_uppercase_sizes_and_trailers() {
+        let (done, seen) = chunks(b"A;name=value\r\n0123456789\r\n 0 \r\nX-Trailer: 1\r\n\r\n");
+        assert!(done);
+        assert_eq!(seen, [b"0123456789".to_vec()]);
+    }
+
+    #[test]
+    fn stops_at_incomplete_framing() {
+        // Size line not finished.
+        assert_eq!(chunks(b"4\r\nWiki\r\n5"), (false, vec![b"Wiki".to_vec()]));
+        // Payload cut short: what is there is passed on.
+        assert_eq!(chunks(b"5\r\npe"), (false, vec![b"pe".to_vec()]));
+        // Payload complete but its CRLF not there yet.
+        assert_eq!(chunks(b"5\r\npedia\r"), (false, vec![b"pedia".to_vec()]));
+        // No terminating chunk yet.
+        assert_eq!(chunks(b"5\r\npedia\r\n"), (false, vec![b"pedia".to_vec()]));
+    }
+
+    #[test]
+    fn rejects_bad_chunk_sizes() {
+        assert_eq!(chunks(b"zz\r\nWiki\r\n0\r\n\r\n"), (false, vec![]));
+        assert_eq!(chunks(b"\r\n"), (false, vec![]));
+    }
+
+    /// Sends `parts` one write at a time after reading the request, then
+    /// closes. Returns the client end.
+    fn serve(parts: Vec<&'static [u8]>) -> TcpStream {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut conn, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 1024];
+            while find(&request, b"\r\n\r\n").is_none() {
+                let n = conn.read(&mut buf).unwrap();
+                if n == 0 {
+                    return;
+                }
+                request.extend_from_slice(&buf[..n]);
+            }
+            for part in parts {
+                conn.write_all(part).unwrap();
+                conn.flush().unwrap();
+                thread::sleep(Duration::from_millis(20));
+            }
+        });
+        TcpStream::connect(addr).unwrap()
+    }
+
+    fn get(parts: Vec<&'static [u8]>, head_only: bool) -> Result<Response, ProbeError> {
+        let control = serve(parts);
+        let mut stream = control.try_clone().unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        exchange(
+            &mut stream,
+            &control,
+            b"GET / HTTP/1.1\r\nHost: x\r\n\r\n",
+            head_only,
+            deadline,
+        )
+    }
+
+    #[test]
+    fn exchange_decodes_chunks_split_across_reads() {
+        let resp = get(
+            vec![
+                b"HTTP/1.1 200 OK\r\nTransfer-Encoding: Chunked\r\n\r\n4\r\nWi",
+                b"ki\r\n5\r",
+                b"\npedia\r\n0\r\n",
+                b"\r\n",
+            ],
+            false,
+        )
+        .unwrap();
+        assert_eq!(resp.status, 200);
+        assert_eq!(resp.body, b"Wikipedia");
+    }
+
+    #[test]
+    fn exchange_keeps_what_arrived_when_chunks_end_early() {
+        let resp = get(
+            vec![
+                b"HTTP/1.1 200 OK\r\ntransfer-encoding: gzip, chunked\r\n\r\n5\r\npedia\r\n3\r\nab",
+            ],
+            false,
+        )
+        .unwrap();
+        assert_eq!(resp.body, b"pediaab");
+    }
+
+    #[test]
+    fn exchange_reads_content_length_bytes() {
+        let resp = get(
+            vec![
+                b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhel",
+                b"lo, and more",
+            ],
+            false,
+        )
+        .unwrap();
+        assert_eq!(resp.body, b"hello");
+    }
+
+    #[test]
+    fn exchange_reads_to_eof_without_length() {
+        let resp = get(vec![b"HTTP/1.0 200 OK\r\n\r\nsome", b" body"], false).unwrap();
+        assert_eq!(resp.body, b"some body");
+    }
+
+    #[test]
+    fn exchange_skips_bodies_that_are_not_there() {
+        let head = get(vec![b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n"], true).unwrap();
+        assert!(head.body.is_empty());
+        let no_content = get(vec![b"HTTP/1.1 204 No Content\r\n\r\nstray"], false).unwrap();
+        assert_eq!(no_content.status, 204);
+        assert!(no_content.body.is_empty());
+    }
+
+    #[test]
+    fn exchange_rejects_bad_responses() {
+        let err = get(vec![b"SSH-2.0-OpenSSH\r\n\r\n"], false)
+            .err()
+            .expect("accepted");
+        assert_eq!(err.kind, ErrorKind::ProtocolError);
+        let err = get(vec![b"HTTP/1.1 200"], false).err().expect("accepted");
+        assert_eq!(err.kind, ErrorKind::ProtocolError);
+    }
+}
@@ -0,0 +1,723 @@
+use crate::net::Bind;
+use crate::{elapsed_ms, now_ms, ErrorKind, ProbeError, ProbeResult};
+use serde::{Deserialize, Serialize};
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::process::Command;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::time::{Duration, Instant};
+
+const ECHO_REQUEST_V4: u8 = 8;
+const ECHO_REPLY_V4: u8 = 0;
+const UNREACHABLE_V4: u8 = 3;
+const TIME_EXCEEDED_V4: u8 = 11;
+const ECHO_REQUEST_V6: u8 = 128;
+const ECHO_REPLY_V6: u8 = 129;
+const UNREACHABLE_V6: u8 = 1;
+const TIME_EXCEEDED_V6: u8 = 3;
+const PAYLOAD: &[u8] = b"connection-pulse-echo-payload-0123456789abcdef";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PingSettings {
+    /// Echo requests per probe (default 1).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ping_count: Option<u32>,
+    /// Gap between echo requests in ms (default 200).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ping_interval_ms: Option<u64>,
+}
+
+#[derive(Debug)]
+pub enum PingError {
+    /// Neither an ICMP socket nor the `ping` binary is usable.
+    PermissionDenied(String),
+    Timeout,
+    /// An ICMP error (destination unreachable, time exceeded) came back.
+    Unreachable(String),
+    /// `bind_address` or `bind_interface` couldn't be applied.
+    Bind(ProbeError),
+    Io(String),
+}
+
+impl From<PingError> for ProbeError {
+    fn from(e: PingError) -> ProbeError {
+        if let PingError::Bind(e) = e {
+            return e;
+        }
+        let kind = match &e {
+            PingError::PermissionDenied(_) => ErrorKind::PermissionDenied,
+            PingError::Timeout => ErrorKind::Timeout,
+            PingError::Unreachable(_) => ErrorKind::HostUnreachable,
+            PingError::Bind(e) => e.kind,
+            PingError::Io(_) => ErrorKind::Io,
+        };
+        ProbeError::new(kind, e.to_string())
+    }
+}
+
+impl fmt::Display for PingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PingError::PermissionDenied(e) => write!(f, "ping_permission_denied: {}", e),
+            PingError::Timeout => write!(f, "ping_timeout"),
+            PingError::Unreachable(e) => write!(f, "ping_unreachable: {}", e),
+            PingError::Bind(e) => e.fmt(f),
+            PingError::Io(e) => write!(f, "ping_error: {}", e),
+        }
+    }
+}
+
+fn next_sequence() -> u16 {
+    static SEQ: AtomicU16 = AtomicU16::new(0);
+    SEQ.fetch_add(1, Ordering::Relaxed)
+}
+
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data
+        .chunks(2)
+        .map(|pair| u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)]) as u32)
+        .sum();
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+fn unreachable_reason(v6: bool, kind: u8, code: u8) -> String {
+    match (v6, kind, code) {
+        (false, UNREACHABLE_V4, 0) | (true, UNREACHABLE_V6, 0) => "network unreachable".into(),
+        (false, UNREACHABLE_V4, 1) | (true, UNREACHABLE_V6, 3) => "host unreachable".into(),
+        (false, UNREACHABLE_V4, 13) | (true, UNREACHABLE_V6, 1) => {
+            "administratively prohibited".into()
+        }
+        (false, TIME_EXCEEDED_V4, _) | (true, TIME_EXCEEDED_V6, _) => "ttl exceeded".into(),
+        (_, _, code) => format!("destination unreachable (code {})", code),
+    }
+}
+
+/// An open ICMP echo socket. Linux/Android "ping sockets" (SOCK_DGRAM) need
+/// no privileges; raw sockets are tried next for platforms or configurations
+/// where those are disabled.
+pub struct Pinger {
+    sock: UdpSocket,
+    addr: SocketAddr,
+    ident: u16,
+    /// Linux ping sockets rewrite the identifier and filter replies themselves.
+    kernel_ident: bool,
+}
+
+impl Pinger {
+    pub fn open(ip: IpAddr, bind: &Bind) -> Result<Pinger, PingError> {
+        let (domain, protocol) = match ip {
+            IpAddr::V4(_) => (Domain::IPV4, Protocol::ICMPV4),
+            IpAddr::V6(_) => (Domain::IPV6, Protocol::ICMPV6),
+        };
+        let (sock, dgram) = match Socket::new(domain, Type::DGRAM, Some(protocol)) {
+            Ok(s) => (s, true),
+            Err(dgram_err) => match Socket::new(domain, Type::RAW, Some(protocol)) {
+                Ok(s) => (s, false),
+                Err(raw_err) => {
+                    return Err(PingError::PermissionDenied(format!(
+                        "dgram: {}; raw: {}",
+                        dgram_err, raw_err
+                    )))
+                }
+            },
+        };
+        let addr = SocketAddr::new(ip, 0);
+        bind.apply(SockRef::from(&sock), addr)
+            .map_err(PingError::Bind)?;
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        if dgram {
+            enable_recv_err(&sock, ip.is_ipv6());
+        }
+        Ok(Pinger {
+            sock: sock.into(),
+            addr,
+            ident: (std::process::id() as u16) ^ (now_ms() as u16),
+            kernel_ident: dgram && cfg!(any(target_os = "linux", target_os = "android")),
+        })
+    }
+
+    fn build_request(&self, seq: u16) -> Vec<u8> {
+        let kind = if self.addr.is_ipv4() {
+            ECHO_REQUEST_V4
+        } else {
+            ECHO_REQUEST_V6
+        };
+        let mut packet = vec![kind, 0, 0, 0];
+        packet.extend_from_slice(&self.ident.to_be_bytes());
+        packet.extend_from_slice(&seq.to_be_bytes());
+        packet.extend_from_slice(PAYLOAD);
+        // The kernel fills in the ICMPv6 checksum (it covers a pseudo-header).
+        if self.addr.is_ipv4() {
+            let sum = checksum(&packet);
+            packet[2..4].copy_from_slice(&sum.to_be_bytes());
+        }
+        packet
+    }
+
+    /// Classifies a received datagram: the sequence number it answers and
+    /// whether that was a reply or an ICMP error, or `None` if it isn't ours.
+    fn match_reply(&self, buf: &[u8]) -> Option<(u16, Result<(), PingError>)> {
+        let v6 = self.addr.is_ipv6();
+        // IPv4 raw sockets (and macOS datagram sockets) include the IP header.
+        let icmp = if !v6 && buf.first().is_some_and(|b| b >> 4 == 4) {
+            buf.get(((buf[0] & 0x0f) as usize) * 4..)?
+        } else {
+            buf
+        };
+        if icmp.len() < 8 {
+            return None;
+        }
+        let ours = |header: &[u8]| {
+            let ident = u16::from_be_bytes([header[4], header[5]]);
+            let seq = u16::from_be_bytes([header[6], header[7]]);
+            (self.kernel_ident || ident == self.ident).then_some(seq)
+        };
+
+        let (kind, code) = (icmp[0], icmp[1]);
+        let reply = if v6 { ECHO_REPLY_V6 } else { ECHO_REPLY_V4 };
+        if kind == reply {
+            return ours(icmp).map(|seq| (seq, Ok(())));
+        }
+
+        let is_error = if v6 {
+            kind == UNREACHABLE_V6 || kind == TIME_EXCEEDED_V6
+        } else {
+            kind == UNREACHABLE_V4 || kind == TIME_EXCEEDED_V4
+        };
+        if !is_error {
+            return None;
+        }
+        // Errors quote the offending packet: IP header, then our ICMP header.
+        let quoted = &icmp[8..];
+        let inner = if v6 {
+            quoted.get(40..)?
+        } else {
+            quoted.get(((quoted.first()? & 0x0f) as usize) * 4..)?
+        };
+        if inner.len() < 8 {
+            return None;
+        }
+        let reason = unreachable_reason(v6, kind, code);
+        ours(inner).map(|seq| (seq, Err(PingError::Unreachable(reason))))
+    }
+
+    /// Sends `count` echo requests spaced `interval` apart and collects the
+    /// replies as they arrive, so a lost packet doesn't delay the next one.
+    /// Nothing is sent or waited for past `deadline`. Returns the number of
+    /// requests sent, the round-trip times (ms) of answered ones and the most
+    /// relevant error seen for the rest.
+    pub fn echo_series(
+        &self,
+        count: u32,
+        interval: Duration,
+        deadline: Instant,
+    ) -> (u32, Vec<f64>, Option<PingError>) {
+        let mut pending: Vec<(u16, Instant)> = Vec::new();
+        let mut rtts = Vec::new();
+        let mut last_error = None;
+        let mut sent = 0;
+        let mut next_send = Instant::now();
+        let mut buf = [0u8; 1500];
+
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                if !pending.is_empty() && last_error.is_none() {
+                    last_error = Some(PingError::Timeout);
+                }
+                break;
+            }
+            if sent < count && now >= next_send {
+                let seq = next_sequence();
+                match self.sock.send_to(&self.build_request(seq), self.addr) {
+                    Ok(_) => pending.push((seq, Instant::now())),
+                    Err(e) => last_error = Some(classify_io(&e)),
+                }
+                sent += 1;
+                next_send = now + interval;
+                continue;
+            }
+            if sent >= count && pending.is_empty() {
+                break;
+            }
+
+            let wake = if sent < count {
+                next_send.min(deadline)
+            } else {
+                deadline
+            };
+            let left = wake
+                .saturating_duration_since(now)
+                .max(Duration::from_millis(1));
+            if let Err(e) = self.sock.set_read_timeout(Some(left)) {
+                last_error = Some(PingError::Io(e.to_string()));
+                break;
+            }
+
+            match self.sock.recv_from(&mut buf) {
+                Ok((n, _)) => {
+                    let Some((seq, outcome)) = self.match_reply(&buf[..n]) else {
+                        continue;
+                    };
+                    let Some(pos) = pending.iter().position(|(s, _)| *s == seq) else {
+                        continue;
+                    };
+                    let (_, sent_at) = pending.remove(pos);
+                    match outcome {
+                        Ok(()) => rtts.push(elapsed_ms(sent_at)),
+                        Err(e) => last_error = Some(e),
+                    }
+                }
+                Err(e) => match classify_io(&e) {
+                    PingError::Timeout => {}
+                    // Socket-level errors (IP_RECVERR) don't say which request
+                    // they belong to; charge them to the oldest one.
+                    other => {
+                        if !pending.is_empty() {
+                            pending.remove(0);
+                        }
+                        last_error = Some(other);
+                    }
+                },
+            }
+        }
+
+        (sent, rtts, last_error)
+    }
+}
+
+/// Ping sockets only report ICMP errors such as "host unreachable" to the
+/// caller when IP_RECVERR is on; otherwise they look like a timeout.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn enable_recv_err(sock: &Socket, v6: bool) {
+    use std::os::fd::AsRawFd;
+    let (level, name) = if v6 {
+        (libc::IPPROTO_IPV6, libc::IPV6_RECVERR)
+    } else {
+        (libc::IPPROTO_IP, libc::IP_RECVERR)
+    };
+    let on: libc::c_int = 1;
+    // Best effort: without it we still get replies, just less precise errors.
+    unsafe {
+        libc::setsockopt(
+            sock.as_raw_fd(),
+            level,
+            name,
+            &on as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        );
+    }
+}
+
+fn classify_io(e: &io::Error) -> PingError {
+    match e.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => PingError::Timeout,
+        io::ErrorKind::PermissionDenied => PingError::PermissionDenied(e.to_string()),
+        io::ErrorKind::HostUnreachable | io::ErrorKind::NetworkUnreachable => {
+            PingError::Unreachable(e.to_string())
+        }
+        _ => PingError::Io(e.to_string()),
+    }
+}
+
+fn parse_ping_latencies(output: &str) -> Vec<f64> {
+    // Match patterns like "time=12.3ms", "time=5ms", "time<1ms", "time=0.456 ms"
+    let mut latencies = Vec::new();
+    for line in output.lines() {
+        let lower = line.to_lowercase();
+        if let Some(pos) = lower.find("time=") {
+            let after = &lower[pos + 5..];
+            let num_str: String = after
+                .chars()
+                .take_while(|c| c.is_ascii_digit() || *c == '.')
+                .collect();
+            if let Ok(val) = num_str.parse::<f64>() {
+                latencies.push(val);
+            }
+        } else if let Some(pos) = lower.find("time<") {
+            let after = &lower[pos + 5..];
+            let num_str: String = after
+                .chars()
+                .take_while(|c| c.is_ascii_digit() || *c == '.')
+                .collect();
+            // time<1ms means sub-millisecond
+            latencies.push(num_str.parse::<f64>().unwrap_or(1.0));
+        }
+    }
+    latencies
+}
+
+/// Last resort when no ICMP socket can be opened: the system `ping` binary.
+fn ping_command(
+    host: &str,
+    count: u32,
+    interval_ms: u64,
+    timeout_ms: u64,
+    bind: &Bind,
+) -> Result<Vec<f64>, PingError> {
+    let count_arg = count.to_string();
+    let secs = timeout_ms.div_ceil(1000).max(1).to_string();
+    // Unprivileged ping refuses intervals below 200ms.
+    let interval = format!("{:.1}", interval_ms.max(200) as f64 / 1000.0);
+    let source = bind.address.map(|a| a.to_string());
+    let mut command = Command::new("ping");
+    if cfg!(target_os = "windows") {
+        // Windows' ping has no overall limit; -w only bounds each reply.
+        command.args(["-n", &count_arg, "-w", &timeout_ms.to_string()]);
+        if let Some(source) = &source {
+            command.args(["-S", source]);
+        }
+    } else if cfg!(target_os = "macos") {
+        command.args([
+            "-c",
+            &count_arg,
+            "-i",
+            &interval,
+            "-W",
+            &timeout_ms.to_string(),
+            "-t",
+            &secs,
+        ]);
+        if let Some(source) = &source {
+            command.args(["-S", source]);
+        }
+    } else {
+        // Linux / Android: -I takes an interface or a source address; -w
+        // bounds the whole run, -W each reply.
+        command.args(["-c", &count_arg, "-i", &interval, "-W", &secs, "-w", &secs]);
+        if let Some(source) = bind.interface.as_ref().or(source.as_ref()) {
+            command.args(["-I", source]);
+        }
+    }
+    let result = command.arg(host).output();
+
+    match result {
+        Ok(output) => {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let latencies = parse_ping_latencies(&stdout);
+            if output.status.success() || !latencies.is_empty() {
+                Ok(latencies)
+            } else {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                Err(PingError::Io(format!("ping failed: {}", stderr.trim())))
+            }
+        }
+        Err(e) => Err(PingError::PermissionDenied(format!(
+            "no ICMP socket and ping unavailable: {}",
+            e
+        ))),
+    }
+}
+
+/// Per-probe packet statistics, in the same terms as `ping`'s summary line.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PingStats {
+    pub sent: u32,
+    pub received: u32,
+    pub loss_pct: f64,
+    pub min_ms: f64,
+    pub avg_ms: f64,
+    pub max_ms: f64,
+    pub mdev_ms: f64,
+    /// Mean difference between consecutive round-trip times.
+    pub jitter_ms: f64,
+}
+
+impl PingStats {
+    fn from_rtts(sent: u32, rtts: &[f64]) -> PingStats {
+        let received = rtts.len() as u32;
+        let loss_pct = if sent == 0 {
+            0.0
+        } else {
+            (sent.saturating_sub(received)) as f64 * 100.0 / sent as f64
+        };
+        if rtts.is_empty() {
+            return PingStats {
+                sent,
+                loss_pct,
+                ..Default::default()
+            };
+        }
+        let n = rtts.len() as f64;
+        let avg = rtts.iter().sum::<f64>() / n;
+        let mean_sq = rtts.iter().map(|r| r * r).sum::<f64>() / n;
+        let jitter = if rtts.len() > 1 {
+            rtts.windows(2).map(|w| (w[1] - w[0]).abs()).sum::<f64>() / (n - 1.0)
+        } else {
+            0.0
+        };
+        PingStats {
+            sent,
+            received,
+            loss_pct,
+            min_ms: rtts.iter().cloned().fold(f64::INFINITY, f64::min),
+            avg_ms: avg,
+            max_ms: rtts.iter().cloned().fold(0.0, f64::max),
+            mdev_ms: (mean_sq - avg * avg).max(0.0).sqrt(),
+            jitter_ms: jitter,
+        }
+    }
+}
+
+/// Pings `ip` with up to `ping_count` echo requests, all within `timeout_ms`.
+/// The probe succeeds if any reply arrives; loss and RTT spread are reported
+/// in `ProbeResult.ping`.
+pub fn icmp_ping(ip: IpAddr, settings: &PingSettings, bind: &Bind, timeout_ms: u64) -> ProbeResult {
+    let start = Instant::now();
+    let count = settings.ping_count.unwrap_or(1).max(1);
+    let interval_ms = settings.ping_interval_ms.unwrap_or(200);
+    // One budget for the whole series: requests that don't fit in it aren't
+    // sent, and replies still outstanding at the end count as lost.
+    let deadline = start + Duration::from_millis(timeout_ms);
+    let interval = Duration::from_millis(interval_ms);
+
+    let outcome = match Pinger::open(ip, bind) {
+        Ok(pinger) => Ok(pinger.echo_series(count, interval, deadline)),
+        Err(PingError::PermissionDenied(_)) => {
+            ping_command(&ip.to_string(), count, interval_ms, timeout_ms, bind)
+                .map(|rtts| (count, rtts, None))
+        }
+        Err(e) => Err(e),
+    };
+
+    match outcome {
+        Ok((sent, rtts, last_error)) => {
+            let stats = PingStats::from_rtts(sent, &rtts);
+            let error = rtts
+                .is_empty()
+                .then(|| ProbeError::from(last_error.unwrap_or(PingError::Timeout)));
+            ProbeResult {
+                ok: error.is_none(),
+                latency_ms: if rtts.is_empty() {
+                    elapsed_ms(start)
+                } else {
+                    stats.avg_ms
+                },
+                error,
+                ping: Some(stats),
+                ..Default::default()
+            }
+        }
+        Err(e) => ProbeResult {
+            ok: false,
+            latency_ms: elapsed_ms(start),
+            error: Some(e.into()),
+            ..Default::default()
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pinger(ip: &str, kernel_ident: bool) -> Pinger {
+        let ip: IpAddr = ip.parse().unwrap();
+        Pinger {
+            sock: UdpSocket::bind("127.0.0.1:0").unwrap(),
+            addr: SocketAddr::new(ip, 0),
+            ident: 0x1234,
+            kernel_ident,
+        }
+    }
+
+    fn echo(kind: u8, ident: u16, seq: u16) -> Vec<u8> {
+        let mut packet = vec![kind, 0, 0, 0];
+        packet.extend_from_slice(&ident.to_be_bytes());
+        packet.extend_from_slice(&seq.to_be_bytes());
+        packet
+    }
+
+    /// A minimal IPv4 header (IHL 5) in front of `payload`.
+    fn ipv4(payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x45];
+        packet.resize(20, 0);
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    fn icmp_error(kind: u8, code: u8, quoted: &[u8]) -> Vec<u8> {
+        let mut packet = vec![kind, code, 0, 0, 0, 0, 0, 0];
+        packet.extend_from_slice(quoted);
+        packet
+    }
+
+    #[test]
+    fn checksum_folds_carries_and_pads_odd_lengths() {
+        // RFC 1071's example: the sum is 0xddf2.
+        let data = [0x00, 0x01, 0xf2, 0x03, 0xf4, 0xf5, 0xf6, 0xf7];
+        assert_eq!(checksum(&data), !0xddf2);
+        assert_eq!(checksum(&[0xab]), !0xab00);
+        assert_eq!(checksum(&[]), 0xffff);
+    }
+
+    #[test]
+    fn built_request_checks_out() {
+        let pinger = pinger("127.0.0.1", false);
+        let request = pinger.build_request(7);
+        assert_eq!(&request[..2], [ECHO_REQUEST_V4, 0]);
+        assert_eq!(&request[4..8], [0x12, 0x34, 0, 7]);
+        assert_eq!(checksum(&request), 0);
+    }
+
+    #[test]
+    fn matches_replies_with_and_without_ip_header() {
+        let pinger = pinger("127.0.0.1", false);
+        let reply = echo(ECHO_REPLY_V4, 0x1234, 9);
+        assert!(matches!(pinger.match_reply(&reply), Some((9, Ok(())))));
+        assert!(matches!(
+            pinger.match_reply(&ipv4(&reply)),
+            Some((9, Ok(())))
+        ));
+    }
+
+    #[test]
+    fn ignores_other_traffic() {
+        let pinger = pinger("127.0.0.1", false);
+        // Someone else's reply.
+        assert!(pinger
+            .match_reply(&echo(ECHO_REPLY_V4, 0x4321, 9))
+            .is_none());
+        // Our own request, seen on a raw socket.
+        assert!(pinger
+            .match_reply(&echo(ECHO_REQUEST_V4, 0x1234, 9))
+            .is_none());
+        assert!(pinger.match_reply(&[ECHO_REPLY_V4, 0, 0]).is_none());
+        assert!(pinger.match_reply(&[]).is_none());
+        // Ping sockets filter by identifier themselves.
+        let kernel = self::pinger("127.0.0.1", true);
+        assert!(kernel
+            .match_reply(&echo(ECHO_REPLY_V4, 0x4321, 9))
+            .is_some());
+    }
+
+    #[test]
+    fn matches_errors_quoting_our_request() {
+        let pinger = pinger("127.0.0.1", false);
+        let quoted = ipv4(&echo(ECHO_REQUEST_V4, 0x1234, 3));
+        let error = icmp_error(UNREACHABLE_V4, 1, &quoted);
+        match pinger.match_reply(&ipv4(&error)) {
+            Some((3, Err(PingError::Unreachable(reason)))) => {
+                assert_eq!(reason, "host unreachable")
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        let foreign = icmp_error(TIME_EXCEEDED_V4, 0, &ipv4(&echo(ECHO_REQUEST_V4, 1, 3)));
+        assert!(pinger.match_reply(&foreign).is_none());
+        // Quoted packet cut short.
+        let short = icmp_error(UNREACHABLE_V4, 1, &quoted[..24]);
+        assert!(pinger.match_reply(&short).is_none());
+    }
+
+    #[test]
+    fn matches_ipv6_replies_and_errors() {
+        let pinger = pinger("::1", false);
+        let reply = echo(ECHO_REPLY_V6, 0x1234, 5);
+        assert!(matches!(pinger.match_reply(&reply), Some((5, Ok(())))));
+        // The leading 0x81 nibble isn't mistaken for an IPv4 header.
+        assert!(pinger
+            .match_reply(&echo(ECHO_REQUEST_V6, 0x1234, 5))
+            .is_none());
+
+        let mut quoted = vec![0x60];
+        quoted.resize(40, 0);
+        quoted.extend_from_slice(&echo(ECHO_REQUEST_V6, 0x1234, 5));
+        match pinger.match_reply(&icmp_error(UNREACHABLE_V6, 1, &quoted)) {
+            Some((5, Err(PingError::Unreachable(reason)))) => {
+                assert_eq!(reason, "administratively prohibited")
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    /// A pinger sending to a local UDP socket instead of a host. With
+    /// `answer`, a thread sends each request back as an echo reply.
+    fn looped_pinger(answer: bool) -> Pinger {
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut pinger = pinger("127.0.0.1", false);
+        pinger.addr = peer.local_addr().unwrap();
+        let from = pinger.sock.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 1500];
+            while let Ok(n) = peer.recv(&mut buf) {
+                if answer {
+                    buf[0] = ECHO_REPLY_V4;
+                    let _ = peer.send_to(&buf[..n], from);
+                }
+            }
+        });
+        pinger
+    }
+
+    #[test]
+    fn echo_series_collects_replies() {
+        let pinger = looped_pinger(true);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let (sent, rtts, error) = pinger.echo_series(3, Duration::from_millis(10), deadline);
+        assert_eq!((sent, rtts.len()), (3, 3));
+        assert!(error.is_none());
+    }
+
+    #[test]
+    fn echo_series_stops_at_the_deadline() {
+        let pinger = looped_pinger(false);
+        let start = Instant::now();
+        let deadline = start + Duration::from_millis(250);
+        let (sent, rtts, error) = pinger.echo_series(10, Duration::from_millis(100), deadline);
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(250), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(400), "{:?}", elapsed);
+        assert_eq!(sent, 3);
+        assert!(rtts.is_empty());
+        assert!(matches!(error, Some(PingError::Timeout)));
+    }
+
+    #[test]
+    fn stats_from_round_trip_times() {
+        let stats = PingStats::from_rtts(5, &[10.0, 14.0, 12.0, 16.0]);
+        assert_eq!((stats.sent, stats.received), (5, 4));
+        assert_eq!(stats.loss_pct, 20.0);
+        assert_eq!(
+            (stats.min_ms, stats.avg_ms, stats.max_ms),
+            (10.0, 13.0, 16.0)
+        );
+        assert!((stats.mdev_ms - 5f64.sqrt()).abs() < 1e-9);
+        assert!((stats.jitter_ms - 10.0 / 3.0).abs() < 1e-9);
+
+        let single = PingStats::from_rtts(1, &[7.5]);
+        assert_eq!(
+            (single.mdev_ms, single.jitter_ms, single.loss_pct),
+            (0.0, 0.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn stats_without_replies() {
+        let lost = PingStats::from_rtts(3, &[]);
+        assert_eq!((lost.received, lost.loss_pct, lost.avg_ms), (0, 100.0, 0.0));
+        assert_eq!(PingStats::from_rtts(0, &[]).loss_pct, 0.0);
+    }
+
+    #[test]
+    fn parses_ping_output() {
+        let linux = "PING example.com (93.184.216.34) 56(84) bytes of data.\n\
+                     64 bytes from 93.184.216.34: icmp_seq=1 ttl=56 time=12.3 ms\n\
+                     64 bytes from 93.184.216.34: icmp_seq=2 ttl=56 time=5 ms\n\
+                     rtt min/avg/max/mdev = 5.000/8.650/12.300/3.650 ms";
+        assert_eq!(parse_ping_latencies(linux), [12.3, 5.0]);
+
+        let windows = "Reply from 10.0.0.1: bytes=32 time<1ms TTL=64\r\n\
+                       Reply from 10.0.0.1: bytes=32 TIME=3ms TTL=64\r\n\
+                       Request timed out.\r\n";
+        assert_eq!(parse_ping_latencies(windows), [1.0, 3.0]);
+
+        let macos = "64 bytes from 10.0.0.1: icmp_seq=0 ttl=64 time=0.456 ms";
+        assert_eq!(parse_ping_latencies(macos), [0.456]);
+        assert!(parse_ping_latencies("ping: unknown host").is_empty());
+    }
+}
@@ -0,0 +1,418 @@
+use crate::events::{Event, EventKind};
+use crate::flap::{Flap, FlapDetector};
+use crate::notify::{self, Notice};
+use crate::stats::Health;
+use crate::{AppState, ProbeError, ProbeResult, Target};
+use serde::Serialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tauri::{AppHandle, Emitter};
+
+/// Consecutive results a new health category must hold for before it's
+/// recorded, so a single slow probe doesn't log two transitions.
+const HEALTH_SETTLE: u32 = 3;
+
+/// A period during which a target was down.
+#[derive(Debug, Clone, Serialize)]
+pub struct Incident {
+    pub target_id: String,
+    /// Timestamp of the first failed probe.
+    pub started: u64,
+    /// Timestamp of the first successful probe after it; absent while open.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ended: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    /// Serialized as `error_kind` and `error`: the first failure's.
+    #[serde(flatten)]
+    pub first_error: Option<ProbeError>,
+}
+
+/// Something `Tracker::observe` decided happened.
+#[derive(Debug, Clone)]
+pub enum Transition {
+    /// `announce` is false while the target is flapping: the incident is
+    /// stored, but not emitted.
+    Opened {
+        incident: Incident,
+        announce: bool,
+    },
+    /// Announced only if the incident's opening was.
+    Closed {
+        incident: Incident,
+        announce: bool,
+    },
+    FlappingStarted {
+        change_rate: f64,
+    },
+    /// `pending` is an incident opened while flapping that is still open,
+    /// and gets announced now.
+    FlappingStopped {
+        change_rate: f64,
+        pending: Option<Incident>,
+    },
+    Health {
+        from: Health,
+        to: Health,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum State {
+    #[default]
+    Unknown,
+    Up,
+    Down,
+}
+
+#[derive(Default)]
+struct TargetState {
+    state: State,
+    /// The current run of failures: its length and first result.
+    failures: u32,
+    first_failure: Option<(u64, Option<ProbeError>)>,
+    /// The current run of successes: its length and first timestamp.
+    successes: u32,
+    first_success: Option<u64>,
+    open: Option<Incident>,
+    /// Whether `incident:opened` went out for `open`.
+    announced: bool,
+    flaps: FlapDetector,
+    health: Option<Health>,
+    pending_health: Option<(Health, u32)>,
+}
+
+/// Per-target up/down state with hysteresis: a target goes down after
+/// `down_after` consecutive failures and comes back after `up_after`
+/// consecutive successes. While a target flaps, its incidents are recorded
+/// but not announced, so it doesn't raise a notification every other probe.
+#[derive(Default)]
+pub struct Tracker {
+    targets: HashMap<String, TargetState>,
+}
+
+impl Tracker {
+    /// Feeds one result (and the health it left the target at) through the
+    /// target's state machine. Only `result.ok` counts: an `address_policy:
+    /// "all"` target with some failing members is still up here.
+    pub fn observe(
+        &mut self,
+        target: &Target,
+        result: &ProbeResult,
+        health: Health,
+    ) -> Vec<Transition> {
+        let s = self.targets.entry(target.id.clone()).or_default();
+        let mut transitions = Vec::new();
+
+        match s.flaps.observe(result.ok) {
+            Some(Flap::Started(change_rate)) => {
+                transitions.push(Transition::FlappingStarted { change_rate })
+            }
+            Some(Flap::Stopped(change_rate)) => {
+                let pending = s.open.clone().filter(|_| !s.announced);
+                s.announced = s.open.is_some();
+                transitions.push(Transition::FlappingStopped {
+                    change_rate,
+                    pending,
+                });
+            }
+            None => {}
+        }
+
+        if result.ok {
+            s.failures = 0;
+            s.first_failure = None;
+            s.successes += 1;
+            let since = *s.first_success.get_or_insert(result.timestamp);
+            if s.state != State::Up && s.successes >= target.up_after {
+                s.state = State::Up;
+                if let Some(mut incident) = s.open.take() {
+                    incident.ended = Some(since);
+                    incident.duration_ms = Some(since.saturating_sub(incident.started));
+                    transitions.push(Transition::Closed {
+                        incident,
+                        announce: s.announced,
+                    });
+                }
+            }
+        } else {
+            s.successes = 0;
+            s.first_success = None;
+            s.failures += 1;
+            let (since, error) = s
+                .first_failure
+                .get_or_insert_with(|| (result.timestamp, result.error.clone()))
+                .clone();
+            if s.state != State::Down && s.failures >= target.down_after {
+                s.state = State::Down;
+                let incident = Incident {
+                    target_id: target.id.clone(),
+                    started: since,
+                    ended: None,
+                    duration_ms: None,
+                    first_error: error,
+                };
+                s.open = Some(incident.clone());
+                s.announced = !s.flaps.is_flapping();
+                transitions.push(Transition::Opened {
+                    incident,
+                    announce: s.announced,
+                });
+            }
+        }
+
+        if health != Health::Unknown && s.health != Some(health) {
+            let seen = match s.pending_health {
+                Some((pending, n)) if pending == health => n + 1,
+                _ => 1,
+            };
+            s.pending_health = Some((health, seen));
+            // The first category is taken as is; later ones have to settle.
+            if s.health.is_none() || seen >= HEALTH_SETTLE {
+                if let Some(from) = s.health {
+                    transitions.push(Transition::Health { from, to: health });
+                }
+                s.health = Some(health);
+                s.pending_health = None;
+            }
+        } else {
+            s.pending_health = None;
+        }
+
+        transitions
+    }
+
+    pub fn is_flapping(&self, id: &str) -> bool {
+        self.targets.get(id).is_some_and(|s| s.flaps.is_flapping())
+    }
+
+    /// Drops state for targets that no longer exist.
+    pub fn retain(&mut self, targets: &[Target]) {
+        self.targets
+            .retain(|id, _| targets.iter().any(|t| &t.id == id));
+    }
+}
+
+/// Emits `incident:opened` / `incident:closed` and notifies about it.
+fn announce(app: &AppHandle, state: &Arc<AppState>, target: &Target, notice: Notice) {
+    let (event, incident) = match &notice {
+        Notice::Down(incident) => ("incident:opened", incident),
+        Notice::Up(incident) => ("incident:closed", incident),
+    };
+    let _ = app.emit(event, incident);
+    notify::dispatch(app, state, target, notice);
+}
+
+/// Stores the transitions `result` caused in the history database and
+/// emits `incident:opened` / `incident:closed` for the announced ones, and
+/// `flapping:started` / `flapping:stopped`.
+pub fn publish(
+    app: &AppHandle,
+    state: &Arc<AppState>,
+    target: &Target,
+    result: &ProbeResult,
+    transitions: Vec<Transition>,
+) {
+    for transition in transitions {
+        let flapping =
+            |change_rate: f64| json!({ "target_id": result.id, "change_rate": change_rate });
+        let kind = match transition {
+            Transition::Opened { incident, announce } => {
+                if announce {
+                    self::announce(app, state, target, Notice::Down(incident.clone()));
+                }
+                EventKind::IncidentOpened(incident)
+            }
+            Transition::Closed { incident, announce } => {
+                if announce {
+                    self::announce(app, state, target, Notice::Up(incident.clone()));
+                }
+                EventKind::IncidentClosed(incident)
+            }
+            Transition::FlappingStarted { change_rate } => {
+                let _ = app.emit("flapping:started", flapping(change_rate));
+                EventKind::FlappingStarted { change_rate }
+            }
+            Transition::FlappingStopped {
+                change_rate,
+                pending,
+            } => {
+                let _ = app.emit("flapping:stopped", flapping(change_rate));
+                if let Some(incident) = pending {
+                    announce(app, state, target, Notice::Down(incident));
+                }
+                EventKind::FlappingStopped { change_rate }
+            }
+            Transition::Health { from, to } => EventKind::Health { from, to },
+        };
+        if let Some(history) = state.history.get() {
+            if let EventKind::IncidentOpened(incident) | EventKind::IncidentClosed(incident) = &kind
+            {
+                history.record_incident(incident);
+            }
+            history.record_event(&Event {
+                timestamp: result.timestamp,
+                target_id: result.id.clone(),
+                kind,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ErrorKind;
+
+    fn target(down_after: u32, up_after: u32) -> Target {
+        serde_json::from_value(serde_json::json!({
+            "id": "t",
+            "name": "T",
+            "host": "example.com",
+            "port": 443,
+            "down_after": down_after,
+            "up_after": up_after,
+        }))
+        .unwrap()
+    }
+
+    fn result(ok: bool, timestamp: u64) -> ProbeResult {
+        ProbeResult {
+            id: "t".into(),
+            ok,
+            timestamp,
+            error: (!ok)
+                .then(|| ProbeError::new(ErrorKind::ConnectTimeout, format!("t{}", timestamp))),
+            ..Default::default()
+        }
+    }
+
+    /// Feeds `states` one second apart, starting at `start`, and returns
+    /// every transition.
+    fn feed(
+        tracker: &mut Tracker,
+        target: &Target,
+        start: u64,
+        states: &[bool],
+    ) -> Vec<Transition> {
+        states
+            .iter()
+            .enumerate()
+            .flat_map(|(i, &ok)| {
+                tracker.observe(
+                    target,
+                    &result(ok, start + i as u64 * 1000),
+                    Health::Unknown,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn opens_after_down_after_failures() {
+        let target = target(3, 1);
+        let mut tracker = Tracker::default();
+        assert!(feed(
+            &mut tracker,
+            &target,
+            0,
+            &[false, false, true, false, false]
+        )
+        .is_empty());
+        let transitions = feed(&mut tracker, &target, 5000, &[false]);
+        match &transitions[..] {
+            [Transition::Opened {
+                incident,
+                announce: true,
+            }] => {
+                // Started with the first failure of the run, and its error.
+                assert_eq!(incident.started, 3000);
+                assert_eq!(incident.first_error.as_ref().unwrap().detail, "t3000");
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(feed(&mut tracker, &target, 6000, &[false, false]).is_empty());
+    }
+
+    #[test]
+    fn closes_after_up_after_successes() {
+        let target = target(1, 2);
+        let mut tracker = Tracker::default();
+        feed(&mut tracker, &target, 0, &[false]);
+        assert!(feed(&mut tracker, &target, 1000, &[true, false, true]).is_empty());
+        let transitions = feed(&mut tracker, &target, 4000, &[true]);
+        match &transitions[..] {
+            [Transition::Closed {
+                incident,
+                announce: true,
+            }] => {
+                assert_eq!(incident.started, 0);
+                assert_eq!(incident.ended, Some(3000));
+                assert_eq!(incident.duration_ms, Some(3000));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn flapping_holds_back_announcements() {
+        let target = target(1, 1);
+        let mut tracker = Tracker::default();
+        let alternating: Vec<bool> = (0..21).map(|i| i % 2 == 0).collect();
+        let transitions = feed(&mut tracker, &target, 0, &alternating);
+        assert!(transitions
+            .iter()
+            .any(|t| matches!(t, Transition::FlappingStarted { .. })));
+        assert!(tracker.is_flapping("t"));
+
+        let transitions = feed(&mut tracker, &target, 21_000, &[false, true]);
+        assert!(matches!(
+            &transitions[..],
+            [
+                Transition::Opened {
+                    announce: false,
+                    ..
+                },
+                Transition::Closed {
+                    announce: false,
+                    ..
+                }
+            ]
+        ));
+    }
+
+    #[test]
+    fn open_incident_is_announced_when_flapping_stops() {
+        let target = target(1, 1);
+        let mut tracker = Tracker::default();
+        let alternating: Vec<bool> = (0..21).map(|i| i % 2 == 0).collect();
+        feed(&mut tracker, &target, 0, &alternating);
+        assert!(tracker.is_flapping("t"));
+
+        // Down for good: opened silently, then announced once flapping stops.
+        let failures = vec![false; 21];
+        let transitions = feed(&mut tracker, &target, 21_000, &failures);
+        let opened = transitions.iter().position(|t| {
+            matches!(
+                t,
+                Transition::Opened {
+                    announce: false,
+                    ..
+                }
+            )
+        });
+        let stopped = transitions.iter().position(|t| {
+            matches!(t, Transition::FlappingStopped { pending: Some(incident), .. } if incident.started == 21_000)
+        });
+        assert!(matches!((opened, stopped), (Some(o), Some(s)) if o < s));
+        assert!(!tracker.is_flapping("t"));
+
+        // The recovery is announced since the opening now was.
+        let transitions = feed(&mut tracker, &target, 42_000, &[true]);
+        assert!(matches!(
+            &transitions[..],
+            [Transition::Closed { announce: true, .. }]
+        ));
+    }
+}
@@ -1,13 +1,76 @@
-use serde::{Deserialize, Serialize};
-use std::net::{TcpStream, ToSocketAddrs};
-use std::process::Command;
-use std::sync::{Arc, RwLock};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
 use std::time::{Duration, Instant};
-use tauri::{AppHandle, Emitter};
-use tokio::time::interval;
+use tokio::net::TcpStream;
+use tokio::sync::Notify;
 
-fn default_probe_type() -> String {
-    "tcp".to_string()
+mod alert;
+mod banner;
+mod dns;
+mod email;
+mod error;
+mod events;
+mod flap;
+mod history;
+mod hook;
+mod http;
+mod icmp;
+mod incident;
+mod net;
+mod notify;
+mod rollup;
+mod scheduler;
+mod secret;
+mod stats;
+mod tls;
+mod udp;
+mod validate;
+mod webhook;
+
+pub use error::{ErrorKind, ProbeError};
+pub use net::AddressPolicy;
+pub use validate::TargetError;
+
+fn default_interval_ms() -> u64 {
+    5000
+}
+
+fn default_timeout_ms() -> u64 {
+    2000
+}
+
+fn default_down_after() -> u32 {
+    3
+}
+
+fn default_up_after() -> u32 {
+    1
+}
+
+/// Floor for `interval_ms`, so a typo can't turn into a probe storm.
+const MIN_INTERVAL_MS: u64 = 250;
+
+/// What to probe and how. Serialized flat into the target, tagged by
+/// `probe_type`, so targets.json keeps one object per target.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "probe_type", rename_all = "lowercase")]
+pub enum ProbeKind {
+    Tcp,
+    Ping(icmp::PingSettings),
+    Http(http::HttpSettings),
+    Tls(tls::TlsSettings),
+    Dns(dns::DnsSettings),
+    Udp(udp::UdpSettings),
+    Banner(banner::BannerSettings),
+}
+
+/// Targets saved before `probe_type` existed are TCP. An unknown
+/// `probe_type` is an error rather than a silent fallback.
+fn probe_kind_or_tcp<'de, D: Deserializer<'de>>(deserializer: D) -> Result<ProbeKind, D::Error> {
+    let mut fields = serde_json::Map::deserialize(deserializer)?;
+    fields.entry("probe_type").or_insert_with(|| "tcp".into());
+    ProbeKind::deserialize(serde_json::Value::Object(fields)).map_err(serde::de::Error::custom)
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -16,229 +79,805 @@ pub struct Target {
     pub name: String,
     pub host: String,
     pub port: u16,
-    #[serde(default = "default_probe_type")]
-    pub probe_type: String,
+    #[serde(flatten, deserialize_with = "probe_kind_or_tcp")]
+    pub kind: ProbeKind,
+    /// How often this target is probed.
+    #[serde(default = "default_interval_ms")]
+    pub interval_ms: u64,
+    /// How long a single probe may take before it counts as failed.
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    /// Which resolved addresses to probe.
+    #[serde(default)]
+    pub address_policy: AddressPolicy,
+    /// DNS server ("ip" or "ip:port") to resolve `host` with, instead of
+    /// the system resolver.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolver: Option<String>,
+    /// How long a resolution is reused before `host` is looked up again;
+    /// 0 resolves on every probe.
+    #[serde(default)]
+    pub resolve_cache_ms: u64,
+    /// Source IP for the probe's sockets and custom resolver queries.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bind_address: Option<String>,
+    /// Network interface to send from (Linux only).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bind_interface: Option<String>,
+    /// Consecutive failures before the target is declared down.
+    #[serde(default = "default_down_after")]
+    pub down_after: u32,
+    /// Consecutive successes before a down target is declared up again.
+    #[serde(default = "default_up_after")]
+    pub up_after: u32,
+    /// Announce when the target goes down or recovers: a desktop
+    /// notification, plus every webhook, command hook and email config that
+    /// doesn't list its own targets.
+    #[serde(default)]
+    pub notify: bool,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Default, Serialize)]
 pub struct ProbeResult {
     pub id: String,
     pub ok: bool,
-    pub latency_ms: u64,
-    pub error: Option<String>,
+    pub latency_ms: f64,
+    /// Serialized as `error_kind` and `error` (the detail).
+    #[serde(flatten)]
+    pub error: Option<ProbeError>,
     pub timestamp: u64,
+    /// Wall time of the whole probe, resolution included.
+    pub total_ms: f64,
+    /// Time spent resolving the target's host; omitted when the answer
+    /// came from the resolution cache.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dns_ms: Option<f64>,
+    /// TCP connect alone, for probes that connect.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connect_ms: Option<f64>,
+    /// TLS handshake alone.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls_ms: Option<f64>,
+    /// From the request (or connect, if nothing is sent) to the first byte back.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_byte_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub http_status: Option<u16>,
+    /// Set when the probe succeeded but something needs attention
+    /// (e.g. a certificate close to expiry); caps health at "warn".
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cert: Option<tls::CertInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dns_rcode: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub dns_answers: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ping: Option<icmp::PingStats>,
+    /// Banner probe: time from connect to the first line.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub banner_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub banner: Option<String>,
+    /// The address that was actually probed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<IpAddr>,
+    /// With `address_policy: "all"`, one result per resolved address.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub members: Vec<ProbeResult>,
+    /// How many of `members` failed; set whenever `members` is.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failed_members: Option<u32>,
 }
 
 #[derive(Default)]
 pub struct AppState {
     pub targets: RwLock<Vec<Target>>,
+    /// Wakes the scheduler when the target list changes.
+    pub targets_changed: Notify,
+    pub stats: Mutex<stats::StatsStore>,
+    pub incidents: Mutex<incident::Tracker>,
+    pub alerts: Mutex<alert::AlertEngine>,
+    pub notifier: Mutex<notify::Notifier>,
+    pub webhooks: RwLock<Vec<webhook::Webhook>>,
+    pub hooks: RwLock<Vec<hook::CommandHook>>,
+    pub mailer: Mutex<email::Mailer>,
+    /// Set once the database is open; probes still run without it.
+    pub history: OnceLock<history::History>,
 }
 
-fn tcp_probe(host: &str, port: u16, timeout_ms: u64) -> ProbeResult {
-    let addr = format!("{}:{}", host, port);
-    let start = Instant::now();
-    let timestamp = std::time::SystemTime::now()
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
         .unwrap()
-        .as_millis() as u64;
-
-    let resolved = match addr.to_socket_addrs() {
-        Ok(mut addrs) => match addrs.next() {
-            Some(a) => a,
-            None => {
-                return ProbeResult {
-                    id: String::new(),
-                    ok: false,
-                    latency_ms: start.elapsed().as_millis() as u64,
-                    error: Some("dns_failed".into()),
-                    timestamp,
-                }
-            }
-        },
-        Err(e) => {
-            return ProbeResult {
-                id: String::new(),
-                ok: false,
-                latency_ms: start.elapsed().as_millis() as u64,
-                error: Some(format!("dns_error: {}", e)),
-                timestamp,
-            }
-        }
-    };
+        .as_millis() as u64
+}
 
-    match TcpStream::connect_timeout(&resolved, Duration::from_millis(timeout_ms)) {
-        Ok(_) => ProbeResult {
-            id: String::new(),
-            ok: true,
-            latency_ms: start.elapsed().as_millis() as u64,
-            error: None,
-            timestamp,
-        },
-        Err(e) => ProbeResult {
-            id: String::new(),
-            ok: false,
-            latency_ms: start.elapsed().as_millis() as u64,
-            error: Some(e.to_string()),
-            timestamp,
-        },
+fn elapsed_ms(start: Instant) -> f64 {
+    start.elapsed().as_secs_f64() * 1000.0
+}
+
+#[tauri::command]
+fn get_targets(state: tauri::State<'_, Arc<AppState>>) -> Vec<Target> {
+    state.targets.read().unwrap().clone()
+}
+
+/// Replaces the target list, unless any target fails validation; then the
+/// current list is kept and every problem is returned.
+#[tauri::command]
+fn set_targets(
+    state: tauri::State<'_, Arc<AppState>>,
+    targets: Vec<serde_json::Value>,
+) -> Result<(), Vec<TargetError>> {
+    let targets = validate::parse_targets(targets)?;
+    state.stats.lock().unwrap().retain(&targets);
+    state.incidents.lock().unwrap().retain(&targets);
+    state.notifier.lock().unwrap().retain(&targets);
+    let mut t = state.targets.write().unwrap();
+    *t = targets;
+    state.targets_changed.notify_one();
+    Ok(())
+}
+
+#[tauri::command]
+fn get_stats(state: tauri::State<'_, Arc<AppState>>) -> stats::StatsSnapshot {
+    stats::snapshot(&state)
+}
+
+/// Sets the rolling window the stats cover.
+#[tauri::command]
+fn set_stats_window(state: tauri::State<'_, Arc<AppState>>, window_ms: u64) -> Result<(), String> {
+    if !(stats::MIN_WINDOW_MS..=stats::MAX_WINDOW_MS).contains(&window_ms) {
+        return Err(format!(
+            "invalid_window: must be between {} and {} ms",
+            stats::MIN_WINDOW_MS,
+            stats::MAX_WINDOW_MS
+        ));
     }
+    state.stats.lock().unwrap().set_window_ms(window_ms);
+    Ok(())
 }
 
-fn parse_ping_latency(output: &str) -> Option<u64> {
-    // Match patterns like "time=12.3ms", "time=5ms", "time<1ms", "time=0.456 ms"
-    for line in output.lines() {
-        let lower = line.to_lowercase();
-        if let Some(pos) = lower.find("time=") {
-            let after = &lower[pos + 5..];
-            let num_str: String = after.chars().take_while(|c| c.is_ascii_digit() || *c == '.').collect();
-            if let Ok(val) = num_str.parse::<f64>() {
-                return Some(val.round() as u64);
-            }
-        } else if let Some(pos) = lower.find("time<") {
-            let after = &lower[pos + 5..];
-            let num_str: String = after.chars().take_while(|c| c.is_ascii_digit() || *c == '.').collect();
-            if let Ok(val) = num_str.parse::<f64>() {
-                return Some(val.round().max(1.0) as u64);
-            }
-            // time<1ms means sub-millisecond
-            return Some(1);
+/// Stored history for one target between `from` and `to` (Unix ms), raw or
+/// rolled up depending on the range.
+#[tauri::command]
+async fn query_history(
+    state: tauri::State<'_, Arc<AppState>>,
+    target_id: String,
+    from: u64,
+    to: u64,
+) -> Result<history::HistoryPage, String> {
+    let state = state.inner().clone();
+    tokio::task::spawn_blocking(move || {
+        let history = state.history.get().ok_or("history_unavailable")?;
+        history
+            .query(&target_id, from, to)
+            .map_err(|e| format!("history_error: {}", e))
+    })
+    .await
+    .map_err(|e| format!("task_failed: {}", e))?
+}
+
+/// Incidents overlapping `from..=to` (Unix ms), for one target or all.
+#[tauri::command]
+async fn query_incidents(
+    state: tauri::State<'_, Arc<AppState>>,
+    target_id: Option<String>,
+    from: u64,
+    to: u64,
+) -> Result<Vec<serde_json::Value>, String> {
+    let state = state.inner().clone();
+    tokio::task::spawn_blocking(move || {
+        let history = state.history.get().ok_or("history_unavailable")?;
+        history
+            .incidents(target_id.as_deref(), from, to)
+            .map_err(|e| format!("history_error: {}", e))
+    })
+    .await
+    .map_err(|e| format!("task_failed: {}", e))?
+}
+
+/// Event log entries between `from` and `to` (Unix ms), for one target or all.
+#[tauri::command]
+async fn query_events(
+    state: tauri::State<'_, Arc<AppState>>,
+    target_id: Option<String>,
+    from: u64,
+    to: u64,
+) -> Result<Vec<serde_json::Value>, String> {
+    let state = state.inner().clone();
+    tokio::task::spawn_blocking(move || {
+        let history = state.history.get().ok_or("history_unavailable")?;
+        history
+            .events(target_id.as_deref(), from, to)
+            .map_err(|e| format!("history_error: {}", e))
+    })
+    .await
+    .map_err(|e| format!("task_failed: {}", e))?
+}
+
+#[tauri::command]
+fn get_alert_rules(state: tauri::State<'_, Arc<AppState>>) -> Vec<alert::AlertRule> {
+    state.alerts.lock().unwrap().rules().to_vec()
+}
+
+/// Replaces the alert rules and saves them. Alerts of rules that changed or
+/// were removed resolve.
+#[tauri::command]
+async fn set_alert_rules(
+    app: tauri::AppHandle,
+    state: tauri::State<'_, Arc<AppState>>,
+    rules: Vec<alert::AlertRule>,
+) -> Result<(), String> {
+    alert::check_rules(&rules)?;
+    let state = state.inner().clone();
+    tokio::task::spawn_blocking(move || {
+        if let Some(history) = state.history.get() {
+            history
+                .set_setting(alert::RULES_KEY, &rules)
+                .map_err(|e| format!("history_error: {}", e))?;
         }
-    }
-    None
+        let (changes, keep_ms) = {
+            let mut alerts = state.alerts.lock().unwrap();
+            (alerts.set_rules(rules), alerts.keep_ms())
+        };
+        state.stats.lock().unwrap().set_keep_ms(keep_ms);
+        alert::publish(&app, &state, changes);
+        Ok(())
+    })
+    .await
+    .map_err(|e| format!("task_failed: {}", e))?
 }
 
-fn icmp_ping(host: &str) -> ProbeResult {
-    let start = Instant::now();
-    let timestamp = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap()
-        .as_millis() as u64;
-
-    let result = if cfg!(target_os = "windows") {
-        Command::new("ping")
-            .args(["-n", "1", "-w", "2000", host])
-            .output()
-    } else if cfg!(target_os = "macos") {
-        Command::new("ping")
-            .args(["-c", "1", "-t", "2", host])
-            .output()
-    } else {
-        // Linux / Android
-        Command::new("ping")
-            .args(["-c", "1", "-W", "2", host])
-            .output()
-    };
+/// Alerts currently firing.
+#[tauri::command]
+fn get_alerts(state: tauri::State<'_, Arc<AppState>>) -> Vec<alert::Alert> {
+    state.alerts.lock().unwrap().firing()
+}
 
-    match result {
-        Ok(output) => {
-            let stdout = String::from_utf8_lossy(&output.stdout);
-            let elapsed = start.elapsed().as_millis() as u64;
+#[tauri::command]
+fn get_notification_settings(
+    state: tauri::State<'_, Arc<AppState>>,
+) -> notify::NotificationSettings {
+    state.notifier.lock().unwrap().settings().clone()
+}
 
-            if output.status.success() {
-                let latency = parse_ping_latency(&stdout).unwrap_or(elapsed);
-                ProbeResult {
-                    id: String::new(),
-                    ok: true,
-                    latency_ms: latency,
-                    error: None,
-                    timestamp,
-                }
-            } else {
-                let stderr = String::from_utf8_lossy(&output.stderr);
-                ProbeResult {
-                    id: String::new(),
-                    ok: false,
-                    latency_ms: elapsed,
-                    error: Some(format!("ping_failed: {}", stderr.trim())),
-                    timestamp,
-                }
-            }
+#[tauri::command]
+async fn set_notification_settings(
+    state: tauri::State<'_, Arc<AppState>>,
+    settings: notify::NotificationSettings,
+) -> Result<(), String> {
+    let state = state.inner().clone();
+    tokio::task::spawn_blocking(move || {
+        if let Some(history) = state.history.get() {
+            history
+                .set_setting(notify::SETTINGS_KEY, &settings)
+                .map_err(|e| format!("history_error: {}", e))?;
         }
-        Err(e) => ProbeResult {
-            id: String::new(),
-            ok: false,
-            latency_ms: start.elapsed().as_millis() as u64,
-            error: Some(format!("ping_unavailable: {}", e)),
-            timestamp,
-        },
-    }
+        state.notifier.lock().unwrap().set_settings(settings);
+        Ok(())
+    })
+    .await
+    .map_err(|e| format!("task_failed: {}", e))?
 }
 
 #[tauri::command]
-fn get_targets(state: tauri::State<'_, Arc<AppState>>) -> Vec<Target> {
-    state.targets.read().unwrap().clone()
+fn get_webhooks(state: tauri::State<'_, Arc<AppState>>) -> Vec<webhook::Webhook> {
+    state
+        .webhooks
+        .read()
+        .unwrap()
+        .iter()
+        .map(webhook::Webhook::redacted)
+        .collect()
 }
 
+/// Replaces the webhooks and saves them. Masked header values keep the
+/// stored ones.
 #[tauri::command]
-fn set_targets(state: tauri::State<'_, Arc<AppState>>, targets: Vec<Target>) {
-    let mut t = state.targets.write().unwrap();
-    *t = targets;
+async fn set_webhooks(
+    state: tauri::State<'_, Arc<AppState>>,
+    mut webhooks: Vec<webhook::Webhook>,
+) -> Result<(), String> {
+    let previous = state.webhooks.read().unwrap().clone();
+    webhook::keep_headers(&mut webhooks, &previous)?;
+    webhook::check_webhooks(&webhooks)?;
+    let state = state.inner().clone();
+    tokio::task::spawn_blocking(move || {
+        webhook::save(state.history.get(), &webhooks, &previous)?;
+        *state.webhooks.write().unwrap() = webhooks;
+        Ok(())
+    })
+    .await
+    .map_err(|e| format!("task_failed: {}", e))?
+}
+
+/// Sends a sample payload to `webhook`, which needn't be saved yet. Masked
+/// header values are the stored ones.
+#[tauri::command]
+async fn test_webhook(
+    state: tauri::State<'_, Arc<AppState>>,
+    mut webhook: webhook::Webhook,
+) -> Result<webhook::Delivery, String> {
+    let current = state.webhooks.read().unwrap().clone();
+    webhook::keep_headers(std::slice::from_mut(&mut webhook), &current)?;
+    webhook::test(&webhook).await
+}
+
+#[tauri::command]
+fn get_hooks(state: tauri::State<'_, Arc<AppState>>) -> Vec<hook::CommandHook> {
+    state.hooks.read().unwrap().clone()
 }
 
+/// Replaces the command hooks and saves them.
 #[tauri::command]
-async fn probe_target(host: String, port: u16, probe_type: Option<String>) -> ProbeResult {
-    let pt = probe_type.unwrap_or_else(|| "tcp".to_string());
+async fn set_hooks(
+    state: tauri::State<'_, Arc<AppState>>,
+    hooks: Vec<hook::CommandHook>,
+) -> Result<(), String> {
+    hook::check_hooks(&hooks)?;
+    let state = state.inner().clone();
     tokio::task::spawn_blocking(move || {
-        if pt == "ping" {
-            icmp_ping(&host)
-        } else {
-            tcp_probe(&host, port, 2000)
+        if let Some(history) = state.history.get() {
+            history
+                .set_setting(hook::SETTINGS_KEY, &hooks)
+                .map_err(|e| format!("history_error: {}", e))?;
         }
+        *state.hooks.write().unwrap() = hooks;
+        Ok(())
     })
     .await
-    .unwrap_or_else(|_| ProbeResult {
-        id: String::new(),
-        ok: false,
-        latency_ms: 0,
-        error: Some("task_failed".into()),
-        timestamp: 0,
+    .map_err(|e| format!("task_failed: {}", e))?
+}
+
+/// Runs `hook`, which needn't be saved yet, with made-up details.
+#[tauri::command]
+async fn test_hook(hook: hook::CommandHook) -> Result<hook::HookRun, String> {
+    hook::test(&hook).await
+}
+
+#[tauri::command]
+fn get_email_settings(state: tauri::State<'_, Arc<AppState>>) -> email::EmailSettings {
+    state.mailer.lock().unwrap().settings().redacted()
+}
+
+/// Replaces the email settings and saves them. An empty or masked
+/// password keeps the stored one.
+#[tauri::command]
+async fn set_email_settings(
+    state: tauri::State<'_, Arc<AppState>>,
+    mut settings: email::EmailSettings,
+) -> Result<(), String> {
+    settings.keep_password(state.mailer.lock().unwrap().settings());
+    email::check_settings(&settings)?;
+    let state = state.inner().clone();
+    tokio::task::spawn_blocking(move || {
+        email::save(state.history.get(), &settings)?;
+        state.mailer.lock().unwrap().set_settings(settings);
+        Ok(())
     })
+    .await
+    .map_err(|e| format!("task_failed: {}", e))?
 }
 
-fn start_probe_loop(app: AppHandle, state: Arc<AppState>) {
-    tauri::async_runtime::spawn(async move {
-        let mut ticker = interval(Duration::from_secs(5));
-        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+/// Sends a message with made-up details through `settings`, which needn't
+/// be saved yet. An empty or masked password is the stored one.
+#[tauri::command]
+async fn test_email(
+    state: tauri::State<'_, Arc<AppState>>,
+    mut settings: email::EmailSettings,
+) -> Result<email::Digest, String> {
+    settings.keep_password(state.mailer.lock().unwrap().settings());
+    email::test(&settings).await
+}
 
-        loop {
-            ticker.tick().await;
+/// Days of history kept per resolution.
+#[tauri::command]
+async fn get_retention(
+    state: tauri::State<'_, Arc<AppState>>,
+) -> Result<rollup::Retention, String> {
+    let state = state.inner().clone();
+    tokio::task::spawn_blocking(move || {
+        let history = state.history.get().ok_or("history_unavailable")?;
+        history
+            .retention()
+            .map_err(|e| format!("history_error: {}", e))
+    })
+    .await
+    .map_err(|e| format!("task_failed: {}", e))?
+}
 
-            let targets = {
-                let t = state.targets.read().unwrap();
-                t.clone()
-            };
+/// Replaces the retention policy; data past it is pruned on the next
+/// rollup pass.
+#[tauri::command]
+async fn set_retention(
+    state: tauri::State<'_, Arc<AppState>>,
+    retention: rollup::Retention,
+) -> Result<(), String> {
+    let state = state.inner().clone();
+    tokio::task::spawn_blocking(move || {
+        let history = state.history.get().ok_or("history_unavailable")?;
+        history
+            .set_retention(&retention)
+            .map_err(|e| format!("history_error: {}", e))
+    })
+    .await
+    .map_err(|e| format!("task_failed: {}", e))?
+}
+
+/// Clears the results of one target, or of all targets when `id` is absent.
+/// Alert rules lose their inputs too, so this is for results that no longer
+/// apply, e.g. after the target's host changed.
+#[tauri::command]
+fn reset_stats(state: tauri::State<'_, Arc<AppState>>, id: Option<String>) {
+    state.stats.lock().unwrap().reset(id.as_deref());
+}
+
+/// Starts the displayed stats of one target (or all) afresh, leaving the
+/// results alert rules and incident tracking work from alone.
+#[tauri::command]
+fn clear_stats_view(state: tauri::State<'_, Arc<AppState>>, id: Option<String>) {
+    state.stats.lock().unwrap().clear_view(id.as_deref());
+}
+
+fn task_failed(e: tokio::task::JoinError) -> ProbeResult {
+    ProbeResult {
+        ok: false,
+        error: Some(ProbeError::new(
+            ErrorKind::Internal,
+            format!("task_failed: {}", e),
+        )),
+        ..Default::default()
+    }
+}
+
+/// Runs a probe that still uses blocking sockets on the blocking pool.
+///
+/// TLS and HTTP(S) drive rustls' synchronous stream, ping may fall back to
+/// waiting on the `ping` binary, and DNS shares its query code with the
+/// synchronous resolver lookup; none of them has an async counterpart here
+/// without another TLS or DNS stack. That's affordable because every one of
+/// them stops at the probe's deadline (read/write timeouts are set from it),
+/// so a thread is held for at most `timeout_ms`, and the scheduler never runs
+/// more than `MAX_CONCURRENT_PROBES` at once, far below the pool's limit.
+async fn blocking(probe: impl FnOnce() -> ProbeResult + Send + 'static) -> ProbeResult {
+    tokio::task::spawn_blocking(probe)
+        .await
+        .unwrap_or_else(task_failed)
+}
+
+/// The host and port a target's probe talks to.
+fn endpoint(target: &Target) -> Result<(String, u16), ProbeError> {
+    match &target.kind {
+        ProbeKind::Http(settings) => http::endpoint(&http::target_url(target, settings)?),
+        ProbeKind::Dns(_) if target.port == 0 => Ok((target.host.clone(), 53)),
+        _ => Ok((target.host.clone(), target.port)),
+    }
+}
+
+/// Resolves the addresses a target's probe should use. The second value is
+/// the time spent resolving, `None` when no query was made.
+async fn resolve_target(
+    target: &Target,
+    bind: &net::Bind,
+    start: Instant,
+    deadline: tokio::time::Instant,
+) -> (Result<Vec<SocketAddr>, ProbeError>, Option<f64>) {
+    let (host, port) = match endpoint(target) {
+        Ok(e) => e,
+        Err(e) => return (Err(e), None),
+    };
+    let server = match target.resolver.as_deref().filter(|s| !s.trim().is_empty()) {
+        Some(raw) => match net::parse_resolver(raw) {
+            Ok(server) => Some(server),
+            Err(e) => return (Err(e), None),
+        },
+        None => None,
+    };
+    let cache_ttl = Duration::from_millis(target.resolve_cache_ms);
+    match net::lookup(&host, server, bind, cache_ttl, deadline).await {
+        Ok(found) => {
+            let dns_ms = (!found.cached).then(|| elapsed_ms(start));
+            let addrs = net::narrow(found.ips, port, target.address_policy, bind);
+            (addrs, dns_ms)
+        }
+        Err(e) => (Err(e), Some(elapsed_ms(start))),
+    }
+}
+
+/// A TCP connection made on behalf of a probe, and how long it took.
+struct Connected {
+    stream: TcpStream,
+    addr: SocketAddr,
+    connect_ms: f64,
+}
+
+impl Connected {
+    async fn open(
+        addrs: &[SocketAddr],
+        bind: &net::Bind,
+        deadline: tokio::time::Instant,
+    ) -> Result<Connected, ProbeError> {
+        let connect_start = Instant::now();
+        let (stream, addr) = net::connect(addrs, bind, deadline).await?;
+        Ok(Connected {
+            stream,
+            addr,
+            connect_ms: elapsed_ms(connect_start),
+        })
+    }
+
+    /// For probes that run on blocking sockets.
+    fn into_std(self) -> Result<std::net::TcpStream, ProbeError> {
+        let sock = self
+            .stream
+            .into_std()
+            .map_err(|e| ProbeError::io("connect_error", e))?;
+        sock.set_nonblocking(false)
+            .map_err(|e| ProbeError::io("connect_error", e))?;
+        Ok(sock)
+    }
+
+    fn finish(addr: SocketAddr, connect_ms: f64, result: ProbeResult) -> ProbeResult {
+        ProbeResult {
+            address: Some(addr.ip()),
+            connect_ms: Some(connect_ms),
+            ..result
+        }
+    }
+}
+
+/// Probes `addrs` (one address, or several to race for TCP-based probes).
+async fn probe_addrs(
+    target: &Target,
+    addrs: &[SocketAddr],
+    bind: &net::Bind,
+    start: Instant,
+    deadline: tokio::time::Instant,
+) -> ProbeResult {
+    let fail = |error: ProbeError| ProbeResult {
+        ok: false,
+        latency_ms: elapsed_ms(start),
+        error: Some(error),
+        address: match addrs {
+            [only] => Some(only.ip()),
+            _ => None,
+        },
+        ..Default::default()
+    };
+    let preferred = addrs[0];
+    let with_address = |result: ProbeResult| ProbeResult {
+        address: Some(preferred.ip()),
+        ..result
+    };
 
-            if targets.is_empty() {
-                continue;
+    match &target.kind {
+        ProbeKind::Tcp => match Connected::open(addrs, bind, deadline).await {
+            Ok(conn) => Connected::finish(
+                conn.addr,
+                conn.connect_ms,
+                ProbeResult {
+                    ok: true,
+                    latency_ms: elapsed_ms(start),
+                    ..Default::default()
+                },
+            ),
+            Err(e) => fail(e),
+        },
+        ProbeKind::Banner(banner) => match Connected::open(addrs, bind, deadline).await {
+            Ok(conn) => {
+                let (addr, connect_ms) = (conn.addr, conn.connect_ms);
+                let result = banner::banner_probe(conn.stream, banner, start, deadline).await;
+                Connected::finish(addr, connect_ms, result)
             }
+            Err(e) => fail(e),
+        },
+        ProbeKind::Tls(tls) => {
+            let conn = match Connected::open(addrs, bind, deadline).await {
+                Ok(c) => c,
+                Err(e) => return fail(e),
+            };
+            let (addr, connect_ms) = (conn.addr, conn.connect_ms);
+            let sock = match conn.into_std() {
+                Ok(s) => s,
+                Err(e) => return fail(e),
+            };
+            let sni = tls
+                .sni
+                .clone()
+                .filter(|s| !s.is_empty())
+                .unwrap_or_else(|| target.host.clone());
+            let tls = tls.clone();
+            let deadline = deadline.into_std();
+            let result = blocking(move || tls::tls_probe(sock, &sni, &tls, start, deadline)).await;
+            Connected::finish(addr, connect_ms, result)
+        }
+        ProbeKind::Http(http) => {
+            let url = match http::target_url(target, http) {
+                Ok(u) => u,
+                Err(e) => return fail(e),
+            };
+            let conn = match Connected::open(addrs, bind, deadline).await {
+                Ok(c) => c,
+                Err(e) => return fail(e),
+            };
+            let (addr, connect_ms) = (conn.addr, conn.connect_ms);
+            let sock = match conn.into_std() {
+                Ok(s) => s,
+                Err(e) => return fail(e),
+            };
+            let http = http.clone();
+            let deadline = deadline.into_std();
+            let result =
+                blocking(move || http::http_probe(sock, &url, &http, start, deadline)).await;
+            Connected::finish(addr, connect_ms, result)
+        }
+        ProbeKind::Udp(udp) => {
+            with_address(udp::udp_probe(preferred, udp, bind, start, deadline).await)
+        }
+        ProbeKind::Ping(ping) => {
+            let (ip, ping, bind, timeout_ms) = (
+                preferred.ip(),
+                ping.clone(),
+                bind.clone(),
+                target.timeout_ms,
+            );
+            with_address(blocking(move || icmp::icmp_ping(ip, &ping, &bind, timeout_ms)).await)
+        }
+        ProbeKind::Dns(dns) => {
+            let (dns, bind, deadline) = (dns.clone(), bind.clone(), deadline.into_std());
+            with_address(
+                blocking(move || dns::dns_probe(preferred, &dns, &bind, start, deadline)).await,
+            )
+        }
+    }
+}
 
-            // Run probes concurrently (up to 20 at a time)
-            let handles: Vec<_> = targets
-                .into_iter()
-                .map(|target| {
-                    let host = target.host.clone();
-                    let port = target.port;
-                    let id = target.id.clone();
-                    let probe_type = target.probe_type.clone();
-                    tokio::task::spawn_blocking(move || {
-                        let mut result = if probe_type == "ping" {
-                            icmp_ping(&host)
-                        } else {
-                            tcp_probe(&host, port, 2000)
-                        };
-                        result.id = id;
-                        result
-                    })
-                })
-                .collect();
-
-            for handle in handles {
-                if let Ok(result) = handle.await {
-                    let _ = app.emit("probe:update", &result);
+/// `address_policy: "all"`: probes every address concurrently. The target
+/// is up while any address answers, but a failing member is a warning and
+/// counts in `failed_members`. Incident tracking only sees the overall `ok`,
+/// so member failures never open an incident; alert on `failed_members`
+/// for that.
+async fn probe_each(
+    target: &Target,
+    addrs: Vec<SocketAddr>,
+    bind: &net::Bind,
+    start: Instant,
+    deadline: tokio::time::Instant,
+) -> ProbeResult {
+    let handles: Vec<_> = addrs
+        .into_iter()
+        .map(|addr| {
+            let (target, bind) = (target.clone(), bind.clone());
+            tokio::spawn(async move {
+                let result = probe_addrs(&target, &[addr], &bind, start, deadline).await;
+                ProbeResult {
+                    total_ms: elapsed_ms(start),
+                    ..result
                 }
-            }
+            })
+        })
+        .collect();
+    let mut members = Vec::with_capacity(handles.len());
+    for handle in handles {
+        members.push(handle.await.unwrap_or_else(task_failed));
+    }
+
+    let label = |m: &ProbeResult| m.address.map(|a| a.to_string()).unwrap_or_default();
+    let mut warnings = Vec::new();
+    for member in &members {
+        if let Some(error) = member.error.as_ref().filter(|_| !member.ok) {
+            warnings.push(format!("address_failed: {} {}", label(member), error));
+        } else if let Some(warning) = &member.warning {
+            warnings.push(format!("{}: {}", label(member), warning));
+        }
+    }
+
+    let up: Vec<f64> = members
+        .iter()
+        .filter(|m| m.ok)
+        .map(|m| m.latency_ms)
+        .collect();
+    let failed_members = Some((members.len() - up.len()) as u32);
+    if up.is_empty() {
+        return ProbeResult {
+            ok: false,
+            latency_ms: elapsed_ms(start),
+            error: members.iter().find_map(|m| m.error.clone()),
+            members,
+            failed_members,
+            ..Default::default()
+        };
+    }
+    ProbeResult {
+        ok: true,
+        latency_ms: up.iter().sum::<f64>() / up.len() as f64,
+        warning: (!warnings.is_empty()).then(|| warnings.join("; ")),
+        members,
+        failed_members,
+        ..Default::default()
+    }
+}
+
+async fn run_probe(target: Target) -> ProbeResult {
+    let start = Instant::now();
+    let timestamp = now_ms();
+    let deadline = tokio::time::Instant::from_std(start + Duration::from_millis(target.timeout_ms));
+
+    let (resolved, dns_ms) = match net::Bind::for_target(&target) {
+        Ok(bind) => {
+            let (addrs, dns_ms) = resolve_target(&target, &bind, start, deadline).await;
+            (addrs.map(|addrs| (addrs, bind)), dns_ms)
+        }
+        Err(e) => (Err(e), None),
+    };
+
+    let mut result = match resolved {
+        Ok((addrs, bind)) if target.address_policy == AddressPolicy::All && addrs.len() > 1 => {
+            probe_each(&target, addrs, &bind, start, deadline).await
+        }
+        Ok((addrs, bind)) => probe_addrs(&target, &addrs, &bind, start, deadline).await,
+        Err(e) => ProbeResult {
+            ok: false,
+            latency_ms: elapsed_ms(start),
+            error: Some(e),
+            ..Default::default()
+        },
+    };
+    result.id = target.id;
+    result.timestamp = timestamp;
+    result.dns_ms = dns_ms;
+    result.total_ms = elapsed_ms(start);
+    for member in &mut result.members {
+        member.id = result.id.clone();
+        member.timestamp = timestamp;
+        member.dns_ms = dns_ms;
+    }
+    result
+}
+
+/// Probes a target once, after the same checks `set_targets` applies.
+#[tauri::command]
+async fn probe_target(target: serde_json::Value) -> Result<ProbeResult, Vec<TargetError>> {
+    let mut targets = validate::parse_targets(vec![target])?;
+    Ok(run_probe(targets.remove(0)).await)
+}
+
+/// Restores the settings kept in the history database.
+fn load_settings(state: &AppState, history: &history::History) {
+    match history.setting::<Vec<alert::AlertRule>>(alert::RULES_KEY) {
+        Ok(Some(rules)) => {
+            let keep_ms = {
+                let mut alerts = state.alerts.lock().unwrap();
+                alerts.set_rules(rules);
+                alerts.keep_ms()
+            };
+            state.stats.lock().unwrap().set_keep_ms(keep_ms);
         }
-    });
+        Ok(None) => {}
+        Err(e) => eprintln!("alerts: {}", e),
+    }
+    match history.setting(notify::SETTINGS_KEY) {
+        Ok(Some(settings)) => state.notifier.lock().unwrap().set_settings(settings),
+        Ok(None) => {}
+        Err(e) => eprintln!("notifications: {}", e),
+    }
+    match webhook::load(history) {
+        Ok(Some(webhooks)) => *state.webhooks.write().unwrap() = webhooks,
+        Ok(None) => {}
+        Err(e) => eprintln!("webhooks: {}", e),
+    }
+    match history.setting(hook::SETTINGS_KEY) {
+        Ok(Some(hooks)) => *state.hooks.write().unwrap() = hooks,
+        Ok(None) => {}
+        Err(e) => eprintln!("hooks: {}", e),
+    }
+    match email::load(history) {
+        Ok(Some(settings)) => state.mailer.lock().unwrap().set_settings(settings),
+        Ok(None) => {}
+        Err(e) => eprintln!("email: {}", e),
+    }
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -250,11 +889,55 @@ pub fn run() {
         .plugin(tauri_plugin_fs::init())
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_os::init())
+        .plugin(tauri_plugin_notification::init())
         .manage(state)
-        .invoke_handler(tauri::generate_handler![get_targets, set_targets, probe_target])
+        .invoke_handler(tauri::generate_handler![
+            get_targets,
+            set_targets,
+            probe_target,
+            get_stats,
+            set_stats_window,
+            reset_stats,
+            clear_stats_view,
+            query_history,
+            query_incidents,
+            query_events,
+            get_retention,
+            set_retention,
+            get_alert_rules,
+            set_alert_rules,
+            get_alerts,
+            get_notification_settings,
+            set_notification_settings,
+            get_webhooks,
+            set_webhooks,
+            test_webhook,
+            get_hooks,
+            set_hooks,
+            test_hook,
+            get_email_settings,
+            set_email_settings,
+            test_email
+        ])
         .setup(move |app| {
             let handle = app.handle().clone();
-            start_probe_loop(handle, state_clone);
+            let dir = history::data_dir(&handle);
+            if let Some(dir) = &dir {
+                secret::init(dir);
+            }
+            match dir.map(|dir| history::History::open(&dir)) {
+                Some(Ok(history)) => {
+                    let _ = state_clone.history.set(history);
+                }
+                Some(Err(e)) => eprintln!("history: {}", e),
+                None => eprintln!("history: no data directory"),
+            }
+            if let Some(history) = state_clone.history.get() {
+                load_settings(&state_clone, history);
+            }
+            scheduler::start_probe_loop(handle.clone(), state_clone.clone());
+            stats::start_stats_loop(handle.clone(), state_clone.clone());
+            alert::start_alert_loop(handle, state_clone);
             Ok(())
         })
         .run(tauri::generate_context!())
@@ -0,0 +1,489 @@
+use crate::{dns, ErrorKind, ProbeError, Target};
+use serde::{Deserialize, Serialize};
+use socket2::SockRef;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use tokio::net::{TcpSocket, TcpStream};
+use tokio::task::JoinSet;
+use tokio::time::{sleep, timeout_at, Instant};
+
+/// RFC 8305 "Connection Attempt Delay": how long to wait for one attempt
+/// before starting the next in parallel.
+const ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Which of a host's resolved addresses to probe.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AddressPolicy {
+    /// Whatever the resolver lists first.
+    #[default]
+    First,
+    Ipv4,
+    Ipv6,
+    /// Race connects across families, IPv6 first (RFC 8305). Probes that
+    /// don't connect over TCP use the preferred address.
+    HappyEyeballs,
+    /// Probe every address separately; any failure degrades the target.
+    All,
+}
+
+/// The source address and/or interface a target's sockets are bound to.
+#[derive(Debug, Clone, Default)]
+pub struct Bind {
+    pub address: Option<IpAddr>,
+    /// Linux and Android only (SO_BINDTODEVICE).
+    pub interface: Option<String>,
+}
+
+impl Bind {
+    /// Reads a target's `bind_address` and `bind_interface`.
+    pub fn for_target(target: &Target) -> Result<Bind, ProbeError> {
+        let set = |field: &Option<String>| {
+            field
+                .as_deref()
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(String::from)
+        };
+        let address = match set(&target.bind_address) {
+            Some(raw) => Some(
+                raw.parse::<IpAddr>()
+                    .map_err(|_| ProbeError::invalid(format!("invalid_bind_address: {}", raw)))?,
+            ),
+            None => None,
+        };
+        let interface = set(&target.bind_interface);
+        if interface.is_some() && !cfg!(any(target_os = "linux", target_os = "android")) {
+            return Err(ProbeError::invalid(
+                "invalid_bind_interface: only supported on Linux",
+            ));
+        }
+        Ok(Bind { address, interface })
+    }
+
+    /// Whether `ip` is in the bound address's family.
+    fn allows(&self, ip: &IpAddr) -> bool {
+        self.address.is_none_or(|a| a.is_ipv4() == ip.is_ipv4())
+    }
+
+    /// Local address for a socket talking to `peer`: the bound address, or
+    /// the unspecified address of `peer`'s family.
+    pub fn local(&self, peer: SocketAddr) -> SocketAddr {
+        let ip = self.address.unwrap_or(match peer {
+            SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        });
+        SocketAddr::new(ip, 0)
+    }
+
+    /// Pins an already bound socket to the interface, if one is set.
+    pub fn device(&self, sock: SockRef<'_>) -> Result<(), ProbeError> {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        if let Some(interface) = &self.interface {
+            sock.bind_device(Some(interface.as_bytes()))
+                .map_err(ProbeError::bind)?;
+        }
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        let _ = sock;
+        Ok(())
+    }
+
+    /// Binds a fresh, unbound socket that is about to talk to `peer`. Without
+    /// a `bind_address` the kernel still picks the source address.
+    pub fn apply(&self, sock: SockRef<'_>, peer: SocketAddr) -> Result<(), ProbeError> {
+        if self.address.is_some() {
+            sock.bind(&self.local(peer).into())
+                .map_err(ProbeError::bind)?;
+        }
+        self.device(sock)
+    }
+}
+
+/// Interleaves address families starting with IPv6, per RFC 8305 §4.
+fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let (mut v6, mut v4): (Vec<_>, Vec<_>) = addrs.into_iter().partition(|a| a.is_ipv6());
+    v6.reverse();
+    v4.reverse();
+    let mut ordered = Vec::with_capacity(v6.len() + v4.len());
+    while !v6.is_empty() || !v4.is_empty() {
+        ordered.extend(v6.pop());
+        ordered.extend(v4.pop());
+    }
+    ordered
+}
+
+/// A successful resolution, kept for reuse until `expires`.
+struct CachedLookup {
+    ips: Vec<IpAddr>,
+    expires: Instant,
+}
+
+/// Keyed by resolver (`None` for the system one) and host.
+type LookupCache = Mutex<HashMap<(Option<SocketAddr>, String), CachedLookup>>;
+
+fn lookup_cache() -> &'static LookupCache {
+    static CACHE: OnceLock<LookupCache> = OnceLock::new();
+    CACHE.get_or_init(Default::default)
+}
+
+/// The addresses a host resolved to.
+pub struct Lookup {
+    pub ips: Vec<IpAddr>,
+    /// Served from the resolution cache; no query was made.
+    pub cached: bool,
+}
+
+/// Parses a target's `resolver`: an IP address, with an optional port
+/// (53 by default).
+pub fn parse_resolver(raw: &str) -> Result<SocketAddr, ProbeError> {
+    let raw = raw.trim();
+    raw.parse::<SocketAddr>()
+        .or_else(|_| raw.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 53)))
+        .map_err(|_| ProbeError::invalid(format!("invalid_resolver: {}", raw)))
+}
+
+async fn query(
+    host: &str,
+    server: Option<SocketAddr>,
+    bind: &Bind,
+    deadline: Instant,
+) -> Result<Vec<IpAddr>, ProbeError> {
+    let ips: Vec<IpAddr> = match server {
+        None => tokio::net::lookup_host((host, 0))
+            .await
+            .map_err(ProbeError::resolve)?
+            .map(|addr| addr.ip())
+            .collect(),
+        Some(server) => {
+            let (host, bind, deadline) = (host.to_string(), bind.clone(), deadline.into_std());
+            tokio::task::spawn_blocking(move || dns::lookup(server, &host, &bind, deadline))
+                .await
+                .map_err(|e| ProbeError::new(ErrorKind::Internal, e.to_string()))??
+        }
+    };
+    let mut unique = Vec::with_capacity(ips.len());
+    for ip in ips {
+        if !unique.contains(&ip) {
+            unique.push(ip);
+        }
+    }
+    Ok(unique)
+}
+
+/// Resolves `host` through the system resolver, or by asking the DNS server
+/// at `server` directly (over `bind`). With a non-zero `cache_ttl`, a
+/// successful answer is reused for that long instead of querying again.
+pub async fn lookup(
+    host: &str,
+    server: Option<SocketAddr>,
+    bind: &Bind,
+    cache_ttl: Duration,
+    deadline: Instant,
+) -> Result<Lookup, ProbeError> {
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok(Lookup {
+            ips: vec![ip],
+            cached: false,
+        });
+    }
+
+    let key = (server, host.to_ascii_lowercase());
+    if !cache_ttl.is_zero() {
+        if let Some(hit) = lookup_cache().lock().unwrap().get(&key) {
+            if hit.expires > Instant::now() {
+                return Ok(Lookup {
+                    ips: hit.ips.clone(),
+                    cached: true,
+                });
+            }
+        }
+    }
+
+    let ips = timeout_at(deadline, query(host, server, bind, deadline))
+        .await
+        .unwrap_or_else(|_| Err(ProbeError::new(ErrorKind::DnsTimeout, "dns_timeout")))?;
+
+    if !cache_ttl.is_zero() && !ips.is_empty() {
+        let now = Instant::now();
+        let mut cache = lookup_cache().lock().unwrap();
+        cache.retain(|_, entry| entry.expires > now);
+        cache.insert(
+            key,
+            CachedLookup {
+                ips: ips.clone(),
+                expires: now + cache_ttl,
+            },
+        );
+    }
+    Ok(Lookup { ips, cached: false })
+}
+
+/// Narrows a host's addresses down to those `bind` can reach, then according
+/// to `policy`. Never returns an empty list.
+pub fn narrow(
+    ips: Vec<IpAddr>,
+    port: u16,
+    policy: AddressPolicy,
+    bind: &Bind,
+) -> Result<Vec<SocketAddr>, ProbeError> {
+    let addrs = ips
+        .into_iter()
+        .filter(|ip| bind.allows(ip))
+        .map(|ip| SocketAddr::new(ip, port));
+    let addrs: Vec<SocketAddr> = match policy {
+        AddressPolicy::First => addrs.take(1).collect(),
+        AddressPolicy::Ipv4 => addrs.filter(|a| a.is_ipv4()).take(1).collect(),
+        AddressPolicy::Ipv6 => addrs.filter(|a| a.is_ipv6()).take(1).collect(),
+        AddressPolicy::HappyEyeballs => interleave(addrs.collect()),
+        AddressPolicy::All => addrs.collect(),
+    };
+    if addrs.is_empty() {
+        let detail = match (policy, bind.address) {
+            (AddressPolicy::Ipv4, _) | (_, Some(IpAddr::V4(_))) => "dns_failed: no IPv4 address",
+            (AddressPolicy::Ipv6, _) | (_, Some(IpAddr::V6(_))) => "dns_failed: no IPv6 address",
+            _ => "dns_failed",
+        };
+        return Err(ProbeError::new(ErrorKind::DnsFailure, detail));
+    }
+    Ok(addrs)
+}
+
+async fn connect_one(
+    addr: SocketAddr,
+    bind: &Bind,
+    deadline: Instant,
+) -> Result<TcpStream, ProbeError> {
+    let sock = match addr {
+        SocketAddr::V4(_) => TcpSocket::new_v4(),
+        SocketAddr::V6(_) => TcpSocket::new_v6(),
+    }
+    .map_err(ProbeError::connect)?;
+    bind.apply(SockRef::from(&sock), addr)?;
+    match timeout_at(deadline, sock.connect(addr)).await {
+        Ok(Ok(stream)) => Ok(stream),
+        Ok(Err(e)) => Err(ProbeError::connect(e)),
+        Err(_) => Err(ProbeError::new(ErrorKind::ConnectTimeout, "timeout")),
+    }
+}
+
+/// Connects to the first address, or races all of them Happy-Eyeballs style
+/// when given several: a new attempt starts every `ATTEMPT_DELAY` or as soon
+/// as the previous one fails, and the first to succeed wins.
+pub async fn connect(
+    addrs: &[SocketAddr],
+    bind: &Bind,
+    deadline: Instant,
+) -> Result<(TcpStream, SocketAddr), ProbeError> {
+    if let [addr] = addrs {
+        return connect_one(*addr, bind, deadline).await.map(|s| (s, *addr));
+    }
+
+    // Dropping the JoinSet aborts the attempts that lost the race.
+    let mut attempts = JoinSet::new();
+    let mut pending = addrs.iter().copied();
+    let mut last_error = None;
+    loop {
+        if let Some(addr) = pending.next() {
+            let bind = bind.clone();
+            attempts.spawn(async move { (connect_one(addr, &bind, deadline).await, addr) });
+        } else if attempts.is_empty() {
+            return Err(
+                last_error.unwrap_or_else(|| ProbeError::new(ErrorKind::DnsFailure, "dns_failed"))
+            );
+        }
+
+        let more = pending.len() > 0;
+        tokio::select! {
+            Some(joined) = attempts.join_next() => match joined {
+                Ok((Ok(stream), addr)) => return Ok((stream, addr)),
+                Ok((Err(e), _)) => last_error = Some(e),
+                Err(e) => last_error = Some(ProbeError::new(ErrorKind::Internal, e.to_string())),
+            },
+            _ = sleep(ATTEMPT_DELAY), if more => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use socket2::{Domain, Socket, Type};
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    fn ips(list: &[&str]) -> Vec<IpAddr> {
+        list.iter().map(|ip| ip.parse().unwrap()).collect()
+    }
+
+    const MIXED: &[&str] = &["192.0.2.1", "2001:db8::1", "192.0.2.2", "2001:db8::2"];
+
+    #[test]
+    fn interleave_starts_with_ipv6_and_alternates() {
+        let addrs = [
+            "192.0.2.1:80",
+            "192.0.2.2:80",
+            "[2001:db8::1]:80",
+            "192.0.2.3:80",
+            "[2001:db8::2]:80",
+        ];
+        let ordered = interleave(addrs.iter().map(|a| addr(a)).collect());
+        let expected = [
+            "[2001:db8::1]:80",
+            "192.0.2.1:80",
+            "[2001:db8::2]:80",
+            "192.0.2.2:80",
+            "192.0.2.3:80",
+        ];
+        assert_eq!(ordered, expected.map(addr));
+
+        let v4 = vec![addr("192.0.2.1:80"), addr("192.0.2.2:80")];
+        assert_eq!(interleave(v4.clone()), v4);
+    }
+
+    #[test]
+    fn narrow_applies_each_policy() {
+        let bind = Bind::default();
+        let narrowed = |policy| narrow(ips(MIXED), 443, policy, &bind).unwrap();
+        assert_eq!(narrowed(AddressPolicy::First), [addr("192.0.2.1:443")]);
+        assert_eq!(narrowed(AddressPolicy::Ipv4), [addr("192.0.2.1:443")]);
+        assert_eq!(narrowed(AddressPolicy::Ipv6), [addr("[2001:db8::1]:443")]);
+        assert_eq!(
+            narrowed(AddressPolicy::HappyEyeballs),
+            [
+                "[2001:db8::1]:443",
+                "192.0.2.1:443",
+                "[2001:db8::2]:443",
+                "192.0.2.2:443"
+            ]
+            .map(addr)
+        );
+        assert_eq!(
+            narrowed(AddressPolicy::All),
+            [
+                "192.0.2.1:443",
+                "[2001:db8::1]:443",
+                "192.0.2.2:443",
+                "[2001:db8::2]:443"
+            ]
+            .map(addr)
+        );
+    }
+
+    #[test]
+    fn narrow_keeps_the_bound_family() {
+        let bind = Bind {
+            address: Some("2001:db8::99".parse().unwrap()),
+            interface: None,
+        };
+        let narrowed = narrow(ips(MIXED), 443, AddressPolicy::All, &bind).unwrap();
+        assert_eq!(
+            narrowed,
+            ["[2001:db8::1]:443", "[2001:db8::2]:443"].map(addr)
+        );
+        let narrowed = narrow(ips(MIXED), 443, AddressPolicy::First, &bind).unwrap();
+        assert_eq!(narrowed, [addr("[2001:db8::1]:443")]);
+    }
+
+    #[test]
+    fn narrow_never_returns_empty() {
+        let policies = [
+            AddressPolicy::First,
+            AddressPolicy::Ipv4,
+            AddressPolicy::Ipv6,
+            AddressPolicy::HappyEyeballs,
+            AddressPolicy::All,
+        ];
+        for policy in policies {
+            let e = narrow(Vec::new(), 443, policy, &Bind::default()).unwrap_err();
+            assert_eq!(e.kind, ErrorKind::DnsFailure, "{:?}", policy);
+        }
+        let v4 = ips(&["192.0.2.1"]);
+        let e = narrow(v4.clone(), 443, AddressPolicy::Ipv6, &Bind::default()).unwrap_err();
+        assert_eq!(e.detail, "dns_failed: no IPv6 address");
+        let bind = Bind {
+            address: Some("2001:db8::99".parse().unwrap()),
+            interface: None,
+        };
+        let e = narrow(v4, 443, AddressPolicy::HappyEyeballs, &bind).unwrap_err();
+        assert_eq!(e.detail, "dns_failed: no IPv6 address");
+    }
+
+    /// A listener whose accept queue is full, so further connects hang as
+    /// they would to a host that drops SYNs. Keep the result alive.
+    fn blackhole() -> (Socket, Vec<std::net::TcpStream>, SocketAddr) {
+        let listener = Socket::new(Domain::IPV4, Type::STREAM, None).unwrap();
+        listener.bind(&addr("127.0.0.1:0").into()).unwrap();
+        listener.listen(0).unwrap();
+        let local = listener.local_addr().unwrap().as_socket().unwrap();
+        let mut queued = Vec::new();
+        for _ in 0..16 {
+            match std::net::TcpStream::connect_timeout(&local, Duration::from_millis(100)) {
+                Ok(stream) => queued.push(stream),
+                Err(_) => return (listener, queued, local),
+            }
+        }
+        panic!("accept queue never filled");
+    }
+
+    fn listener() -> (std::net::TcpListener, SocketAddr) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let local = listener.local_addr().unwrap();
+        (listener, local)
+    }
+
+    /// An address nothing listens on, so connects are refused.
+    fn closed() -> SocketAddr {
+        listener().1
+    }
+
+    #[tokio::test]
+    async fn connect_moves_on_from_an_unanswered_address() {
+        let (_hole, _queued, hole) = blackhole();
+        let (_listener, open) = listener();
+        let start = Instant::now();
+        let deadline = start + Duration::from_secs(5);
+        let (_, used) = connect(&[hole, open], &Bind::default(), deadline)
+            .await
+            .unwrap();
+        assert_eq!(used, open);
+        let elapsed = start.elapsed();
+        assert!(
+            elapsed >= ATTEMPT_DELAY - Duration::from_millis(10),
+            "{:?}",
+            elapsed
+        );
+        assert!(elapsed < Duration::from_secs(2), "{:?}", elapsed);
+    }
+
+    #[tokio::test]
+    async fn connect_moves_on_at_once_when_refused() {
+        let (_listener, open) = listener();
+        let start = Instant::now();
+        let deadline = start + Duration::from_secs(5);
+        let (_, used) = connect(&[closed(), open], &Bind::default(), deadline)
+            .await
+            .unwrap();
+        assert_eq!(used, open);
+        assert!(start.elapsed() < ATTEMPT_DELAY, "{:?}", start.elapsed());
+    }
+
+    #[tokio::test]
+    async fn connect_reports_the_last_failure() {
+        let (_hole, _queued, hole) = blackhole();
+        let deadline = Instant::now() + Duration::from_millis(600);
+        let e = connect(&[closed(), hole], &Bind::default(), deadline)
+            .await
+            .unwrap_err();
+        assert_eq!(e.kind, ErrorKind::ConnectTimeout);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let e = connect(&[closed(), closed()], &Bind::default(), deadline)
+            .await
+            .unwrap_err();
+        assert_eq!(e.kind, ErrorKind::ConnectRefused);
+    }
+}
@@ -0,0 +1,356 @@
+use crate::incident::Incident;
+use crate::stats::Summary;
+use crate::{email, hook, now_ms, webhook, AppState, ErrorKind, Target};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tauri::AppHandle;
+use tauri_plugin_notification::NotificationExt;
+
+/// Key of the notification settings in the `settings` table.
+pub const SETTINGS_KEY: &str = "notifications";
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_quiet_period_ms() -> u64 {
+    5 * 60 * 1000
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotificationSettings {
+    /// Desktop notifications for targets with `notify` set.
+    #[serde(default = "default_true")]
+    pub desktop: bool,
+    /// After a target's notification, further outages of that target within
+    /// this period (and their recoveries) don't raise another one.
+    #[serde(default = "default_quiet_period_ms")]
+    pub quiet_period_ms: u64,
+}
+
+impl Default for NotificationSettings {
+    fn default() -> NotificationSettings {
+        NotificationSettings {
+            desktop: true,
+            quiet_period_ms: default_quiet_period_ms(),
+        }
+    }
+}
+
+/// An announced incident change: what every notifier reacts to.
+#[derive(Debug, Clone)]
+pub enum Notice {
+    Down(Incident),
+    Up(Incident),
+}
+
+impl Notice {
+    pub fn incident(&self) -> &Incident {
+        match self {
+            Notice::Down(incident) | Notice::Up(incident) => incident,
+        }
+    }
+
+    /// "incident_opened" or "incident_closed", as in the event log.
+    pub fn event(&self) -> &'static str {
+        match self {
+            Notice::Down(_) => "incident_opened",
+            Notice::Up(_) => "incident_closed",
+        }
+    }
+}
+
+/// Everything a notifier may report about a notice. Webhook template
+/// variables are these field names.
+#[derive(Debug, Clone, Serialize)]
+pub struct Details {
+    pub event: &'static str,
+    pub target_id: String,
+    /// The target's name, or its id when unnamed.
+    pub target_name: String,
+    pub host: String,
+    pub port: u16,
+    pub probe_type: String,
+    pub error_kind: Option<ErrorKind>,
+    pub error: Option<String>,
+    pub started: u64,
+    pub ended: Option<u64>,
+    pub duration_ms: Option<u64>,
+    /// `duration_ms` for people, e.g. "3m 10s".
+    pub duration: Option<String>,
+    /// Over the stats window.
+    pub success_rate: Option<f64>,
+    pub avg_ms: Option<f64>,
+    pub p90_ms: Option<f64>,
+}
+
+impl Details {
+    pub fn new(target: &Target, notice: &Notice, stats: &Summary) -> Details {
+        let incident = notice.incident();
+        let round = |v: Option<f64>| v.map(|v| (v * 10.0).round() / 10.0);
+        Details {
+            event: notice.event(),
+            target_id: target.id.clone(),
+            target_name: if target.name.is_empty() {
+                target.id.clone()
+            } else {
+                target.name.clone()
+            },
+            host: target.host.clone(),
+            port: target.port,
+            probe_type: serde_json::to_value(&target.kind)
+                .ok()
+                .and_then(|v| v["probe_type"].as_str().map(String::from))
+                .unwrap_or_default(),
+            error_kind: incident.first_error.as_ref().map(|e| e.kind),
+            error: incident.first_error.as_ref().map(|e| e.detail.clone()),
+            started: incident.started,
+            ended: incident.ended,
+            duration_ms: incident.duration_ms,
+            duration: incident.duration_ms.map(format_duration),
+            success_rate: stats.success_rate.map(|r| (r * 1000.0).round() / 1000.0),
+            avg_ms: round(stats.avg_ms),
+            p90_ms: round(stats.p90_ms),
+        }
+    }
+
+    /// Made-up details for trying a notifier out.
+    pub fn example() -> Details {
+        let now = now_ms();
+        Details {
+            event: "test",
+            target_id: "example".into(),
+            target_name: "Example".into(),
+            host: "example.com".into(),
+            port: 443,
+            probe_type: "tcp".into(),
+            error_kind: Some(ErrorKind::ConnectTimeout),
+            error: Some("timeout".into()),
+            started: now - 190_000,
+            ended: Some(now),
+            duration_ms: Some(190_000),
+            duration: Some(format_duration(190_000)),
+            success_rate: Some(0.95),
+            avg_ms: Some(23.4),
+            p90_ms: Some(41.0),
+        }
+    }
+
+    pub fn to_map(&self) -> Map<String, Value> {
+        match serde_json::to_value(self) {
+            Ok(Value::Object(map)) => map,
+            _ => Map::new(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Sent {
+    last: Option<u64>,
+    /// Whether the open incident's outage was notified, so its recovery is.
+    down: bool,
+}
+
+/// Decides which notices become desktop notifications.
+#[derive(Default)]
+pub struct Notifier {
+    settings: NotificationSettings,
+    sent: HashMap<String, Sent>,
+}
+
+impl Notifier {
+    pub fn settings(&self) -> &NotificationSettings {
+        &self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: NotificationSettings) {
+        self.settings = settings;
+    }
+
+    /// Drops state for targets that no longer exist.
+    pub fn retain(&mut self, targets: &[Target]) {
+        self.sent
+            .retain(|id, _| targets.iter().any(|t| &t.id == id));
+    }
+
+    /// Whether `notice` should raise a desktop notification. Recoveries
+    /// are notified exactly when their outage was.
+    fn desktop_due(&mut self, target: &Target, notice: &Notice, now: u64) -> bool {
+        if !self.settings.desktop || !target.notify {
+            return false;
+        }
+        let quiet_period_ms = self.settings.quiet_period_ms;
+        let sent = self.sent.entry(target.id.clone()).or_default();
+        let due = match notice {
+            Notice::Down(_) => {
+                sent.down = sent
+                    .last
+                    .is_none_or(|last| now.saturating_sub(last) >= quiet_period_ms);
+                sent.down
+            }
+            Notice::Up(_) => std::mem::take(&mut sent.down),
+        };
+        if due {
+            sent.last = Some(now);
+        }
+        due
+    }
+}
+
+/// "1h 5m", "3m 10s", "42s".
+pub fn format_duration(ms: u64) -> String {
+    let secs = ms / 1000;
+    match (secs / 3600, secs % 3600 / 60, secs % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m {}s", m, s),
+        (h, m, _) => format!("{}h {}m", h, m),
+    }
+}
+
+fn show_desktop(app: &AppHandle, notice: &Notice, details: &Details) {
+    let (title, body) = match notice {
+        Notice::Down(_) => (
+            format!("{} is down", details.target_name),
+            match &details.error {
+                Some(error) => format!("{}: {}", details.host, error),
+                None => details.host.clone(),
+            },
+        ),
+        Notice::Up(_) => (
+            format!("{} is back up", details.target_name),
+            format!(
+                "{} was down for {}",
+                details.host,
+                details.duration.as_deref().unwrap_or("0s")
+            ),
+        ),
+    };
+    if let Err(e) = app.notification().builder().title(title).body(body).show() {
+        eprintln!("notifications: {}", e);
+    }
+}
+
+/// Hands an announced incident change to the notifiers. Runs in the
+/// backend, so notifications work with the window hidden.
+pub fn dispatch(app: &AppHandle, state: &Arc<AppState>, target: &Target, notice: Notice) {
+    let summary = {
+        let stats = state.stats.lock().unwrap();
+        stats.summary(&target.id, stats.window_ms())
+    };
+    let details = Details::new(target, &notice, &summary);
+
+    let desktop = state
+        .notifier
+        .lock()
+        .unwrap()
+        .desktop_due(target, &notice, now_ms());
+    if desktop {
+        show_desktop(app, &notice, &details);
+    }
+    webhook::dispatch(state, target, &details);
+    hook::dispatch(state, target, &details);
+    email::dispatch(state, target, &details);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const QUIET: u64 = 60_000;
+
+    fn target(notify: bool) -> Target {
+        serde_json::from_value(json!({
+            "id": "t", "name": "T", "host": "example.com", "port": 443, "notify": notify,
+        }))
+        .unwrap()
+    }
+
+    fn notifier(desktop: bool) -> Notifier {
+        let mut notifier = Notifier::default();
+        notifier.set_settings(NotificationSettings {
+            desktop,
+            quiet_period_ms: QUIET,
+        });
+        notifier
+    }
+
+    fn incident(started: u64) -> Incident {
+        Incident {
+            target_id: "t".into(),
+            started,
+            ended: None,
+            duration_ms: None,
+            first_error: None,
+        }
+    }
+
+    /// Whether an outage at `down` and its recovery at `up` are notified.
+    fn outage(notifier: &mut Notifier, target: &Target, down: u64, up: u64) -> (bool, bool) {
+        (
+            notifier.desktop_due(target, &Notice::Down(incident(down)), down),
+            notifier.desktop_due(target, &Notice::Up(incident(down)), up),
+        )
+    }
+
+    #[test]
+    fn outage_in_quiet_period_is_suppressed_with_its_recovery() {
+        let (mut notifier, target) = (notifier(true), target(true));
+        assert_eq!(outage(&mut notifier, &target, 1_000, 2_000), (true, true));
+        // The recovery restarts the quiet period.
+        assert_eq!(
+            outage(
+                &mut notifier,
+                &target,
+                2_000 + QUIET - 1,
+                2_000 + QUIET + 5_000
+            ),
+            (false, false)
+        );
+        assert_eq!(
+            outage(&mut notifier, &target, 2_000 + QUIET, 3_000 + QUIET),
+            (true, true)
+        );
+    }
+
+    #[test]
+    fn quiet_period_is_per_target() {
+        let mut notifier = notifier(true);
+        let (a, mut b) = (target(true), target(true));
+        b.id = "u".into();
+        assert_eq!(outage(&mut notifier, &a, 1_000, 2_000), (true, true));
+        assert_eq!(outage(&mut notifier, &b, 3_000, 4_000), (true, true));
+    }
+
+    #[test]
+    fn desktop_or_notify_off_suppresses_everything() {
+        for (desktop, notify) in [(false, true), (true, false), (false, false)] {
+            let (mut notifier, target) = (notifier(desktop), target(notify));
+            assert_eq!(outage(&mut notifier, &target, 1_000, 2_000), (false, false));
+            assert_eq!(
+                outage(&mut notifier, &target, 10 * QUIET, 11 * QUIET),
+                (false, false)
+            );
+        }
+    }
+
+    #[test]
+    fn recovery_of_an_outage_notified_before_desktop_was_turned_off_is_dropped() {
+        let (mut notifier, target) = (notifier(true), target(true));
+        assert!(notifier.desktop_due(&target, &Notice::Down(incident(1_000)), 1_000));
+        notifier.set_settings(NotificationSettings {
+            desktop: false,
+            quiet_period_ms: QUIET,
+        });
+        assert!(!notifier.desktop_due(&target, &Notice::Up(incident(1_000)), 2_000));
+    }
+
+    #[test]
+    fn format_duration_picks_two_units() {
+        assert_eq!(format_duration(42_999), "42s");
+        assert_eq!(format_duration(190_000), "3m 10s");
+        assert_eq!(format_duration(3_900_000), "1h 5m");
+    }
+}
@@ -0,0 +1,712 @@
+use crate::history;
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+pub const MINUTE_MS: u64 = 60 * 1000;
+pub const HOUR_MS: u64 = 60 * MINUTE_MS;
+pub const DAY_MS: u64 = 24 * HOUR_MS;
+/// How long after a minute ends before it's rolled up, so results from
+/// probes still in flight (or queued for writing) make it into their bucket.
+const GRACE_MS: u64 = 2 * MINUTE_MS;
+/// Raw results are only served for ranges up to this long.
+const MAX_RAW_SPAN_MS: u64 = 6 * HOUR_MS;
+/// Most buckets a query should return before a coarser resolution is used.
+const MAX_BUCKETS: u64 = 1500;
+
+/// Sketch bucket growth factor: quantiles are within about ±2.5%.
+const GAMMA: f64 = 1.05;
+/// Latencies below this (ms) share the lowest sketch bucket.
+const MIN_SKETCH_MS: f64 = 0.001;
+
+/// Granularity of stored history.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Resolution {
+    #[serde(rename = "raw")]
+    Raw,
+    #[serde(rename = "1m")]
+    Minute,
+    #[serde(rename = "1h")]
+    Hour,
+    #[serde(rename = "1d")]
+    Day,
+}
+
+impl Resolution {
+    const ALL: [Resolution; 4] = [
+        Resolution::Raw,
+        Resolution::Minute,
+        Resolution::Hour,
+        Resolution::Day,
+    ];
+
+    pub fn bucket_ms(self) -> u64 {
+        match self {
+            Resolution::Raw => 0,
+            Resolution::Minute => MINUTE_MS,
+            Resolution::Hour => HOUR_MS,
+            Resolution::Day => DAY_MS,
+        }
+    }
+
+    pub fn key(self) -> &'static str {
+        match self {
+            Resolution::Raw => "raw",
+            Resolution::Minute => "1m",
+            Resolution::Hour => "1h",
+            Resolution::Day => "1d",
+        }
+    }
+}
+
+/// Key of the retention policy in the `settings` table.
+pub const RETENTION_KEY: &str = "retention";
+
+/// Days to keep each resolution; 0 keeps it forever.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Retention {
+    pub raw: u32,
+    #[serde(rename = "1m")]
+    pub minute: u32,
+    #[serde(rename = "1h")]
+    pub hour: u32,
+    #[serde(rename = "1d")]
+    pub day: u32,
+}
+
+impl Default for Retention {
+    fn default() -> Retention {
+        Retention {
+            raw: 7,
+            minute: 30,
+            hour: 365,
+            day: 0,
+        }
+    }
+}
+
+impl Retention {
+    fn days(&self, resolution: Resolution) -> u32 {
+        match resolution {
+            Resolution::Raw => self.raw,
+            Resolution::Minute => self.minute,
+            Resolution::Hour => self.hour,
+            Resolution::Day => self.day,
+        }
+    }
+
+    /// Oldest timestamp still kept at `resolution`, if any are dropped.
+    fn cutoff(&self, resolution: Resolution, now: u64) -> Option<u64> {
+        match self.days(resolution) {
+            0 => None,
+            days => Some(now.saturating_sub(days as u64 * DAY_MS)),
+        }
+    }
+}
+
+/// Log-bucketed latency histogram. Unlike stored percentiles, sketches can
+/// be merged, so hourly and daily quantiles come from the full distribution.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Sketch(BTreeMap<i32, u64>);
+
+impl Sketch {
+    fn add(&mut self, latency_ms: f64) {
+        let index = (latency_ms.max(MIN_SKETCH_MS).ln() / GAMMA.ln()).ceil() as i32;
+        *self.0.entry(index).or_default() += 1;
+    }
+
+    fn merge(&mut self, other: &Sketch) {
+        for (index, count) in &other.0 {
+            *self.0.entry(*index).or_default() += count;
+        }
+    }
+
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        let total: u64 = self.0.values().sum();
+        let rank = (q * total.checked_sub(1)? as f64).round() as u64;
+        let mut seen = 0;
+        for (index, count) in &self.0 {
+            seen += count;
+            if seen > rank {
+                // Midpoint of (GAMMA^(i-1), GAMMA^i].
+                return Some(2.0 * GAMMA.powi(*index) / (GAMMA + 1.0));
+            }
+        }
+        None
+    }
+}
+
+/// Aggregate of one target over one bucket. Latency figures cover
+/// successful results only.
+#[derive(Debug, Clone)]
+struct Aggregate {
+    count: u64,
+    failures: u64,
+    min_ms: f64,
+    max_ms: f64,
+    sum_ms: f64,
+    sketch: Sketch,
+}
+
+impl Default for Aggregate {
+    fn default() -> Aggregate {
+        Aggregate {
+            count: 0,
+            failures: 0,
+            min_ms: f64::INFINITY,
+            max_ms: 0.0,
+            sum_ms: 0.0,
+            sketch: Sketch::default(),
+        }
+    }
+}
+
+impl Aggregate {
+    fn add(&mut self, ok: bool, latency_ms: f64) {
+        self.count += 1;
+        if !ok {
+            self.failures += 1;
+            return;
+        }
+        self.min_ms = self.min_ms.min(latency_ms);
+        self.max_ms = self.max_ms.max(latency_ms);
+        self.sum_ms += latency_ms;
+        self.sketch.add(latency_ms);
+    }
+
+    fn merge(&mut self, other: &Aggregate) {
+        self.count += other.count;
+        self.failures += other.failures;
+        self.min_ms = self.min_ms.min(other.min_ms);
+        self.max_ms = self.max_ms.max(other.max_ms);
+        self.sum_ms += other.sum_ms;
+        self.sketch.merge(&other.sketch);
+    }
+}
+
+/// One rolled-up bucket as returned by `query_history`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Bucket {
+    /// Start of the bucket (Unix ms).
+    pub timestamp: u64,
+    pub count: u64,
+    pub failures: u64,
+    pub min_ms: Option<f64>,
+    pub avg_ms: Option<f64>,
+    pub max_ms: Option<f64>,
+    pub p50_ms: Option<f64>,
+    pub p90_ms: Option<f64>,
+    pub p99_ms: Option<f64>,
+}
+
+impl Bucket {
+    fn new(timestamp: u64, agg: &Aggregate) -> Bucket {
+        let ok = agg.count - agg.failures;
+        let latency = |v: f64| (ok > 0).then_some(v);
+        Bucket {
+            timestamp,
+            count: agg.count,
+            failures: agg.failures,
+            min_ms: latency(agg.min_ms),
+            avg_ms: latency(agg.sum_ms / ok.max(1) as f64),
+            max_ms: latency(agg.max_ms),
+            p50_ms: agg.sketch.quantile(0.50),
+            p90_ms: agg.sketch.quantile(0.90),
+            p99_ms: agg.sketch.quantile(0.99),
+        }
+    }
+}
+
+pub fn create_tables(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE rollups (
+             resolution TEXT NOT NULL,
+             target_id TEXT NOT NULL,
+             bucket INTEGER NOT NULL,
+             count INTEGER NOT NULL,
+             failures INTEGER NOT NULL,
+             min_ms REAL,
+             max_ms REAL,
+             sum_ms REAL NOT NULL,
+             sketch TEXT NOT NULL,
+             PRIMARY KEY (resolution, target_id, bucket)
+         );
+         CREATE TABLE rollup_state (
+             resolution TEXT PRIMARY KEY,
+             done_until INTEGER NOT NULL
+         );
+         CREATE TABLE settings (
+             key TEXT PRIMARY KEY,
+             value TEXT NOT NULL
+         );",
+    )
+}
+
+pub fn load_retention(conn: &Connection) -> rusqlite::Result<Retention> {
+    Ok(history::read_setting(conn, RETENTION_KEY)?.unwrap_or_default())
+}
+
+/// The finest resolution that still holds `from` and returns a manageable
+/// number of points for the range.
+pub fn pick_resolution(from: u64, to: u64, now: u64, retention: &Retention) -> Resolution {
+    let span = to.saturating_sub(from);
+    Resolution::ALL
+        .into_iter()
+        .find(|&resolution| {
+            let kept = retention
+                .cutoff(resolution, now)
+                .is_none_or(|cutoff| from >= cutoff);
+            let points_ok = match resolution {
+                Resolution::Raw => span <= MAX_RAW_SPAN_MS,
+                _ => span / resolution.bucket_ms() <= MAX_BUCKETS,
+            };
+            kept && points_ok
+        })
+        .unwrap_or(Resolution::Day)
+}
+
+pub fn query_buckets(
+    conn: &Connection,
+    resolution: Resolution,
+    target_id: &str,
+    from: u64,
+    to: u64,
+) -> rusqlite::Result<Vec<Bucket>> {
+    let start = from - from % resolution.bucket_ms();
+    let mut stmt = conn.prepare(
+        "SELECT bucket, count, failures, min_ms, max_ms, sum_ms, sketch FROM rollups
+         WHERE resolution = ?1 AND target_id = ?2 AND bucket BETWEEN ?3 AND ?4
+         ORDER BY bucket",
+    )?;
+    let rows = stmt.query_map(
+        params![resolution.key(), target_id, start as i64, to as i64],
+        |row| {
+            let (bucket, agg) = read_aggregate(row, 0)?;
+            Ok(Bucket::new(bucket, &agg))
+        },
+    )?;
+    rows.collect()
+}
+
+/// Reads `bucket, count, failures, min_ms, max_ms, sum_ms, sketch` starting
+/// at column `first`.
+fn read_aggregate(row: &rusqlite::Row, first: usize) -> rusqlite::Result<(u64, Aggregate)> {
+    let sketch: String = row.get(first + 6)?;
+    Ok((
+        row.get::<_, i64>(first)? as u64,
+        Aggregate {
+            count: row.get::<_, i64>(first + 1)? as u64,
+            failures: row.get::<_, i64>(first + 2)? as u64,
+            min_ms: row
+                .get::<_, Option<f64>>(first + 3)?
+                .unwrap_or(f64::INFINITY),
+            max_ms: row.get::<_, Option<f64>>(first + 4)?.unwrap_or(0.0),
+            sum_ms: row.get(first + 5)?,
+            sketch: serde_json::from_str(&sketch).unwrap_or_default(),
+        },
+    ))
+}
+
+fn done_until(conn: &Connection, resolution: Resolution) -> rusqlite::Result<Option<u64>> {
+    conn.query_row(
+        "SELECT done_until FROM rollup_state WHERE resolution = ?1",
+        params![resolution.key()],
+        |row| row.get::<_, i64>(0).map(|v| v as u64),
+    )
+    .optional()
+}
+
+/// Where rolling up into `resolution` starts on a fresh database: the
+/// oldest data in the resolution below it.
+fn first_pending(conn: &Connection, resolution: Resolution) -> rusqlite::Result<Option<u64>> {
+    let oldest: Option<i64> = match resolution {
+        Resolution::Minute => {
+            conn.query_row("SELECT MIN(timestamp) FROM results", [], |row| row.get(0))?
+        }
+        _ => conn.query_row(
+            "SELECT MIN(bucket) FROM rollups WHERE resolution = ?1",
+            params![finer(resolution).key()],
+            |row| row.get(0),
+        )?,
+    };
+    Ok(oldest.map(|t| t as u64 - t as u64 % resolution.bucket_ms()))
+}
+
+fn finer(resolution: Resolution) -> Resolution {
+    match resolution {
+        Resolution::Raw | Resolution::Minute => Resolution::Raw,
+        Resolution::Hour => Resolution::Minute,
+        Resolution::Day => Resolution::Hour,
+    }
+}
+
+/// Aggregates everything in `[from, until)` from the resolution below into
+/// `resolution`'s buckets.
+fn aggregate(
+    conn: &Connection,
+    resolution: Resolution,
+    from: u64,
+    until: u64,
+) -> rusqlite::Result<HashMap<(String, u64), Aggregate>> {
+    let size = resolution.bucket_ms();
+    let mut buckets: HashMap<(String, u64), Aggregate> = HashMap::new();
+    if resolution == Resolution::Minute {
+        let mut stmt = conn.prepare_cached(
+            "SELECT target_id, timestamp, ok, latency_ms FROM results
+             WHERE timestamp >= ?1 AND timestamp < ?2",
+        )?;
+        let mut rows = stmt.query(params![from as i64, until as i64])?;
+        while let Some(row) = rows.next()? {
+            let timestamp = row.get::<_, i64>(1)? as u64;
+            buckets
+                .entry((row.get(0)?, timestamp - timestamp % size))
+                .or_default()
+                .add(row.get(2)?, row.get(3)?);
+        }
+    } else {
+        let mut stmt = conn.prepare_cached(
+            "SELECT target_id, bucket, count, failures, min_ms, max_ms, sum_ms, sketch
+             FROM rollups WHERE resolution = ?1 AND bucket >= ?2 AND bucket < ?3",
+        )?;
+        let mut rows = stmt.query(params![finer(resolution).key(), from as i64, until as i64])?;
+        while let Some(row) = rows.next()? {
+            let (bucket, agg) = read_aggregate(row, 1)?;
+            buckets
+                .entry((row.get(0)?, bucket - bucket % size))
+                .or_default()
+                .merge(&agg);
+        }
+    }
+    Ok(buckets)
+}
+
+/// Rolls up every complete bucket of `resolution` not done yet. `closed` is
+/// the end of the data that is final in the resolution below.
+fn roll_up(conn: &mut Connection, resolution: Resolution, closed: u64) -> rusqlite::Result<u64> {
+    let size = resolution.bucket_ms();
+    let closed = closed - closed % size;
+    let mut from = match done_until(conn, resolution)? {
+        Some(done) => done,
+        None => first_pending(conn, resolution)?.unwrap_or(closed),
+    };
+    // In steps, so a large backlog doesn't have to fit in memory at once.
+    let step = size * 60;
+    while from < closed {
+        let until = (from + step).min(closed);
+        // Immediate, so the busy timeout applies: a deferred transaction that
+        // reads first fails outright if the writer commits in between.
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let buckets = aggregate(&tx, resolution, from, until)?;
+        {
+            let mut insert = tx.prepare_cached(
+                "INSERT OR REPLACE INTO rollups
+                 (resolution, target_id, bucket, count, failures, min_ms, max_ms, sum_ms, sketch)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            )?;
+            for ((target_id, bucket), agg) in &buckets {
+                let ok = agg.count > agg.failures;
+                insert.execute(params![
+                    resolution.key(),
+                    target_id,
+                    *bucket as i64,
+                    agg.count as i64,
+                    agg.failures as i64,
+                    ok.then_some(agg.min_ms),
+                    ok.then_some(agg.max_ms),
+                    agg.sum_ms,
+                    serde_json::to_string(&agg.sketch).unwrap_or_default(),
+                ])?;
+            }
+        }
+        tx.execute(
+            "INSERT OR REPLACE INTO rollup_state (resolution, done_until) VALUES (?1, ?2)",
+            params![resolution.key(), until as i64],
+        )?;
+        tx.commit()?;
+        from = until;
+    }
+    Ok(from.max(closed))
+}
+
+/// Deletes data past its retention, but never anything the next resolution
+/// up hasn't absorbed yet.
+fn enforce_retention(
+    conn: &Connection,
+    retention: &Retention,
+    now: u64,
+    done: &HashMap<Resolution, u64>,
+) -> rusqlite::Result<()> {
+    for resolution in Resolution::ALL {
+        let Some(cutoff) = retention.cutoff(resolution, now) else {
+            continue;
+        };
+        let coarser = match resolution {
+            Resolution::Raw => Some(Resolution::Minute),
+            Resolution::Minute => Some(Resolution::Hour),
+            Resolution::Hour => Some(Resolution::Day),
+            Resolution::Day => None,
+        };
+        let cutoff = match coarser {
+            Some(coarser) => cutoff.min(done.get(&coarser).copied().unwrap_or(0)),
+            None => cutoff,
+        };
+        match resolution {
+            Resolution::Raw => conn.execute(
+                "DELETE FROM results WHERE timestamp < ?1",
+                params![cutoff as i64],
+            )?,
+            _ => conn.execute(
+                "DELETE FROM rollups WHERE resolution = ?1 AND bucket < ?2",
+                params![resolution.key(), cutoff as i64],
+            )?,
+        };
+    }
+    Ok(())
+}
+
+/// One pass of the background job: roll up into minutes, hours and days,
+/// then apply the retention policy.
+pub fn run(conn: &mut Connection, now: u64) -> rusqlite::Result<()> {
+    let mut done = HashMap::new();
+    let mut closed = now.saturating_sub(GRACE_MS);
+    for resolution in [Resolution::Minute, Resolution::Hour, Resolution::Day] {
+        closed = roll_up(conn, resolution, closed)?;
+        done.insert(resolution, closed);
+    }
+    let retention = load_retention(conn)?;
+    enforce_retention(conn, &retention, now, &done)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 400 * DAY_MS;
+
+    /// `results` as far as rolling up reads it, plus the rollup tables.
+    fn db() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE results (
+                 target_id TEXT NOT NULL,
+                 timestamp INTEGER NOT NULL,
+                 ok INTEGER NOT NULL,
+                 latency_ms REAL NOT NULL
+             );",
+        )
+        .unwrap();
+        create_tables(&conn).unwrap();
+        conn
+    }
+
+    fn add_result(conn: &Connection, target_id: &str, timestamp: u64, ok: bool, latency_ms: f64) {
+        conn.execute(
+            "INSERT INTO results (target_id, timestamp, ok, latency_ms) VALUES (?1, ?2, ?3, ?4)",
+            params![target_id, timestamp as i64, ok, latency_ms],
+        )
+        .unwrap();
+    }
+
+    fn add_rollup(conn: &Connection, resolution: Resolution, bucket: u64) {
+        conn.execute(
+            "INSERT INTO rollups (resolution, target_id, bucket, count, failures, sum_ms, sketch)
+             VALUES (?1, 'a', ?2, 1, 1, 0, '{}')",
+            params![resolution.key(), bucket as i64],
+        )
+        .unwrap();
+    }
+
+    fn count(conn: &Connection, sql: &str) -> i64 {
+        conn.query_row(sql, [], |row| row.get(0)).unwrap()
+    }
+
+    fn sketch(values: impl IntoIterator<Item = f64>) -> Sketch {
+        let mut sketch = Sketch::default();
+        for value in values {
+            sketch.add(value);
+        }
+        sketch
+    }
+
+    #[test]
+    fn sketch_quantiles_are_within_the_error_bound() {
+        assert_eq!(Sketch::default().quantile(0.5), None);
+        let values: Vec<f64> = (1..=5000).map(|i| i as f64 * 0.37).collect();
+        let sketch = sketch(values.iter().copied());
+        let bound = (GAMMA - 1.0) / (GAMMA + 1.0);
+        for q in [0.0, 0.25, 0.5, 0.9, 0.99, 1.0] {
+            let exact = values[(q * (values.len() - 1) as f64).round() as usize];
+            let estimate = sketch.quantile(q).unwrap();
+            assert!(
+                (estimate - exact).abs() <= exact * bound + 1e-9,
+                "q{}: {} vs {}",
+                q,
+                estimate,
+                exact
+            );
+        }
+    }
+
+    #[test]
+    fn merged_sketch_matches_one_built_from_all_values() {
+        let mut merged = sketch([0.0, 1.0, 2.5, 40.0]);
+        merged.merge(&sketch([2.5, 300.0, 7.0]));
+        let all = sketch([0.0, 1.0, 2.5, 40.0, 2.5, 300.0, 7.0]);
+        assert_eq!(merged.0, all.0);
+        assert_eq!(merged.0.values().sum::<u64>(), 7);
+    }
+
+    #[test]
+    fn rolls_up_closed_buckets_once() {
+        let mut conn = db();
+        let start = NOW - DAY_MS;
+        add_result(&conn, "a", start, true, 10.0);
+        add_result(&conn, "a", start + 30_000, true, 30.0);
+        add_result(&conn, "a", start + 59_999, false, 0.0);
+        add_result(&conn, "a", start + MINUTE_MS, true, 20.0);
+        add_result(&conn, "b", start + MINUTE_MS, true, 5.0);
+        // Not closed yet.
+        add_result(&conn, "a", start + 2 * MINUTE_MS, true, 1.0);
+
+        let done = roll_up(
+            &mut conn,
+            Resolution::Minute,
+            start + 2 * MINUTE_MS + 30_000,
+        )
+        .unwrap();
+        assert_eq!(done, start + 2 * MINUTE_MS);
+        let buckets = query_buckets(&conn, Resolution::Minute, "a", start, NOW).unwrap();
+        assert_eq!(buckets.len(), 2);
+        let first = &buckets[0];
+        assert_eq!(
+            (first.timestamp, first.count, first.failures),
+            (start, 3, 1)
+        );
+        assert_eq!(
+            (first.min_ms, first.avg_ms, first.max_ms),
+            (Some(10.0), Some(20.0), Some(30.0))
+        );
+        assert_eq!(
+            (buckets[1].timestamp, buckets[1].count),
+            (start + MINUTE_MS, 1)
+        );
+
+        // Done buckets aren't counted again.
+        roll_up(&mut conn, Resolution::Minute, start + 3 * MINUTE_MS).unwrap();
+        let buckets = query_buckets(&conn, Resolution::Minute, "a", start, NOW).unwrap();
+        assert_eq!(
+            buckets.iter().map(|b| b.count).collect::<Vec<_>>(),
+            [3, 1, 1]
+        );
+
+        roll_up(&mut conn, Resolution::Hour, start + HOUR_MS).unwrap();
+        let hours = query_buckets(&conn, Resolution::Hour, "a", start, NOW).unwrap();
+        assert_eq!(hours.len(), 1);
+        assert_eq!((hours[0].count, hours[0].failures), (5, 1));
+        assert_eq!((hours[0].min_ms, hours[0].max_ms), (Some(1.0), Some(30.0)));
+    }
+
+    #[test]
+    fn all_failures_leave_latency_empty() {
+        let mut conn = db();
+        let start = NOW - DAY_MS;
+        add_result(&conn, "a", start, false, 0.0);
+        roll_up(&mut conn, Resolution::Minute, start + MINUTE_MS).unwrap();
+        let bucket = &query_buckets(&conn, Resolution::Minute, "a", start, NOW).unwrap()[0];
+        assert_eq!((bucket.count, bucket.failures), (1, 1));
+        assert_eq!(
+            (bucket.min_ms, bucket.avg_ms, bucket.p50_ms),
+            (None, None, None)
+        );
+    }
+
+    #[test]
+    fn picks_the_finest_resolution_that_fits() {
+        let retention = Retention::default();
+        let pick = |from: u64, to: u64| pick_resolution(from, to, NOW, &retention);
+        assert_eq!(pick(NOW - MAX_RAW_SPAN_MS, NOW), Resolution::Raw);
+        assert_eq!(pick(NOW - MAX_RAW_SPAN_MS - 1, NOW), Resolution::Minute);
+        assert_eq!(pick(NOW - MAX_BUCKETS * MINUTE_MS, NOW), Resolution::Minute);
+        assert_eq!(
+            pick(NOW - (MAX_BUCKETS + 1) * MINUTE_MS, NOW),
+            Resolution::Hour
+        );
+        assert_eq!(pick(NOW - MAX_BUCKETS * HOUR_MS, NOW), Resolution::Hour);
+        assert_eq!(
+            pick(NOW - (MAX_BUCKETS + 1) * HOUR_MS, NOW),
+            Resolution::Day
+        );
+    }
+
+    #[test]
+    fn picks_a_resolution_that_still_holds_from() {
+        let retention = Retention::default();
+        let pick = |from: u64| pick_resolution(from, from + MINUTE_MS, NOW, &retention);
+        assert_eq!(pick(NOW - 7 * DAY_MS), Resolution::Raw);
+        assert_eq!(pick(NOW - 7 * DAY_MS - 1), Resolution::Minute);
+        assert_eq!(pick(NOW - 30 * DAY_MS - 1), Resolution::Hour);
+        assert_eq!(pick(NOW - 365 * DAY_MS - 1), Resolution::Day);
+        let forever = Retention {
+            raw: 0,
+            ..retention
+        };
+        assert_eq!(
+            pick_resolution(0, MINUTE_MS, NOW, &forever),
+            Resolution::Raw
+        );
+    }
+
+    #[test]
+    fn retention_deletes_only_what_was_rolled_up() {
+        let conn = db();
+        let retention = Retention {
+            raw: 1,
+            minute: 2,
+            hour: 3,
+            day: 0,
+        };
+        add_result(&conn, "a", NOW - 2 * DAY_MS, true, 1.0);
+        add_result(&conn, "a", NOW - DAY_MS, true, 1.0);
+        add_rollup(&conn, Resolution::Minute, NOW - 3 * DAY_MS);
+        add_rollup(&conn, Resolution::Minute, NOW - DAY_MS);
+        add_rollup(&conn, Resolution::Hour, NOW - 4 * DAY_MS);
+        add_rollup(&conn, Resolution::Day, 0);
+
+        // Minutes only rolled up to before the old result: it stays.
+        let done = HashMap::from([
+            (Resolution::Minute, NOW - 3 * DAY_MS),
+            (Resolution::Hour, NOW),
+            (Resolution::Day, NOW),
+        ]);
+        enforce_retention(&conn, &retention, NOW, &done).unwrap();
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM results"), 2);
+        assert_eq!(
+            count(
+                &conn,
+                "SELECT COUNT(*) FROM rollups WHERE resolution = '1m'"
+            ),
+            1
+        );
+        assert_eq!(
+            count(
+                &conn,
+                "SELECT COUNT(*) FROM rollups WHERE resolution = '1h'"
+            ),
+            0
+        );
+        assert_eq!(
+            count(
+                &conn,
+                "SELECT COUNT(*) FROM rollups WHERE resolution = '1d'"
+            ),
+            1
+        );
+
+        let done = HashMap::from([(Resolution::Minute, NOW)]);
+        enforce_retention(&conn, &retention, NOW, &done).unwrap();
+        assert_eq!(
+            count(&conn, "SELECT MIN(timestamp) FROM results"),
+            (NOW - DAY_MS) as i64
+        );
+    }
+}
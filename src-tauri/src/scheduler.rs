@@ -0,0 +1,230 @@
+use crate::{incident, run_probe, AppState, MIN_INTERVAL_MS};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter};
+use tokio::sync::Semaphore;
+
+/// Probes allowed to run at the same time, across all targets.
+const MAX_CONCURRENT_PROBES: usize = 20;
+/// Upper bound on the start offset given to each target.
+const MAX_JITTER_MS: u64 = 1000;
+
+/// A stable per-target start offset, so targets sharing an interval are
+/// spread out instead of firing in one burst every tick.
+fn jitter(id: &str, interval: Duration) -> Duration {
+    let mut hasher = DefaultHasher::new();
+    id.hash(&mut hasher);
+    let span = (interval.as_millis() as u64).clamp(1, MAX_JITTER_MS);
+    Duration::from_millis(hasher.finish() % span)
+}
+
+/// When each target is next due, with the interval that was worked out from.
+#[derive(Default)]
+struct Schedule(HashMap<String, (Instant, Duration)>);
+
+impl Schedule {
+    /// Whether `id` is due at `now`; if so, its next run is booked. A new
+    /// target, or one whose interval changed, starts over at its offset
+    /// rather than waiting out the old interval.
+    fn take_due(&mut self, id: &str, interval: Duration, now: Instant) -> bool {
+        let first = || (now + jitter(id, interval), interval);
+        let (due, scheduled) = self.0.entry(id.to_string()).or_insert_with(first);
+        if *scheduled != interval {
+            (*due, *scheduled) = first();
+        }
+        if *due > now {
+            return false;
+        }
+        *due = now + interval;
+        true
+    }
+
+    fn retain(&mut self, ids: &HashSet<&str>) {
+        self.0.retain(|id, _| ids.contains(id.as_str()));
+    }
+
+    fn next_wake(&self) -> Option<Instant> {
+        self.0.values().map(|(due, _)| *due).min()
+    }
+}
+
+/// Targets whose probe is running or waiting for a slot.
+type InFlightSet = Arc<Mutex<HashSet<String>>>;
+
+/// Marks a target's probe as in flight until dropped, so a probe that
+/// panics doesn't keep its target off the schedule for good.
+struct InFlight {
+    ids: InFlightSet,
+    id: String,
+}
+
+impl InFlight {
+    /// None if the target's previous probe is still in flight.
+    fn claim(ids: &InFlightSet, id: &str) -> Option<InFlight> {
+        ids.lock()
+            .unwrap()
+            .insert(id.to_string())
+            .then(|| InFlight {
+                ids: ids.clone(),
+                id: id.to_string(),
+            })
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.ids
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&self.id);
+    }
+}
+
+/// Probes every target on its own `interval_ms` cadence, with at most
+/// `MAX_CONCURRENT_PROBES` in flight. Each result is emitted as soon as its
+/// probe completes. A target whose previous probe is still running (or
+/// waiting for a slot) skips that tick.
+pub fn start_probe_loop(app: AppHandle, state: Arc<AppState>) {
+    tauri::async_runtime::spawn(async move {
+        let permits = Arc::new(Semaphore::new(MAX_CONCURRENT_PROBES));
+        let mut schedule = Schedule::default();
+        let in_flight = InFlightSet::default();
+
+        loop {
+            let targets = {
+                let t = state.targets.read().unwrap();
+                t.clone()
+            };
+
+            let now = Instant::now();
+            schedule.retain(&targets.iter().map(|t| t.id.as_str()).collect());
+
+            for target in targets {
+                let interval = Duration::from_millis(target.interval_ms.max(MIN_INTERVAL_MS));
+                if !schedule.take_due(&target.id, interval, now) {
+                    continue;
+                }
+                // Like MissedTickBehavior::Skip: a slow probe costs its next tick.
+                let Some(claim) = InFlight::claim(&in_flight, &target.id) else {
+                    continue;
+                };
+
+                let app = app.clone();
+                let state = state.clone();
+                let permits = permits.clone();
+                tauri::async_runtime::spawn(async move {
+                    let result = {
+                        let _permit = permits.acquire_owned().await;
+                        run_probe(target.clone()).await
+                    };
+                    drop(claim);
+                    let health = {
+                        let mut stats = state.stats.lock().unwrap();
+                        stats.record(&result);
+                        stats.health(&target.id)
+                    };
+                    if let Some(history) = state.history.get() {
+                        history.record(&result);
+                    }
+                    let _ = app.emit("probe:update", &result);
+                    let transitions = state
+                        .incidents
+                        .lock()
+                        .unwrap()
+                        .observe(&target, &result, health);
+                    incident::publish(&app, &state, &target, &result, transitions);
+                });
+            }
+
+            let wake = schedule
+                .next_wake()
+                .unwrap_or(now + Duration::from_secs(60));
+            tokio::select! {
+                _ = tokio::time::sleep_until(wake.into()) => {}
+                _ = state.targets_changed.notified() => {}
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    /// Adds `id` at `now` and runs it once; returns when that was.
+    fn first_run(schedule: &mut Schedule, id: &str, interval: Duration, now: Instant) -> Instant {
+        let at = now + jitter(id, interval);
+        schedule.take_due(id, interval, now);
+        assert!(schedule.take_due(id, interval, at) || at == now);
+        at
+    }
+
+    #[test]
+    fn new_targets_start_at_their_offset() {
+        let mut schedule = Schedule::default();
+        let now = Instant::now();
+        let ran = first_run(&mut schedule, "a", 5 * SECOND, now);
+        assert!(ran < now + SECOND);
+        assert_eq!(schedule.next_wake(), Some(ran + 5 * SECOND));
+    }
+
+    #[test]
+    fn runs_once_per_interval() {
+        let mut schedule = Schedule::default();
+        let ran = first_run(&mut schedule, "a", 5 * SECOND, Instant::now());
+        assert!(!schedule.take_due("a", 5 * SECOND, ran));
+        assert!(!schedule.take_due("a", 5 * SECOND, ran + 4 * SECOND));
+        assert!(schedule.take_due("a", 5 * SECOND, ran + 5 * SECOND));
+    }
+
+    #[test]
+    fn changed_interval_takes_effect_at_once() {
+        let mut schedule = Schedule::default();
+        let ran = first_run(&mut schedule, "a", 60 * SECOND, Instant::now());
+        let later = ran + 2 * SECOND;
+        assert!(!schedule.take_due("a", 60 * SECOND, later));
+        // Without the reset the next run would wait until ran + 60s.
+        schedule.take_due("a", 5 * SECOND, later);
+        assert!(schedule.next_wake().unwrap() <= later + 5 * SECOND);
+    }
+
+    #[test]
+    fn forgets_removed_targets() {
+        let mut schedule = Schedule::default();
+        let now = Instant::now() + 2 * SECOND;
+        schedule.take_due("a", 5 * SECOND, now);
+        schedule.take_due("b", 5 * SECOND, now);
+        schedule.retain(&HashSet::from(["b"]));
+        assert_eq!(schedule.0.len(), 1);
+        assert!(schedule.0.contains_key("b"));
+    }
+
+    #[test]
+    fn in_flight_claim_is_released_on_drop() {
+        let ids = InFlightSet::default();
+        let claim = InFlight::claim(&ids, "a").unwrap();
+        assert!(InFlight::claim(&ids, "a").is_none());
+        assert!(InFlight::claim(&ids, "b").is_some());
+        drop(claim);
+        assert!(InFlight::claim(&ids, "a").is_some());
+        assert!(ids.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn in_flight_claim_is_released_when_the_probe_panics() {
+        let ids = InFlightSet::default();
+        let claimed = ids.clone();
+        let panicked = std::thread::spawn(move || {
+            let _claim = InFlight::claim(&claimed, "a").unwrap();
+            panic!("probe panicked");
+        })
+        .join();
+        assert!(panicked.is_err());
+        assert!(InFlight::claim(&ids, "a").is_some());
+    }
+}
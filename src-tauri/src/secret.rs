@@ -0,0 +1,119 @@
+use keyring::Entry;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+/// Service name the secrets are filed under in the OS keyring.
+const SERVICE: &str = "connection-pulse";
+/// Shown to the webview in place of a secret. Sent back unchanged, it
+/// keeps the stored secret.
+pub const MASK: &str = "********";
+
+/// Where secrets go when the OS has no keyring to offer, e.g. Linux
+/// without a Secret Service: a file readable only by the user, next to
+/// (not inside) the history database.
+static FALLBACK: OnceLock<PathBuf> = OnceLock::new();
+/// Serializes read-modify-write cycles of the fallback file.
+static FALLBACK_LOCK: Mutex<()> = Mutex::new(());
+
+pub fn init(dir: &Path) {
+    let _ = FALLBACK.set(dir.join("secrets.json"));
+}
+
+/// Whether `e` means there is no usable keyring, rather than a failure of
+/// the one there is.
+fn unavailable(e: &keyring::Error) -> bool {
+    matches!(
+        e,
+        keyring::Error::PlatformFailure(_) | keyring::Error::NoStorageAccess(_)
+    )
+}
+
+fn secret_error(e: impl std::fmt::Display) -> String {
+    format!("secret_error: {}", e)
+}
+
+fn read_fallback(path: &Path) -> Result<BTreeMap<String, String>, String> {
+    match fs::read(path) {
+        Ok(data) => serde_json::from_slice(&data).map_err(secret_error),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(secret_error(e)),
+    }
+}
+
+fn write_fallback(path: &Path, secrets: &BTreeMap<String, String>) -> Result<(), String> {
+    let tmp = path.with_extension("json.tmp");
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(&tmp).map_err(secret_error)?;
+    file.write_all(&serde_json::to_vec(secrets).unwrap_or_default())
+        .and_then(|_| file.sync_all())
+        .map_err(secret_error)?;
+    fs::rename(&tmp, path).map_err(secret_error)
+}
+
+/// Applies `change` to the fallback file's secrets; `None` when there is
+/// no fallback file configured.
+fn with_fallback<T>(
+    change: impl FnOnce(&mut BTreeMap<String, String>) -> (T, bool),
+) -> Result<Option<T>, String> {
+    let Some(path) = FALLBACK.get() else {
+        return Ok(None);
+    };
+    let _guard = FALLBACK_LOCK.lock().unwrap();
+    let mut secrets = read_fallback(path)?;
+    let (value, changed) = change(&mut secrets);
+    if changed {
+        write_fallback(path, &secrets)?;
+    }
+    Ok(Some(value))
+}
+
+/// Reads the secret stored under `key`. Blocks on the keyring, so it must
+/// not run on an async worker thread.
+pub fn get(key: &str) -> Result<Option<String>, String> {
+    let keyring = Entry::new(SERVICE, key).and_then(|entry| entry.get_password());
+    match keyring {
+        Ok(value) => Ok(Some(value)),
+        Err(e) if matches!(e, keyring::Error::NoEntry) || unavailable(&e) => {
+            Ok(with_fallback(|secrets| (secrets.get(key).cloned(), false))?.flatten())
+        }
+        Err(e) => Err(secret_error(e)),
+    }
+}
+
+/// Stores `value` under `key`, or removes the secret when `value` is
+/// empty. Blocks on the keyring like `get`.
+pub fn set(key: &str, value: &str) -> Result<(), String> {
+    if value.is_empty() {
+        return delete(key);
+    }
+    let keyring = Entry::new(SERVICE, key).and_then(|entry| entry.set_password(value));
+    match keyring {
+        // A copy left in the file from before the keyring was available
+        // would shadow nothing, but shouldn't linger either.
+        Ok(()) => with_fallback(|secrets| ((), secrets.remove(key).is_some())).map(|_| ()),
+        Err(e) if unavailable(&e) => with_fallback(|secrets| {
+            (
+                (),
+                secrets.insert(key.into(), value.into()).as_deref() != Some(value),
+            )
+        })?
+        .ok_or_else(|| "secret_store_unavailable".to_string()),
+        Err(e) => Err(secret_error(e)),
+    }
+}
+
+pub fn delete(key: &str) -> Result<(), String> {
+    let keyring = Entry::new(SERVICE, key).and_then(|entry| entry.delete_credential());
+    match keyring {
+        Ok(()) | Err(keyring::Error::NoEntry) => {}
+        Err(e) if unavailable(&e) => {}
+        Err(e) => return Err(secret_error(e)),
+    }
+    with_fallback(|secrets| ((), secrets.remove(key).is_some())).map(|_| ())
+}
@@ -0,0 +1,432 @@
+use crate::{now_ms, AppState, ProbeResult, Target};
+use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter};
+
+pub const DEFAULT_WINDOW_MS: u64 = 5 * 60 * 1000;
+pub const MIN_WINDOW_MS: u64 = 10 * 1000;
+pub const MAX_WINDOW_MS: u64 = 24 * 60 * 60 * 1000;
+/// How often `stats:update` is emitted.
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Health {
+    Optimal,
+    Great,
+    Good,
+    Warn,
+    Bad,
+    Down,
+    /// Alternating between OK and failing; see `flap`.
+    Flapping,
+    /// No results in the window yet.
+    Unknown,
+}
+
+/// Buckets a target by success rate and latency. A warning on the latest
+/// result (e.g. certificate expiring) caps health at `Warn`.
+fn classify(success_rate: f64, avg: Option<f64>, p90: Option<f64>, warning: bool) -> Health {
+    let within = |v: Option<f64>, max: f64| v.is_some_and(|v| v <= max);
+    let at_most = |v: Option<f64>, max: f64| v.is_none_or(|v| v <= max);
+    if warning && success_rate >= 0.95 {
+        Health::Warn
+    } else if success_rate >= 0.995 && within(avg, 15.0) && within(p90, 30.0) {
+        Health::Optimal
+    } else if success_rate >= 0.99 && within(avg, 30.0) && within(p90, 80.0) {
+        Health::Great
+    } else if success_rate >= 0.98 && at_most(avg, 80.0) && at_most(p90, 200.0) {
+        Health::Good
+    } else if success_rate >= 0.95 {
+        Health::Warn
+    } else if success_rate >= 0.70 {
+        Health::Bad
+    } else {
+        Health::Down
+    }
+}
+
+/// Linear-interpolated quantile of an ascending slice.
+fn quantile(sorted: &[f64], q: f64) -> Option<f64> {
+    let last = sorted.len().checked_sub(1)?;
+    let pos = last as f64 * q;
+    let base = pos.floor() as usize;
+    let rest = pos - base as f64;
+    Some(match sorted.get(base + 1) {
+        Some(next) => sorted[base] + rest * (next - sorted[base]),
+        None => sorted[base],
+    })
+}
+
+struct Sample {
+    timestamp: u64,
+    ok: bool,
+    latency_ms: f64,
+}
+
+/// Aggregates over a run of samples.
+#[derive(Debug, Clone, Copy)]
+struct Window {
+    samples: usize,
+    ok: usize,
+    avg_ms: Option<f64>,
+    p50_ms: Option<f64>,
+    p90_ms: Option<f64>,
+    p99_ms: Option<f64>,
+}
+
+#[derive(Default)]
+struct Series {
+    samples: VecDeque<Sample>,
+    last: Option<ProbeResult>,
+    /// Results before this (Unix ms) are kept for alerts and incidents but
+    /// no longer shown; see `clear_view`.
+    shown_from: u64,
+    /// Windows worked out since the samples last changed, by the index of
+    /// their first sample: the stats loop and every alert rule ask again
+    /// each tick, and sorting a long window's latencies isn't cheap.
+    windows: RefCell<HashMap<usize, Window>>,
+}
+
+impl Series {
+    fn push(&mut self, sample: Sample) {
+        self.samples.push_back(sample);
+        self.windows.get_mut().clear();
+    }
+
+    fn prune(&mut self, cutoff: u64) {
+        let kept = self.samples.partition_point(|s| s.timestamp < cutoff);
+        if kept > 0 {
+            self.samples.drain(..kept);
+            self.windows.get_mut().clear();
+        }
+    }
+
+    /// Aggregates the samples from `cutoff` on.
+    fn window(&self, cutoff: u64) -> Window {
+        let start = self.samples.partition_point(|s| s.timestamp < cutoff);
+        if let Some(window) = self.windows.borrow().get(&start) {
+            return *window;
+        }
+        let in_window = self.samples.range(start..);
+        let mut latencies: Vec<f64> = in_window
+            .clone()
+            .filter(|s| s.ok)
+            .map(|s| s.latency_ms)
+            .collect();
+        latencies.sort_by(f64::total_cmp);
+        let window = Window {
+            samples: in_window.len(),
+            ok: latencies.len(),
+            avg_ms: (!latencies.is_empty())
+                .then(|| latencies.iter().sum::<f64>() / latencies.len() as f64),
+            p50_ms: quantile(&latencies, 0.50),
+            p90_ms: quantile(&latencies, 0.90),
+            p99_ms: quantile(&latencies, 0.99),
+        };
+        self.windows.borrow_mut().insert(start, window);
+        window
+    }
+}
+
+/// Rolling aggregates for one target over the current window.
+#[derive(Debug, Clone, Serialize)]
+pub struct TargetStats {
+    pub id: String,
+    /// Results in the window.
+    pub samples: usize,
+    pub success_rate: Option<f64>,
+    /// Latency figures cover successful results only.
+    pub avg_ms: Option<f64>,
+    pub p50_ms: Option<f64>,
+    pub p90_ms: Option<f64>,
+    pub p99_ms: Option<f64>,
+    pub health: Health,
+    /// The most recent result, if it's still in the window.
+    pub last: Option<ProbeResult>,
+}
+
+/// Payload of `get_stats` and `stats:update`.
+#[derive(Debug, Clone, Serialize)]
+pub struct StatsSnapshot {
+    pub window_ms: u64,
+    /// In target order.
+    pub targets: Vec<TargetStats>,
+}
+
+/// Aggregates over one target's results in some window.
+#[derive(Debug, Clone, Default)]
+pub struct Summary {
+    pub samples: usize,
+    pub success_rate: Option<f64>,
+    /// Latency figures cover successful results only.
+    pub avg_ms: Option<f64>,
+    pub p50_ms: Option<f64>,
+    pub p90_ms: Option<f64>,
+    pub p99_ms: Option<f64>,
+    /// Failures since the last success, regardless of window.
+    pub consecutive_failures: usize,
+    /// `failed_members` of the latest result, regardless of window.
+    pub failed_members: Option<u32>,
+}
+
+/// Per-target results within the rolling window, oldest first.
+pub struct StatsStore {
+    window_ms: u64,
+    /// Results are kept at least this long even past the window, for alert
+    /// rules looking further back.
+    keep_ms: u64,
+    series: HashMap<String, Series>,
+}
+
+impl Default for StatsStore {
+    fn default() -> StatsStore {
+        StatsStore {
+            window_ms: DEFAULT_WINDOW_MS,
+            keep_ms: 0,
+            series: HashMap::new(),
+        }
+    }
+}
+
+impl StatsStore {
+    pub fn window_ms(&self) -> u64 {
+        self.window_ms
+    }
+
+    pub fn set_window_ms(&mut self, window_ms: u64) {
+        self.window_ms = window_ms;
+    }
+
+    pub fn set_keep_ms(&mut self, keep_ms: u64) {
+        self.keep_ms = keep_ms;
+    }
+
+    pub fn record(&mut self, result: &ProbeResult) {
+        let series = self.series.entry(result.id.clone()).or_default();
+        series.push(Sample {
+            timestamp: result.timestamp,
+            ok: result.ok,
+            latency_ms: result.latency_ms,
+        });
+        series.last = Some(result.clone());
+        self.prune();
+    }
+
+    /// Forgets one target's results, or everyone's.
+    pub fn reset(&mut self, id: Option<&str>) {
+        match id {
+            Some(id) => {
+                self.series.remove(id);
+            }
+            None => self.series.clear(),
+        }
+    }
+
+    /// Hides one target's results so far (or everyone's) from snapshots,
+    /// without forgetting them: alert rules and incident tracking still see
+    /// them.
+    pub fn clear_view(&mut self, id: Option<&str>) {
+        let now = now_ms();
+        match id {
+            Some(id) => self.series.entry(id.to_string()).or_default().shown_from = now,
+            None => {
+                for series in self.series.values_mut() {
+                    series.shown_from = now;
+                }
+            }
+        }
+    }
+
+    /// Drops series for targets that no longer exist.
+    pub fn retain(&mut self, targets: &[Target]) {
+        self.series
+            .retain(|id, _| targets.iter().any(|t| &t.id == id));
+    }
+
+    fn prune(&mut self) {
+        let cutoff = now_ms().saturating_sub(self.window_ms.max(self.keep_ms));
+        for series in self.series.values_mut() {
+            series.prune(cutoff);
+        }
+    }
+
+    /// Aggregates `id`'s results from the last `window_ms`.
+    pub fn summary(&self, id: &str, window_ms: u64) -> Summary {
+        self.summary_since(id, now_ms().saturating_sub(window_ms))
+    }
+
+    fn summary_since(&self, id: &str, cutoff: u64) -> Summary {
+        let Some(series) = self.series.get(id) else {
+            return Summary::default();
+        };
+        let window = series.window(cutoff);
+
+        Summary {
+            samples: window.samples,
+            success_rate: (window.samples > 0).then(|| window.ok as f64 / window.samples as f64),
+            avg_ms: window.avg_ms,
+            p50_ms: window.p50_ms,
+            p90_ms: window.p90_ms,
+            p99_ms: window.p99_ms,
+            consecutive_failures: series.samples.iter().rev().take_while(|s| !s.ok).count(),
+            failed_members: series.last.as_ref().and_then(|r| r.failed_members),
+        }
+    }
+
+    /// `id`'s stats over the window; with `shown_only`, without the results
+    /// hidden by `clear_view`.
+    fn target_stats(&self, id: &str, shown_only: bool) -> TargetStats {
+        let mut cutoff = now_ms().saturating_sub(self.window_ms);
+        if shown_only {
+            cutoff = cutoff.max(self.series.get(id).map_or(0, |s| s.shown_from));
+        }
+        let summary = self.summary_since(id, cutoff);
+        let last = self
+            .series
+            .get(id)
+            .and_then(|s| s.last.clone())
+            .filter(|r| r.timestamp >= cutoff);
+        let health = match summary.success_rate {
+            Some(rate) => classify(
+                rate,
+                summary.avg_ms,
+                summary.p90_ms,
+                last.as_ref().is_some_and(|r| r.warning.is_some()),
+            ),
+            None => Health::Unknown,
+        };
+
+        TargetStats {
+            id: id.to_string(),
+            samples: summary.samples,
+            success_rate: summary.success_rate,
+            avg_ms: summary.avg_ms,
+            p50_ms: summary.p50_ms,
+            p90_ms: summary.p90_ms,
+            p99_ms: summary.p99_ms,
+            health,
+            last,
+        }
+    }
+
+    pub fn health(&self, id: &str) -> Health {
+        self.target_stats(id, false).health
+    }
+
+    pub fn snapshot(&mut self, targets: &[Target]) -> StatsSnapshot {
+        self.prune();
+        StatsSnapshot {
+            window_ms: self.window_ms,
+            targets: targets
+                .iter()
+                .map(|t| self.target_stats(&t.id, true))
+                .collect(),
+        }
+    }
+}
+
+/// Builds the current snapshot from the shared state. Flapping targets are
+/// reported as such, whatever their numbers say.
+pub fn snapshot(state: &AppState) -> StatsSnapshot {
+    let targets = state.targets.read().unwrap().clone();
+    let mut snapshot = state.stats.lock().unwrap().snapshot(&targets);
+    let incidents = state.incidents.lock().unwrap();
+    for stats in &mut snapshot.targets {
+        if incidents.is_flapping(&stats.id) {
+            stats.health = Health::Flapping;
+        }
+    }
+    snapshot
+}
+
+/// Emits `stats:update` every `STATS_INTERVAL`.
+pub fn start_stats_loop(app: AppHandle, state: Arc<AppState>) {
+    tauri::async_runtime::spawn(async move {
+        let mut ticker = tokio::time::interval(STATS_INTERVAL);
+        loop {
+            ticker.tick().await;
+            let _ = app.emit("stats:update", &snapshot(&state));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn record(stats: &mut StatsStore, ok: bool, timestamp: u64) {
+        stats.record(&ProbeResult {
+            id: "t".into(),
+            ok,
+            latency_ms: 10.0,
+            timestamp,
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn cleared_view_keeps_results_for_alerts() {
+        let targets: Vec<Target> = vec![serde_json::from_value(
+            json!({ "id": "t", "name": "T", "host": "example.com", "port": 443 }),
+        )
+        .unwrap()];
+        let mut stats = StatsStore::default();
+        let before = now_ms() - 1;
+        record(&mut stats, false, before);
+        record(&mut stats, false, before);
+        stats.clear_view(Some("t"));
+
+        let shown = &stats.snapshot(&targets).targets[0];
+        assert_eq!((shown.samples, shown.health), (0, Health::Unknown));
+        assert!(shown.last.is_none());
+        let summary = stats.summary("t", DEFAULT_WINDOW_MS);
+        assert_eq!((summary.samples, summary.consecutive_failures), (2, 2));
+        assert_eq!(stats.health("t"), Health::Down);
+
+        record(&mut stats, true, now_ms() + 1);
+        let shown = &stats.snapshot(&targets).targets[0];
+        assert_eq!((shown.samples, shown.success_rate), (1, Some(1.0)));
+        assert_eq!(stats.summary("t", DEFAULT_WINDOW_MS).samples, 3);
+
+        stats.reset(None);
+        assert_eq!(stats.summary("t", DEFAULT_WINDOW_MS).samples, 0);
+    }
+
+    #[test]
+    fn cached_windows_follow_new_and_pruned_results() {
+        let mut stats = StatsStore::default();
+        let now = now_ms();
+        let timed = |latency_ms, timestamp| ProbeResult {
+            id: "t".into(),
+            ok: true,
+            latency_ms,
+            timestamp,
+            ..Default::default()
+        };
+        stats.record(&timed(30.0, now - 2_000));
+        stats.record(&timed(10.0, now - 1_000));
+        let all = stats.summary_since("t", 0);
+        assert_eq!((all.samples, all.p50_ms), (2, Some(20.0)));
+        let recent = stats.summary_since("t", now - 1_000);
+        assert_eq!((recent.samples, recent.p50_ms), (1, Some(10.0)));
+        assert_eq!(stats.summary_since("t", 0).p50_ms, Some(20.0));
+
+        stats.record(&timed(20.0, now));
+        let all = stats.summary_since("t", 0);
+        assert_eq!(
+            (all.samples, all.p50_ms, all.avg_ms),
+            (3, Some(20.0), Some(20.0))
+        );
+        assert_eq!(stats.summary_since("t", now - 1_000).samples, 2);
+
+        stats.set_window_ms(1_500);
+        stats.prune();
+        let all = stats.summary_since("t", 0);
+        assert_eq!((all.samples, all.p50_ms), (2, Some(15.0)));
+    }
+}
@@ -0,0 +1,476 @@
+use crate::{elapsed_ms, now_ms, ErrorKind, ProbeError, ProbeResult};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{
+    CertificateError, ClientConfig, ClientConnection, DigitallySignedStruct, RootCertStore,
+    SignatureScheme,
+};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::net::{IpAddr, TcpStream};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use x509_parser::prelude::{FromDer, GeneralName, X509Certificate};
+
+const DEFAULT_CERT_WARN_DAYS: u32 = 14;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TlsSettings {
+    /// Server name sent via SNI; defaults to `host`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sni: Option<String>,
+    /// Warn when the certificate expires within this many days (default 14).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cert_warn_days: Option<u32>,
+}
+
+/// Leaf certificate details reported by the "tls" probe.
+#[derive(Debug, Clone, Serialize)]
+pub struct CertInfo {
+    pub subject: String,
+    pub issuer: String,
+    pub sans: Vec<String>,
+    /// Expiry as Unix milliseconds.
+    pub not_after: u64,
+    pub days_until_expiry: i64,
+    pub hostname_match: bool,
+    /// Whether the presented chain verifies against the bundled roots.
+    pub chain_valid: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chain_error: Option<String>,
+    /// Subjects of every certificate the server sent, leaf first.
+    pub chain: Vec<String>,
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    static PROVIDER: OnceLock<Arc<CryptoProvider>> = OnceLock::new();
+    PROVIDER
+        .get_or_init(|| Arc::new(rustls::crypto::ring::default_provider()))
+        .clone()
+}
+
+fn root_store() -> Arc<RootCertStore> {
+    static ROOTS: OnceLock<Arc<RootCertStore>> = OnceLock::new();
+    ROOTS
+        .get_or_init(|| {
+            Arc::new(RootCertStore::from_iter(
+                webpki_roots::TLS_SERVER_ROOTS.iter().cloned(),
+            ))
+        })
+        .clone()
+}
+
+/// Shared client config trusting the bundled Mozilla root set, so probes
+/// behave the same on every platform regardless of the OS trust store.
+pub fn client_config() -> Arc<ClientConfig> {
+    static CONFIG: OnceLock<Arc<ClientConfig>> = OnceLock::new();
+    CONFIG
+        .get_or_init(|| {
+            let config = ClientConfig::builder_with_provider(provider())
+                .with_safe_default_protocol_versions()
+                .expect("ring provider supports the default protocol versions")
+                .with_root_certificates(root_store())
+                .with_no_client_auth();
+            Arc::new(config)
+        })
+        .clone()
+}
+
+fn tls_error(detail: impl std::fmt::Display) -> ProbeError {
+    ProbeError::new(ErrorKind::TlsHandshake, format!("tls_error: {}", detail))
+}
+
+fn invalid_server_name(e: impl std::fmt::Display) -> ProbeError {
+    ProbeError::invalid(format!("tls_error: invalid server name: {}", e))
+}
+
+pub fn client_connection(host: &str) -> Result<ClientConnection, ProbeError> {
+    let server_name = ServerName::try_from(host.to_string()).map_err(invalid_server_name)?;
+    ClientConnection::new(client_config(), server_name).map_err(tls_error)
+}
+
+/// Runs the normal WebPKI checks but records the outcome instead of aborting,
+/// so the probe can still report on expired or untrusted certificates.
+/// Handshake signatures are still verified for real.
+#[derive(Debug)]
+struct RecordingVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    outcome: Mutex<Option<rustls::Error>>,
+}
+
+impl ServerCertVerifier for RecordingVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let result = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        );
+        *self.outcome.lock().unwrap() = result.err();
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+fn name_matches(pattern: &str, host: &str) -> bool {
+    let pattern = pattern.trim_end_matches('.').to_ascii_lowercase();
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    match pattern.strip_prefix("*.") {
+        // A wildcard covers exactly one leftmost label.
+        Some(suffix) => host
+            .split_once('.')
+            .is_some_and(|(label, rest)| !label.is_empty() && rest == suffix),
+        None => pattern == host,
+    }
+}
+
+/// Whole days left, rounded down: negative only once `not_after` has
+/// passed.
+fn days_until(not_after_secs: i64, now_secs: i64) -> i64 {
+    (not_after_secs - now_secs).div_euclid(86_400)
+}
+
+/// The failure for an expired certificate, or the warning for one that
+/// expires within `warn_days`.
+fn check_expiry(days_until_expiry: i64, warn_days: i64) -> Result<Option<String>, ProbeError> {
+    if days_until_expiry < 0 {
+        return Err(ProbeError::new(
+            ErrorKind::CertExpired,
+            format!("cert_expired: {} days ago", -days_until_expiry),
+        ));
+    }
+    Ok((days_until_expiry < warn_days)
+        .then(|| format!("cert_expiring: {} days left", days_until_expiry)))
+}
+
+fn parse_leaf(der: &[u8], server_name: &str) -> Result<CertInfo, ProbeError> {
+    let (_, cert) = X509Certificate::from_der(der)
+        .map_err(|e| tls_error(format!("unparseable certificate: {}", e)))?;
+
+    let mut sans = Vec::new();
+    let mut hostname_match = false;
+    let host_ip = server_name.parse::<IpAddr>().ok();
+    if let Ok(Some(ext)) = cert.subject_alternative_name() {
+        for name in &ext.value.general_names {
+            match name {
+                GeneralName::DNSName(dns) => {
+                    hostname_match |= host_ip.is_none() && name_matches(dns, server_name);
+                    sans.push(dns.to_string());
+                }
+                GeneralName::IPAddress(bytes) => {
+                    let ip = match bytes.len() {
+                        4 => <[u8; 4]>::try_from(*bytes).ok().map(IpAddr::from),
+                        16 => <[u8; 16]>::try_from(*bytes).ok().map(IpAddr::from),
+                        _ => None,
+                    };
+                    if let Some(ip) = ip {
+                        hostname_match |= host_ip == Some(ip);
+                        sans.push(ip.to_string());
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let not_after_secs = cert.validity().not_after.timestamp();
+    let now_secs = (now_ms() / 1000) as i64;
+    Ok(CertInfo {
+        subject: cert.subject().to_string(),
+        issuer: cert.issuer().to_string(),
+        sans,
+        not_after: (not_after_secs.max(0) as u64) * 1000,
+        days_until_expiry: days_until(not_after_secs, now_secs),
+        hostname_match,
+        chain_valid: false,
+        chain_error: None,
+        chain: Vec::new(),
+    })
+}
+
+fn chain_subjects(certs: &[CertificateDer<'_>]) -> Vec<String> {
+    certs
+        .iter()
+        .map(|der| match X509Certificate::from_der(der) {
+            Ok((_, cert)) => cert.subject().to_string(),
+            Err(_) => "<unparseable>".to_string(),
+        })
+        .collect()
+}
+
+fn handshake_error(e: io::Error) -> ProbeError {
+    match e.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => ProbeError::timeout(),
+        _ => match e
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<rustls::Error>())
+        {
+            Some(tls_err) => tls_error(tls_err),
+            None => tls_error(e),
+        },
+    }
+}
+
+/// Completes a TLS handshake for `sni` over an established connection and
+/// reports the leaf certificate. Handshake latency is reported on its own.
+/// A certificate expiring within `cert_warn_days` and hostname or chain
+/// problems produce a warning rather than a failure; an expired certificate
+/// fails the probe with `cert_expired`.
+pub fn tls_probe(
+    mut sock: TcpStream,
+    sni: &str,
+    tls: &TlsSettings,
+    start: Instant,
+    deadline: Instant,
+) -> ProbeResult {
+    let fail = |error: ProbeError| ProbeResult {
+        ok: false,
+        latency_ms: elapsed_ms(start),
+        error: Some(error),
+        ..Default::default()
+    };
+
+    let server_name = match ServerName::try_from(sni.to_string()) {
+        Ok(n) => n,
+        Err(e) => return fail(invalid_server_name(e)),
+    };
+
+    let left = deadline
+        .saturating_duration_since(Instant::now())
+        .max(Duration::from_millis(1));
+    if let Err(e) = sock
+        .set_read_timeout(Some(left))
+        .and_then(|_| sock.set_write_timeout(Some(left)))
+    {
+        return fail(ProbeError::io("tls_error", e));
+    }
+
+    let inner = match WebPkiServerVerifier::builder_with_provider(root_store(), provider()).build()
+    {
+        Ok(v) => v,
+        Err(e) => return fail(tls_error(e)),
+    };
+    let verifier = Arc::new(RecordingVerifier {
+        inner,
+        outcome: Mutex::new(None),
+    });
+    let config = match ClientConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+    {
+        Ok(b) => b
+            .dangerous()
+            .with_custom_certificate_verifier(verifier.clone())
+            .with_no_client_auth(),
+        Err(e) => return fail(tls_error(e)),
+    };
+    let mut conn = match ClientConnection::new(Arc::new(config), server_name) {
+        Ok(c) => c,
+        Err(e) => return fail(tls_error(e)),
+    };
+
+    let handshake_start = Instant::now();
+    while conn.is_handshaking() {
+        if let Err(e) = conn.complete_io(&mut sock) {
+            return fail(handshake_error(e));
+        }
+    }
+    let tls_ms = elapsed_ms(handshake_start);
+    let latency_ms = elapsed_ms(start);
+
+    let certs = conn.peer_certificates().unwrap_or_default();
+    let Some(leaf) = certs.first() else {
+        return fail(tls_error("server sent no certificate"));
+    };
+    let mut cert = match parse_leaf(leaf, sni) {
+        Ok(c) => c,
+        Err(e) => return fail(e),
+    };
+    cert.chain = chain_subjects(certs);
+    let chain_outcome = verifier.outcome.lock().unwrap().take();
+    cert.chain_valid = chain_outcome.is_none();
+
+    // Expiry and name mismatches are reported from our own checks below;
+    // only surface the verifier's error when it is about something else.
+    let mut warnings = Vec::new();
+    if let Some(err) = chain_outcome {
+        let covered = matches!(
+            err,
+            rustls::Error::InvalidCertificate(
+                CertificateError::Expired
+                    | CertificateError::ExpiredContext { .. }
+                    | CertificateError::NotValidForName
+                    | CertificateError::NotValidForNameContext { .. }
+            )
+        );
+        if !covered {
+            warnings.push(format!("cert_untrusted: {}", err));
+        }
+        cert.chain_error = Some(err.to_string());
+    }
+    if !cert.hostname_match {
+        warnings.push(format!("cert_hostname_mismatch: {}", sni));
+    }
+    let warn_days = tls.cert_warn_days.unwrap_or(DEFAULT_CERT_WARN_DAYS) as i64;
+    let error = match check_expiry(cert.days_until_expiry, warn_days) {
+        Ok(warning) => {
+            warnings.extend(warning);
+            None
+        }
+        Err(e) => Some(e),
+    };
+
+    ProbeResult {
+        ok: error.is_none(),
+        latency_ms,
+        error,
+        warning: (!warnings.is_empty()).then(|| warnings.join("; ")),
+        tls_ms: Some(tls_ms),
+        cert: Some(cert),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use x509_parser::pem::parse_x509_pem;
+
+    /// Self-signed, valid 2024 to 2099, for `*.example.com`, `Example.NET`,
+    /// 192.0.2.1 and 2001:db8::1.
+    const VALID: &str = "-----BEGIN CERTIFICATE-----
+MIIBxDCCAWqgAwIBAgIUKWfy6Z/xelbMXTF5OuD351fzwx8wCgYIKoZIzj0EAwIw
+FjEUMBIGA1UEAwwLZXhhbXBsZS5jb20wIBcNMjQwMTAxMDAwMDAwWhgPMjA5OTEy
+MzEyMzU5NTlaMBYxFDASBgNVBAMMC2V4YW1wbGUuY29tMFkwEwYHKoZIzj0CAQYI
+KoZIzj0DAQcDQgAEb4MHmpiU1S0SPDPWgJbFfCHcczJ4/wNi18A/sX7g7y2cb66z
+woYiib0l2ueTsfp/0FeayA4uBwilS8bwom/6yKOBkzCBkDAdBgNVHQ4EFgQUyT5n
+ouy6PiqtBmRq3gdSa4rEW/AwHwYDVR0jBBgwFoAUyT5nouy6PiqtBmRq3gdSa4rE
+W/AwDwYDVR0TAQH/BAUwAwEB/zA9BgNVHREENjA0gg0qLmV4YW1wbGUuY29tggtF
+eGFtcGxlLk5FVIcEwAACAYcQIAENuAAAAAAAAAAAAAAAATAKBggqhkjOPQQDAgNI
+ADBFAiBScXzRoviKQUckSleevLHoqzM/0l+PAsp9EunTtcNl+AIhAIADQi8p9b9X
+x/ASUfVsjsoPgwImmVfXyQ0GceHnYPUR
+-----END CERTIFICATE-----
+";
+
+    /// Self-signed for `old.example.com`, expired on 2001-01-01.
+    const EXPIRED: &str = "-----BEGIN CERTIFICATE-----
+MIIBpTCCAUugAwIBAgIUWUOEZdKDvh4ByJknA60OsgvVf98wCgYIKoZIzj0EAwIw
+GjEYMBYGA1UEAwwPb2xkLmV4YW1wbGUuY29tMB4XDTAwMDEwMTAwMDAwMFoXDTAx
+MDEwMTAwMDAwMFowGjEYMBYGA1UEAwwPb2xkLmV4YW1wbGUuY29tMFkwEwYHKoZI
+zj0CAQYIKoZIzj0DAQcDQgAEb4MHmpiU1S0SPDPWgJbFfCHcczJ4/wNi18A/sX7g
+7y2cb66zwoYiib0l2ueTsfp/0FeayA4uBwilS8bwom/6yKNvMG0wHQYDVR0OBBYE
+FMk+Z6Lsuj4qrQZkat4HUmuKxFvwMB8GA1UdIwQYMBaAFMk+Z6Lsuj4qrQZkat4H
+UmuKxFvwMA8GA1UdEwEB/wQFMAMBAf8wGgYDVR0RBBMwEYIPb2xkLmV4YW1wbGUu
+Y29tMAoGCCqGSM49BAMCA0gAMEUCIEm6ZVX0YhUGd/KT9gh7TVfRivb8Du3dABwC
+orT8j4cMAiEAzcRLtRVIK7PVdNR/x1uxM0uwnCTTQi2BGtS58D5QDVo=
+-----END CERTIFICATE-----
+";
+
+    fn leaf(pem: &str, server_name: &str) -> CertInfo {
+        let (_, pem) = parse_x509_pem(pem.as_bytes()).unwrap();
+        parse_leaf(&pem.contents, server_name).unwrap()
+    }
+
+    #[test]
+    fn wildcard_covers_one_label() {
+        assert!(name_matches("*.example.com", "www.example.com"));
+        assert!(!name_matches("*.example.com", "example.com"));
+        assert!(!name_matches("*.example.com", "a.b.example.com"));
+        assert!(!name_matches("*.example.com", ".example.com"));
+        assert!(!name_matches("*.example.com", "www.example.org"));
+    }
+
+    #[test]
+    fn names_match_ignoring_case_and_trailing_dot() {
+        assert!(name_matches("example.com", "example.com"));
+        assert!(name_matches("Example.COM", "example.com."));
+        assert!(name_matches("*.Example.com.", "WWW.example.com"));
+        assert!(!name_matches("example.com", "www.example.com"));
+    }
+
+    #[test]
+    fn parse_leaf_reads_names_and_expiry() {
+        let cert = leaf(VALID, "api.example.com");
+        assert_eq!(cert.subject, "CN=example.com");
+        assert_eq!(
+            cert.sans,
+            ["*.example.com", "Example.NET", "192.0.2.1", "2001:db8::1"]
+        );
+        assert!(cert.hostname_match);
+        assert!(cert.days_until_expiry > 365);
+        assert_eq!(cert.not_after, 4_102_444_799_000);
+
+        assert!(leaf(VALID, "example.net.").hostname_match);
+        assert!(leaf(VALID, "192.0.2.1").hostname_match);
+        assert!(leaf(VALID, "2001:db8::1").hostname_match);
+        assert!(!leaf(VALID, "example.com").hostname_match);
+        assert!(!leaf(VALID, "192.0.2.2").hostname_match);
+
+        let cert = leaf(EXPIRED, "old.example.com");
+        assert!(cert.hostname_match);
+        assert!(cert.days_until_expiry < -365);
+    }
+
+    #[test]
+    fn parse_leaf_rejects_garbage() {
+        let e = parse_leaf(b"not a certificate", "example.com").unwrap_err();
+        assert_eq!(e.kind, ErrorKind::TlsHandshake);
+    }
+
+    #[test]
+    fn expired_only_once_not_after_has_passed() {
+        let not_after = 1_700_000_000;
+        assert_eq!(days_until(not_after, not_after - 86_400), 1);
+        assert_eq!(days_until(not_after, not_after - 1), 0);
+        assert_eq!(days_until(not_after, not_after), 0);
+        assert_eq!(days_until(not_after, not_after + 1), -1);
+        assert_eq!(days_until(not_after, not_after + 86_401), -2);
+    }
+
+    #[test]
+    fn expiry_boundaries() {
+        let e = check_expiry(-1, 14).unwrap_err();
+        assert_eq!(e.kind, ErrorKind::CertExpired);
+        assert_eq!(e.detail, "cert_expired: 1 days ago");
+        assert_eq!(
+            check_expiry(0, 14).unwrap().as_deref(),
+            Some("cert_expiring: 0 days left")
+        );
+        assert_eq!(
+            check_expiry(13, 14).unwrap().as_deref(),
+            Some("cert_expiring: 13 days left")
+        );
+        assert_eq!(check_expiry(14, 14).unwrap(), None);
+        assert_eq!(check_expiry(0, 0).unwrap(), None);
+    }
+}
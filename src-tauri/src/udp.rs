@@ -0,0 +1,206 @@
+use crate::net::Bind;
+use crate::{elapsed_ms, ErrorKind, ProbeError, ProbeResult};
+use serde::{Deserialize, Serialize};
+use socket2::SockRef;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Instant;
+use tokio::net::UdpSocket;
+
+const MAX_DATAGRAM: usize = 65_535;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UdpSettings {
+    /// Datagram to send (may be empty).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub udp_payload: Option<String>,
+    /// Treat `udp_payload` and `udp_expect_prefix` as hex.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub udp_hex: bool,
+    /// Bytes the reply must start with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub udp_expect_prefix: Option<String>,
+    /// Regex the reply must match.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub udp_expect_regex: Option<String>,
+}
+
+/// Decodes a hex payload, tolerating whitespace, `:` separators and a `0x` prefix.
+fn decode_hex(raw: &str) -> Result<Vec<u8>, String> {
+    let trimmed = raw.trim();
+    let digits: Vec<u8> = trimmed
+        .strip_prefix("0x")
+        .unwrap_or(trimmed)
+        .bytes()
+        .filter(|b| !b.is_ascii_whitespace() && *b != b':')
+        .collect();
+    if !digits.len().is_multiple_of(2) {
+        return Err("odd number of hex digits".into());
+    }
+    digits
+        .chunks(2)
+        .map(|pair| {
+            // from_str_radix alone would take "+1" as a byte.
+            std::str::from_utf8(pair)
+                .ok()
+                .filter(|s| s.bytes().all(|b| b.is_ascii_hexdigit()))
+                .and_then(|s| u8::from_str_radix(s, 16).ok())
+                .ok_or_else(|| format!("not a hex byte: {}", String::from_utf8_lossy(pair)))
+        })
+        .collect()
+}
+
+/// Payload and expected prefix are hex when `udp_hex` is set, text otherwise.
+pub fn encoded(udp: &UdpSettings, value: &str) -> Result<Vec<u8>, ProbeError> {
+    if udp.udp_hex {
+        decode_hex(value).map_err(|e| ProbeError::invalid(format!("invalid_payload: {}", e)))
+    } else {
+        Ok(value.as_bytes().to_vec())
+    }
+}
+
+fn io_error(e: io::Error) -> ProbeError {
+    match e.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => ProbeError::timeout(),
+        // Linux reports an ICMP port-unreachable on a connected socket as
+        // ECONNREFUSED, Windows as WSAECONNRESET.
+        io::ErrorKind::ConnectionRefused | io::ErrorKind::ConnectionReset => {
+            ProbeError::new(ErrorKind::PortUnreachable, "udp_port_unreachable")
+        }
+        io::ErrorKind::HostUnreachable | io::ErrorKind::NetworkUnreachable => ProbeError::new(
+            ErrorKind::HostUnreachable,
+            format!("udp_unreachable: {}", e),
+        ),
+        io::ErrorKind::PermissionDenied => {
+            ProbeError::new(ErrorKind::PermissionDenied, format!("udp_error: {}", e))
+        }
+        _ => ProbeError::new(ErrorKind::Io, format!("udp_error: {}", e)),
+    }
+}
+
+fn unexpected_reply(detail: String) -> ProbeError {
+    ProbeError::new(ErrorKind::ProtocolMismatch, detail)
+}
+
+fn check_reply(udp: &UdpSettings, reply: &[u8]) -> Result<(), ProbeError> {
+    if let Some(prefix) = udp.udp_expect_prefix.as_deref().filter(|s| !s.is_empty()) {
+        if !reply.starts_with(&encoded(udp, prefix)?) {
+            return Err(unexpected_reply(
+                "udp_unexpected_reply: prefix mismatch".into(),
+            ));
+        }
+    }
+
+    if let Some(pattern) = udp.udp_expect_regex.as_deref().filter(|s| !s.is_empty()) {
+        let re = regex::bytes::Regex::new(pattern)
+            .map_err(|e| ProbeError::invalid(format!("invalid_regex: {}", e)))?;
+        if !re.is_match(reply) {
+            return Err(unexpected_reply(format!(
+                "udp_unexpected_reply: no match for /{}/",
+                pattern
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the reply and how long after sending it arrived.
+async fn exchange(
+    addr: SocketAddr,
+    payload: &[u8],
+    bind: &Bind,
+) -> Result<(Vec<u8>, f64), ProbeError> {
+    let sock = UdpSocket::bind(bind.local(addr))
+        .await
+        .map_err(ProbeError::bind)?;
+    bind.device(SockRef::from(&sock))?;
+    // Connecting lets the kernel hand ICMP errors for this peer back to us.
+    sock.connect(addr).await.map_err(io_error)?;
+    sock.send(payload).await.map_err(io_error)?;
+    let sent = Instant::now();
+
+    let mut buf = vec![0u8; MAX_DATAGRAM];
+    let n = sock.recv(&mut buf).await.map_err(io_error)?;
+    buf.truncate(n);
+    Ok((buf, elapsed_ms(sent)))
+}
+
+/// Sends one datagram to `addr` and waits for any reply, optionally
+/// checking it against an expected prefix or regex.
+pub async fn udp_probe(
+    addr: SocketAddr,
+    udp: &UdpSettings,
+    bind: &Bind,
+    start: Instant,
+    deadline: tokio::time::Instant,
+) -> ProbeResult {
+    let fail = |error: ProbeError| ProbeResult {
+        ok: false,
+        latency_ms: elapsed_ms(start),
+        error: Some(error),
+        ..Default::default()
+    };
+
+    let payload = match encoded(udp, udp.udp_payload.as_deref().unwrap_or("")) {
+        Ok(p) => p,
+        Err(e) => return fail(e),
+    };
+    let (reply, first_byte_ms) =
+        match tokio::time::timeout_at(deadline, exchange(addr, &payload, bind)).await {
+            Ok(Ok(r)) => r,
+            Ok(Err(e)) => return fail(e),
+            Err(_) => return fail(ProbeError::timeout()),
+        };
+    let latency_ms = elapsed_ms(start);
+
+    let error = check_reply(udp, &reply).err();
+    ProbeResult {
+        ok: error.is_none(),
+        latency_ms,
+        error,
+        first_byte_ms: Some(first_byte_ms),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_hex_with_separators() {
+        assert_eq!(decode_hex("0x00ff7A").unwrap(), [0x00, 0xff, 0x7a]);
+        assert_eq!(
+            decode_hex(" de ad\tbe\nef ").unwrap(),
+            [0xde, 0xad, 0xbe, 0xef]
+        );
+        assert_eq!(decode_hex("de:ad:be:ef").unwrap(), [0xde, 0xad, 0xbe, 0xef]);
+        // Whitespace is dropped, not a byte boundary.
+        assert_eq!(decode_hex("d e").unwrap(), [0xde]);
+        assert!(decode_hex("").unwrap().is_empty());
+        assert!(decode_hex("0x").unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_odd_lengths_and_non_hex() {
+        assert_eq!(decode_hex("abc").unwrap_err(), "odd number of hex digits");
+        assert_eq!(
+            decode_hex("0x1 2 3").unwrap_err(),
+            "odd number of hex digits"
+        );
+        assert_eq!(decode_hex("zz").unwrap_err(), "not a hex byte: zz");
+        assert_eq!(decode_hex("+1").unwrap_err(), "not a hex byte: +1");
+        assert!(decode_hex("é0").is_err());
+    }
+
+    #[test]
+    fn encodes_text_or_hex() {
+        let mut udp = UdpSettings::default();
+        assert_eq!(encoded(&udp, "0x41").unwrap(), b"0x41");
+        udp.udp_hex = true;
+        assert_eq!(encoded(&udp, "0x41").unwrap(), b"A");
+        let err = encoded(&udp, "4").unwrap_err();
+        assert_eq!(err.detail, "invalid_payload: odd number of hex digits");
+    }
+}
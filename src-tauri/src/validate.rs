@@ -0,0 +1,423 @@
+use crate::{dns, http, net, udp, ProbeKind, Target};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::net::IpAddr;
+
+/// Longest interface name Linux accepts (IFNAMSIZ minus the NUL).
+const MAX_INTERFACE_LEN: usize = 15;
+const MAX_PING_COUNT: u32 = 100;
+
+/// A problem with one field of one submitted target.
+#[derive(Debug, Clone, Serialize)]
+pub struct TargetError {
+    /// Position in the submitted list.
+    pub index: usize,
+    /// The target's id, if it had one.
+    pub id: String,
+    /// Offending field, or "target" when the entry couldn't be read at all.
+    pub field: String,
+    pub error: String,
+}
+
+/// Checks a hostname or IP literal the way the probes will use it.
+/// Internationalised names are checked in their punycode form.
+pub fn check_host(host: &str) -> Result<(), String> {
+    if host.is_empty() {
+        return Err("required".into());
+    }
+    // Anything that ends up on a command line must not look like a flag.
+    if host.starts_with('-') {
+        return Err("option_like_host: must not start with '-'".into());
+    }
+    if host.parse::<IpAddr>().is_ok() {
+        return Ok(());
+    }
+    let ascii = match url::Host::parse(host) {
+        Ok(url::Host::Domain(d)) => d,
+        Ok(url::Host::Ipv4(_)) => return Ok(()),
+        Ok(url::Host::Ipv6(_)) => {
+            return Err("invalid_host: IPv6 addresses go without brackets".into())
+        }
+        Err(e) => return Err(format!("invalid_host: {}", e)),
+    };
+    let name = ascii.strip_suffix('.').unwrap_or(&ascii);
+    if name.len() > 253 {
+        return Err("invalid_host: longer than 253 characters".into());
+    }
+    for label in name.split('.') {
+        let valid = !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+        if !valid {
+            return Err(format!("invalid_host: bad label {:?}", label));
+        }
+    }
+    Ok(())
+}
+
+fn check_interface(name: &str) -> Result<(), String> {
+    if !cfg!(any(target_os = "linux", target_os = "android")) {
+        return Err("invalid_bind_interface: only supported on Linux".into());
+    }
+    let valid = !name.is_empty()
+        && name.len() <= MAX_INTERFACE_LEN
+        && !name.starts_with('-')
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_graphic() && b != b'/' && b != b':');
+    if !valid {
+        return Err(format!("invalid_bind_interface: {:?}", name));
+    }
+    Ok(())
+}
+
+fn check_regex(pattern: Option<&str>) -> Result<(), String> {
+    match pattern.filter(|p| !p.is_empty()) {
+        Some(p) => regex::Regex::new(p)
+            .map(|_| ())
+            .map_err(|e| format!("invalid_regex: {}", e)),
+        None => Ok(()),
+    }
+}
+
+fn check_port(target: &Target) -> Result<(), String> {
+    if target.port == 0 {
+        return Err("invalid_port: must be between 1 and 65535".into());
+    }
+    Ok(())
+}
+
+/// Collects the errors for one target.
+struct Checker<'a> {
+    index: usize,
+    id: &'a str,
+    errors: &'a mut Vec<TargetError>,
+}
+
+impl Checker<'_> {
+    fn check(&mut self, field: &str, result: Result<(), String>) {
+        if let Err(error) = result {
+            self.errors.push(TargetError {
+                index: self.index,
+                id: self.id.to_string(),
+                field: field.to_string(),
+                error,
+            });
+        }
+    }
+}
+
+fn check_target(target: &Target, c: &mut Checker) {
+    if target.id.trim().is_empty() {
+        c.check("id", Err("required".into()));
+    }
+
+    let explicit_url = matches!(&target.kind, ProbeKind::Http(h)
+        if h.url.as_deref().is_some_and(|u| !u.trim().is_empty()));
+    if !explicit_url || !target.host.is_empty() {
+        c.check("host", check_host(&target.host));
+    }
+    if target.timeout_ms == 0 {
+        c.check("timeout_ms", Err("must be positive".into()));
+    }
+    if target.down_after == 0 {
+        c.check("down_after", Err("must be positive".into()));
+    }
+    if target.up_after == 0 {
+        c.check("up_after", Err("must be positive".into()));
+    }
+    if let Some(raw) = target.resolver.as_deref().filter(|s| !s.trim().is_empty()) {
+        c.check(
+            "resolver",
+            net::parse_resolver(raw).map(|_| ()).map_err(|e| e.detail),
+        );
+    }
+    if let Some(raw) = target
+        .bind_address
+        .as_deref()
+        .filter(|s| !s.trim().is_empty())
+    {
+        let parsed = raw.trim().parse::<IpAddr>();
+        c.check(
+            "bind_address",
+            parsed
+                .map(|_| ())
+                .map_err(|_| format!("invalid_bind_address: {}", raw)),
+        );
+    }
+    if let Some(name) = target
+        .bind_interface
+        .as_deref()
+        .filter(|s| !s.trim().is_empty())
+    {
+        c.check("bind_interface", check_interface(name.trim()));
+    }
+
+    match &target.kind {
+        ProbeKind::Tcp | ProbeKind::Tls(_) | ProbeKind::Udp(_) | ProbeKind::Banner(_) => {
+            c.check("port", check_port(target))
+        }
+        ProbeKind::Http(_) if !explicit_url => c.check("port", check_port(target)),
+        ProbeKind::Http(_) | ProbeKind::Ping(_) | ProbeKind::Dns(_) => {}
+    }
+
+    match &target.kind {
+        ProbeKind::Tcp => {}
+        ProbeKind::Ping(ping) => {
+            if let Some(count) = ping.ping_count {
+                if count == 0 || count > MAX_PING_COUNT {
+                    c.check(
+                        "ping_count",
+                        Err(format!("must be between 1 and {}", MAX_PING_COUNT)),
+                    );
+                }
+            }
+        }
+        ProbeKind::Http(settings) => {
+            let url = http::target_url(target, settings).and_then(|u| http::endpoint(&u));
+            c.check("url", url.map(|_| ()).map_err(|e| e.detail));
+            if let Some(method) = settings.http_method.as_deref() {
+                if !method.eq_ignore_ascii_case("GET") && !method.eq_ignore_ascii_case("HEAD") {
+                    c.check("http_method", Err(format!("invalid_method: {}", method)));
+                }
+            }
+            if let Some(code) = settings
+                .expected_status
+                .iter()
+                .find(|code| !(100..=599).contains(*code))
+            {
+                c.check("expected_status", Err(format!("invalid_status: {}", code)));
+            }
+            c.check("body_regex", check_regex(settings.body_regex.as_deref()));
+        }
+        ProbeKind::Tls(settings) => {
+            if let Some(sni) = settings.sni.as_deref().filter(|s| !s.is_empty()) {
+                c.check("sni", check_host(sni));
+            }
+        }
+        ProbeKind::Dns(settings) => {
+            let name = settings.dns_name.as_deref().map(str::trim).unwrap_or("");
+            c.check(
+                "dns_name",
+                if name.is_empty() {
+                    Err("required".into())
+                } else {
+                    check_host(name)
+                },
+            );
+            c.check(
+                "dns_record_type",
+                dns::parse_record_type(settings.dns_record_type.as_deref())
+                    .map(|_| ())
+                    .map_err(|e| e.detail),
+            );
+        }
+        ProbeKind::Udp(settings) => {
+            for (field, value) in [
+                ("udp_payload", &settings.udp_payload),
+                ("udp_expect_prefix", &settings.udp_expect_prefix),
+            ] {
+                if let Some(value) = value {
+                    c.check(
+                        field,
+                        udp::encoded(settings, value)
+                            .map(|_| ())
+                            .map_err(|e| e.detail),
+                    );
+                }
+            }
+            c.check(
+                "udp_expect_regex",
+                check_regex(settings.udp_expect_regex.as_deref()),
+            );
+        }
+        ProbeKind::Banner(settings) => {
+            c.check(
+                "banner_expect",
+                check_regex(settings.banner_expect.as_deref()),
+            );
+        }
+    }
+}
+
+/// Parses and checks a target list as submitted by the frontend or an
+/// imported file. Every problem is reported, not just the first.
+pub fn parse_targets(values: Vec<Value>) -> Result<Vec<Target>, Vec<TargetError>> {
+    let mut targets = Vec::with_capacity(values.len());
+    let mut errors = Vec::new();
+    let mut seen = HashSet::new();
+
+    for (index, value) in values.into_iter().enumerate() {
+        let id = value
+            .get("id")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string();
+        let mut c = Checker {
+            index,
+            id: &id,
+            errors: &mut errors,
+        };
+
+        // A port outside u16 would otherwise only surface as a generic
+        // deserialization error.
+        if let Some(port) = value.get("port").and_then(Value::as_i64) {
+            if !(0..=65535).contains(&port) {
+                c.check(
+                    "port",
+                    Err("invalid_port: must be between 1 and 65535".into()),
+                );
+                continue;
+            }
+        }
+        let target: Target = match serde_json::from_value(value) {
+            Ok(t) => t,
+            Err(e) => {
+                c.check("target", Err(e.to_string()));
+                continue;
+            }
+        };
+        check_target(&target, &mut c);
+        if !target.id.is_empty() && !seen.insert(target.id.clone()) {
+            c.check("id", Err("duplicate_id".into()));
+        }
+        targets.push(target);
+    }
+
+    if errors.is_empty() {
+        Ok(targets)
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn target(id: &str, host: &str) -> Value {
+        json!({ "id": id, "name": id, "host": host, "port": 443 })
+    }
+
+    fn errors(values: Vec<Value>) -> Vec<(usize, String, String)> {
+        parse_targets(values)
+            .err()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|e| (e.index, e.id, e.field))
+            .collect()
+    }
+
+    #[test]
+    fn accepts_names_and_addresses() {
+        for host in [
+            "example.com",
+            "example.com.",
+            "_sip._tcp.example.com",
+            "localhost",
+            "10.0.0.1",
+            "::1",
+        ] {
+            assert_eq!(check_host(host), Ok(()), "{}", host);
+        }
+    }
+
+    #[test]
+    fn rejects_option_like_hosts() {
+        assert!(check_host("-oProxyCommand=x")
+            .unwrap_err()
+            .starts_with("option_like_host"));
+        assert!(check_host("-1")
+            .unwrap_err()
+            .starts_with("option_like_host"));
+        assert_eq!(check_host(""), Err("required".into()));
+    }
+
+    #[test]
+    fn checks_internationalised_names_as_punycode() {
+        assert_eq!(check_host("münchen.de"), Ok(()));
+        assert_eq!(check_host("例え.テスト"), Ok(()));
+        // Each label still counts in its ASCII form.
+        let long = format!("{}.de", "ü".repeat(60));
+        assert!(check_host(&long).unwrap_err().contains("bad label"));
+    }
+
+    #[test]
+    fn rejects_bad_labels_and_lengths() {
+        let label = "a".repeat(63);
+        assert_eq!(check_host(&format!("{}.com", label)), Ok(()));
+        assert!(check_host(&format!("a{}.com", label))
+            .unwrap_err()
+            .contains("bad label"));
+        let name = [label.as_str(); 4].join(".");
+        assert!(check_host(&name).unwrap_err().contains("longer than 253"));
+        for host in ["a..b", "a-.com", "x.-a.com", "a b.com", "[::1]"] {
+            assert!(check_host(host).is_err(), "{}", host);
+        }
+    }
+
+    #[test]
+    fn reports_duplicate_ids() {
+        let result = errors(vec![
+            target("a", "example.com"),
+            target("b", "example.com"),
+            target("a", "example.org"),
+            target("b", "example.org"),
+        ]);
+        assert_eq!(
+            result,
+            [(2, "a".into(), "id".into()), (3, "b".into(), "id".into())]
+        );
+        let ok = parse_targets(vec![target("a", "example.com"), target("b", "example.com")]);
+        assert_eq!(ok.unwrap().len(), 2);
+    }
+
+    #[test]
+    fn reports_every_problem() {
+        let mut bad_port = target("p", "example.com");
+        bad_port["port"] = json!(70000);
+        let result = errors(vec![
+            target("", "-x"),
+            bad_port,
+            json!({ "id": "k", "host": "example.com" }),
+            target("ok", "example.com"),
+        ]);
+        assert_eq!(
+            result,
+            [
+                (0, "".into(), "id".into()),
+                (0, "".into(), "host".into()),
+                (1, "p".into(), "port".into()),
+                (2, "k".into(), "target".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_bind_settings() {
+        let mut value = target("a", "example.com");
+        value["bind_address"] = json!("10.0.0.300");
+        assert_eq!(
+            errors(vec![value.clone()]),
+            [(0, "a".into(), "bind_address".into())]
+        );
+        value["bind_address"] = json!(" 10.0.0.3 ");
+        assert!(errors(vec![value]).is_empty());
+
+        if cfg!(any(target_os = "linux", target_os = "android")) {
+            assert_eq!(check_interface("eth0"), Ok(()));
+            assert_eq!(check_interface("wg-home.10"), Ok(()));
+            for name in ["-eth0", "eth/0", "a:b", "with space", "sixteen-chars-xx"] {
+                assert!(check_interface(name).is_err(), "{}", name);
+            }
+        } else {
+            assert!(check_interface("eth0").is_err());
+        }
+    }
+}
@@ -0,0 +1,526 @@
+use crate::events::{Event, EventKind};
+use crate::history::History;
+use crate::http;
+use crate::net::{self, AddressPolicy, Bind};
+use crate::notify::Details;
+use crate::{now_ms, secret, AppState, ProbeError, Target};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use url::Url;
+
+/// Key of the webhook list in the `settings` table.
+pub const SETTINGS_KEY: &str = "webhooks";
+/// Attempts per delivery, the first one included.
+const MAX_ATTEMPTS: u32 = 5;
+/// Wait before the first retry; doubles after each one.
+const FIRST_RETRY: Duration = if cfg!(test) {
+    Duration::from_millis(10)
+} else {
+    Duration::from_secs(2)
+};
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn default_true() -> bool {
+    true
+}
+
+/// Somewhere incident changes are POSTed to as JSON.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Webhook {
+    pub id: String,
+    pub url: String,
+    /// Sent with every request, e.g. `Authorization`. The values are kept
+    /// in the secret store and masked when sent to the webview.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    /// JSON payload with `{{variable}}` placeholders, named after the
+    /// fields of `Details`. A string that is only a placeholder takes the
+    /// variable's JSON value; elsewhere the variable is inserted as text.
+    /// Without a template, the details are sent as they are.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    /// Only these targets; all targets with `notify` set when empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub targets: Vec<String>,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+}
+
+impl Webhook {
+    fn check(&self) -> Result<(), String> {
+        if self.id.trim().is_empty() {
+            return Err("id: required".into());
+        }
+        let url = Url::parse(&self.url).map_err(|e| format!("url: {}", e))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err("url: must be http or https".into());
+        }
+        for (name, value) in &self.headers {
+            if name.is_empty() || !name.bytes().all(|b| b.is_ascii_graphic() && b != b':') {
+                return Err(format!("headers: invalid name {:?}", name));
+            }
+            if value.contains(['\r', '\n']) {
+                return Err(format!("headers: {}: must be a single line", name));
+            }
+        }
+        if let Some(template) = &self.template {
+            serde_json::from_str::<Value>(template).map_err(|e| format!("template: {}", e))?;
+            let known = Details::example().to_map();
+            for captures in placeholder().captures_iter(template) {
+                if !known.contains_key(&captures[1]) {
+                    return Err(format!("template: unknown variable {}", &captures[1]));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// A copy for the webview, with the header values masked.
+    pub fn redacted(&self) -> Webhook {
+        let mut webhook = self.clone();
+        for value in webhook.headers.values_mut() {
+            *value = secret::MASK.into();
+        }
+        webhook
+    }
+
+    fn applies_to(&self, target: &Target) -> bool {
+        if self.targets.is_empty() {
+            target.notify
+        } else {
+            self.targets.contains(&target.id)
+        }
+    }
+
+    /// The request body for `details`.
+    fn payload(&self, details: &Details) -> Vec<u8> {
+        let variables = details.to_map();
+        let payload = match self
+            .template
+            .as_deref()
+            .and_then(|t| serde_json::from_str(t).ok())
+        {
+            Some(template) => fill(template, &variables),
+            None => Value::Object(variables),
+        };
+        payload.to_string().into_bytes()
+    }
+}
+
+/// The outcome of one delivery, kept in the event log.
+#[derive(Debug, Clone, Serialize)]
+pub struct Delivery {
+    pub webhook_id: String,
+    pub url: String,
+    /// What was delivered, e.g. "incident_opened".
+    pub event: String,
+    pub ok: bool,
+    pub attempts: u32,
+    /// The last response's status, if there was one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    /// Why the last attempt failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+fn placeholder() -> &'static Regex {
+    static PLACEHOLDER: OnceLock<Regex> = OnceLock::new();
+    PLACEHOLDER.get_or_init(|| Regex::new(r"\{\{\s*([a-z0-9_]+)\s*\}\}").unwrap())
+}
+
+/// Replaces the placeholders in every string of `template`.
+fn fill(template: Value, variables: &Map<String, Value>) -> Value {
+    match template {
+        Value::String(s) => {
+            let re = placeholder();
+            if let Some(captures) = re.captures(&s).filter(|c| c[0].len() == s.len()) {
+                return variables.get(&captures[1]).cloned().unwrap_or(Value::Null);
+            }
+            let filled = re.replace_all(&s, |c: &regex::Captures| match variables.get(&c[1]) {
+                Some(Value::String(v)) => v.clone(),
+                Some(Value::Null) | None => String::new(),
+                Some(v) => v.to_string(),
+            });
+            Value::String(filled.into_owned())
+        }
+        Value::Array(items) => {
+            Value::Array(items.into_iter().map(|v| fill(v, variables)).collect())
+        }
+        Value::Object(fields) => Value::Object(
+            fields
+                .into_iter()
+                .map(|(k, v)| (k, fill(v, variables)))
+                .collect(),
+        ),
+        other => other,
+    }
+}
+
+/// Secret store key of one header value.
+fn header_secret(id: &str, name: &str) -> String {
+    format!("webhook.{}.{}", id, name)
+}
+
+/// Header values that came back from the webview masked take the stored
+/// value of the same header of the webhook with the same id.
+pub fn keep_headers(webhooks: &mut [Webhook], current: &[Webhook]) -> Result<(), String> {
+    for webhook in webhooks {
+        let stored = current.iter().find(|w| w.id == webhook.id);
+        for (name, value) in webhook.headers.iter_mut() {
+            if value == secret::MASK {
+                *value = stored
+                    .and_then(|w| w.headers.get(name))
+                    .cloned()
+                    .ok_or_else(|| {
+                        format!(
+                            "invalid_webhook: {}: headers: {}: no stored value",
+                            webhook.id, name
+                        )
+                    })?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Saves `webhooks`, with the header values in the secret store rather
+/// than the history database, and drops the secrets of headers `previous`
+/// had that are gone now. Blocking.
+pub fn save(
+    history: Option<&History>,
+    webhooks: &[Webhook],
+    previous: &[Webhook],
+) -> Result<(), String> {
+    for webhook in previous {
+        for name in webhook.headers.keys() {
+            let kept = webhooks
+                .iter()
+                .any(|w| w.id == webhook.id && w.headers.contains_key(name));
+            if !kept {
+                secret::delete(&header_secret(&webhook.id, name))?;
+            }
+        }
+    }
+    for webhook in webhooks {
+        for (name, value) in &webhook.headers {
+            secret::set(&header_secret(&webhook.id, name), value)?;
+        }
+    }
+    if let Some(history) = history {
+        let stored: Vec<Webhook> = webhooks
+            .iter()
+            .map(|webhook| Webhook {
+                headers: webhook
+                    .headers
+                    .keys()
+                    .map(|name| (name.clone(), String::new()))
+                    .collect(),
+                ..webhook.clone()
+            })
+            .collect();
+        history
+            .set_setting(SETTINGS_KEY, &stored)
+            .map_err(|e| format!("history_error: {}", e))?;
+    }
+    Ok(())
+}
+
+/// The saved webhooks with their header values. Values still in the
+/// database are moved to the secret store. Blocking.
+pub fn load(history: &History) -> Result<Option<Vec<Webhook>>, String> {
+    let Some(mut webhooks) = history
+        .setting::<Vec<Webhook>>(SETTINGS_KEY)
+        .map_err(|e| format!("history_error: {}", e))?
+    else {
+        return Ok(None);
+    };
+    let mut in_database = false;
+    for webhook in &mut webhooks {
+        for (name, value) in webhook.headers.iter_mut() {
+            if value.is_empty() {
+                *value = secret::get(&header_secret(&webhook.id, name))?.unwrap_or_default();
+            } else {
+                in_database = true;
+            }
+        }
+    }
+    if in_database {
+        save(Some(history), &webhooks, &[])?;
+    }
+    Ok(Some(webhooks))
+}
+
+/// Checks a webhook list before it replaces the current one.
+pub fn check_webhooks(webhooks: &[Webhook]) -> Result<(), String> {
+    let mut ids = HashSet::new();
+    for webhook in webhooks {
+        webhook
+            .check()
+            .map_err(|e| format!("invalid_webhook: {}: {}", webhook.id, e))?;
+        if !ids.insert(webhook.id.as_str()) {
+            return Err(format!("invalid_webhook: {}: id: duplicate", webhook.id));
+        }
+    }
+    Ok(())
+}
+
+/// `url`'s host and port as addresses to connect to, resolved within
+/// `deadline` like a probe's.
+async fn resolve(url: &Url, deadline: tokio::time::Instant) -> Result<Vec<SocketAddr>, ProbeError> {
+    let (host, port) = http::endpoint(url)?;
+    let bind = Bind::default();
+    let found = net::lookup(&host, None, &bind, Duration::ZERO, deadline).await?;
+    net::narrow(found.ips, port, AddressPolicy::All, &bind)
+}
+
+/// POSTs `body`, retrying with exponential backoff after connection
+/// failures, timeouts, 429 and 5xx responses; other responses are final.
+async fn deliver(webhook: &Webhook, event: &str, body: Vec<u8>, max_attempts: u32) -> Delivery {
+    let mut delivery = Delivery {
+        webhook_id: webhook.id.clone(),
+        url: webhook.url.clone(),
+        event: event.to_string(),
+        ok: false,
+        attempts: 0,
+        status: None,
+        error: None,
+    };
+    let url = match Url::parse(&webhook.url) {
+        Ok(url) => url,
+        Err(e) => {
+            delivery.error = Some(format!("invalid_url: {}", e));
+            return delivery;
+        }
+    };
+    let headers: Vec<(String, String)> = webhook.headers.clone().into_iter().collect();
+    let body = Arc::new(body);
+    let mut delay = FIRST_RETRY;
+    loop {
+        delivery.attempts += 1;
+        let deadline = tokio::time::Instant::now() + REQUEST_TIMEOUT;
+        let outcome = match resolve(&url, deadline).await {
+            Ok(addrs) => {
+                let (url, headers, body) = (url.clone(), headers.clone(), body.clone());
+                tokio::task::spawn_blocking(move || {
+                    http::post_json(&url, &addrs, &headers, &body, deadline.into_std())
+                })
+                .await
+            }
+            Err(e) => Ok(Err(e)),
+        };
+        let retry = match outcome {
+            Ok(Ok(status)) => {
+                delivery.status = Some(status);
+                delivery.ok = (200..300).contains(&status);
+                delivery.error = (!delivery.ok).then(|| format!("http_status: {}", status));
+                status == 408 || status == 429 || status >= 500
+            }
+            Ok(Err(e)) => {
+                delivery.status = None;
+                delivery.error = Some(e.detail);
+                true
+            }
+            Err(e) => {
+                delivery.error = Some(format!("task_failed: {}", e));
+                false
+            }
+        };
+        if delivery.ok || !retry || delivery.attempts >= max_attempts {
+            return delivery;
+        }
+        tokio::time::sleep(delay).await;
+        delay *= 2;
+    }
+}
+
+/// Delivers `details` to every enabled webhook that covers `target`, in
+/// the background, and logs each delivery in the target's event log.
+pub fn dispatch(state: &Arc<AppState>, target: &Target, details: &Details) {
+    let webhooks: Vec<Webhook> = state
+        .webhooks
+        .read()
+        .unwrap()
+        .iter()
+        .filter(|w| w.enabled && w.applies_to(target))
+        .cloned()
+        .collect();
+    for webhook in webhooks {
+        let state = state.clone();
+        let details = details.clone();
+        tauri::async_runtime::spawn(async move {
+            let body = webhook.payload(&details);
+            let delivery = deliver(&webhook, details.event, body, MAX_ATTEMPTS).await;
+            if !delivery.ok {
+                eprintln!(
+                    "webhook {}: {}",
+                    webhook.id,
+                    delivery.error.as_deref().unwrap_or_default()
+                );
+            }
+            if let Some(history) = state.history.get() {
+                history.record_event(&Event {
+                    timestamp: now_ms(),
+                    target_id: details.target_id,
+                    kind: EventKind::WebhookDelivery(delivery),
+                });
+            }
+        });
+    }
+}
+
+/// Sends made-up details to `webhook` once, without retrying, so its URL,
+/// headers and template can be tried out.
+pub async fn test(webhook: &Webhook) -> Result<Delivery, String> {
+    webhook
+        .check()
+        .map_err(|e| format!("invalid_webhook: {}: {}", webhook.id, e))?;
+    let details = Details::example();
+    Ok(deliver(webhook, details.event, webhook.payload(&details), 1).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    fn variables() -> Map<String, Value> {
+        Details::example().to_map()
+    }
+
+    #[test]
+    fn fill_whole_string_takes_json_value() {
+        let filled = fill(
+            json!({ "ms": "{{duration_ms}}", "port": "{{ port }}", "kind": "{{error_kind}}" }),
+            &variables(),
+        );
+        assert_eq!(filled["ms"], json!(190_000));
+        assert_eq!(filled["port"], json!(443));
+        assert_eq!(filled["kind"], json!("connect_timeout"));
+    }
+
+    #[test]
+    fn fill_inline_inserts_text() {
+        let filled = fill(
+            json!({ "text": ["{{target_name}} on {{host}}:{{port}} for {{duration}}"] }),
+            &variables(),
+        );
+        assert_eq!(filled["text"][0], "Example on example.com:443 for 3m 10s");
+    }
+
+    #[test]
+    fn fill_names_with_digits() {
+        let filled = fill(
+            json!({ "p90": "{{p90_ms}}", "text": "p90 {{p90_ms}} ms" }),
+            &variables(),
+        );
+        assert_eq!(filled["p90"], json!(41.0));
+        assert_eq!(filled["text"], "p90 41.0 ms");
+    }
+
+    #[test]
+    fn check_rejects_unknown_variables() {
+        let mut webhook = hook("http://127.0.0.1/");
+        webhook.template = Some(r#"{"a": "{{p90_ms}}"}"#.into());
+        assert!(webhook.check().is_ok());
+        webhook.template = Some(r#"{"a": "{{p95_ms}}"}"#.into());
+        assert_eq!(
+            webhook.check().unwrap_err(),
+            "template: unknown variable p95_ms"
+        );
+    }
+
+    fn hook(url: &str) -> Webhook {
+        Webhook {
+            id: "test".into(),
+            url: url.into(),
+            headers: BTreeMap::new(),
+            template: None,
+            targets: Vec::new(),
+            enabled: true,
+        }
+    }
+
+    #[test]
+    fn masked_headers_keep_stored_values() {
+        let mut stored = hook("http://127.0.0.1/");
+        stored
+            .headers
+            .insert("Authorization".into(), "Bearer secret".into());
+        let redacted = stored.redacted();
+        assert_eq!(redacted.headers["Authorization"], secret::MASK);
+
+        let mut incoming = vec![redacted.clone()];
+        incoming[0].headers.insert("X-Extra".into(), "1".into());
+        keep_headers(&mut incoming, std::slice::from_ref(&stored)).unwrap();
+        assert_eq!(incoming[0].headers["Authorization"], "Bearer secret");
+        assert_eq!(incoming[0].headers["X-Extra"], "1");
+
+        let mut renamed = vec![redacted];
+        renamed[0].id = "other".into();
+        assert!(keep_headers(&mut renamed, &[stored]).is_err());
+    }
+
+    /// Answers one request per status in `statuses`, in order.
+    fn serve(statuses: Vec<u16>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for status in statuses {
+                let (mut conn, _) = listener.accept().unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !request.ends_with(b"}") {
+                    let n = conn.read(&mut buf).unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buf[..n]);
+                }
+                write!(
+                    conn,
+                    "HTTP/1.1 {} X\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status
+                )
+                .unwrap();
+            }
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn deliver_retries_5xx_and_429() {
+        let webhook = hook(&serve(vec![503, 429, 204]));
+        let delivery = deliver(&webhook, "test", b"{}".to_vec(), 5).await;
+        assert!(delivery.ok);
+        assert_eq!(delivery.attempts, 3);
+        assert_eq!(delivery.status, Some(204));
+        assert_eq!(delivery.error, None);
+    }
+
+    #[tokio::test]
+    async fn deliver_gives_up_on_4xx() {
+        let webhook = hook(&serve(vec![404]));
+        let delivery = deliver(&webhook, "test", b"{}".to_vec(), 5).await;
+        assert!(!delivery.ok);
+        assert_eq!(delivery.attempts, 1);
+        assert_eq!(delivery.status, Some(404));
+        assert_eq!(delivery.error.as_deref(), Some("http_status: 404"));
+    }
+
+    #[tokio::test]
+    async fn deliver_stops_after_max_attempts() {
+        let webhook = hook(&serve(vec![500, 500]));
+        let delivery = deliver(&webhook, "test", b"{}".to_vec(), 2).await;
+        assert!(!delivery.ok);
+        assert_eq!(delivery.attempts, 2);
+        assert_eq!(delivery.status, Some(500));
+    }
+}
@@ -3,7 +3,7 @@ import { invoke } from "@tauri-apps/api/core";
 import { listen } from "@tauri-apps/api/event";
 import { open, save } from "@tauri-apps/plugin-dialog";
 import { readTextFile, writeTextFile } from "@tauri-apps/plugin-fs";
-import type { Target, ProbeResult, TargetStats } from "./types";
+import type { Target, ProbeResult, TargetStats, StatsSnapshot, TargetError } from "./types";
 import { loadTargets, saveTargets, parseTargetsJson, getStorageInfo, StorageMode } from "./storage";
 import { platform } from "@tauri-apps/plugin-os";
 import {
@@ -23,7 +23,12 @@ import {
 } from "@dnd-kit/sortable";
 import { CSS } from "@dnd-kit/utilities";
 
-const WINDOW_MS = 5 * 60 * 1000; // 5 minute rolling window
+const DEFAULT_WINDOW_MS = 5 * 60 * 1000;
+const WINDOW_OPTIONS_MS = [60 * 1000, 5 * 60 * 1000, 15 * 60 * 1000, 60 * 60 * 1000];
+const WINDOW_STORAGE_KEY = "statsWindowMs";
+// Backend defaults for a target's interval_ms
+const DEFAULT_INTERVAL_MS = 5000;
+const MIN_INTERVAL_MS = 250;
 
 const EXAMPLE_TARGETS: Omit<Target, "id">[] = [
   { name: "Cloudflare", host: "1.1.1.1", port: 443, probe_type: "tcp" },
@@ -36,49 +41,44 @@ const EXAMPLE_TARGETS: Omit<Target, "id">[] = [
   { name: "Amazon", host: "amazon.com", port: 443, probe_type: "tcp" },
 ];
 
-function quantile(arr: number[], q: number): number | null {
-  if (!arr.length) return null;
-  const sorted = [...arr].sort((a, b) => a - b);
-  const pos = (sorted.length - 1) * q;
-  const base = Math.floor(pos);
-  const rest = pos - base;
-  if (sorted[base + 1] !== undefined) {
-    return sorted[base] + rest * (sorted[base + 1] - sorted[base]);
-  }
-  return sorted[base];
-}
-
-function computeHealth(
-  successRate: number | null,
-  average: number | null,
-  p90: number | null
-): TargetStats["health"] {
-  if (successRate === null) return "unknown";
-  if (successRate >= 0.995 && average !== null && average <= 15 && p90 !== null && p90 <= 30) return "optimal";
-  if (successRate >= 0.99 && average !== null && average <= 30 && p90 !== null && p90 <= 80) return "great";
-  if (successRate >= 0.98 && (average === null || average <= 80) && (p90 === null || p90 <= 200)) return "good";
-  if (successRate >= 0.95) return "warn";
-  if (successRate >= 0.70) return "bad";
-  return "down";
-}
-
 function generateId(): string {
   return crypto.randomUUID();
 }
 
-const fmtMs = (v: number | null) => (v === null ? "—" : `${Math.round(v)} ms`);
+const fmtMs = (v: number | null) =>
+  v === null ? "—" : v < 10 ? `${v.toFixed(1)} ms` : `${Math.round(v)} ms`;
 const fmtPct = (v: number | null) => (v === null ? "—" : `${Math.round(v * 100)}%`);
+const fmtWindow = (ms: number) => (ms >= 60 * 60 * 1000 ? `${ms / (60 * 60 * 1000)}h` : `${Math.round(ms / 60000)}m`);
+const fmtInterval = (ms: number) => (ms >= 60 * 1000 && ms % 60000 === 0 ? `${ms / 60000}m` : `${ms / 1000}s`);
+
+// "every 5s", or "every 1s–30s" when targets run on different intervals
+function fmtCadence(targets: Target[]): string {
+  const intervals = targets.map((t) => Math.max(t.interval_ms ?? DEFAULT_INTERVAL_MS, MIN_INTERVAL_MS));
+  const min = Math.min(...intervals);
+  const max = Math.max(...intervals);
+  return min === max ? `every ${fmtInterval(min)}` : `every ${fmtInterval(min)}–${fmtInterval(max)}`;
+}
+
+function savedWindowMs(): number {
+  const saved = parseInt(localStorage.getItem(WINDOW_STORAGE_KEY) || "");
+  return WINDOW_OPTIONS_MS.includes(saved) ? saved : DEFAULT_WINDOW_MS;
+}
 
 // Sortable row component
 interface SortableRowProps {
-  stat: TargetStats;
+  target: Target;
+  stat: TargetStats | undefined;
   onRefresh: (id: string) => void;
   onEdit: (target: Target) => void;
   onDelete: (id: string) => void;
 }
 
-function SortableRow({ stat, onRefresh, onEdit, onDelete }: SortableRowProps) {
-  const { target, successRate, average, p90, lastResult, health } = stat;
+function SortableRow({ target, stat, onRefresh, onEdit, onDelete }: SortableRowProps) {
+  const successRate = stat?.success_rate ?? null;
+  const average = stat?.avg_ms ?? null;
+  const p90 = stat?.p90_ms ?? null;
+  const lastResult = stat?.last ?? null;
+  const health = stat?.health ?? "unknown";
 
   const {
     attributes,
@@ -103,18 +103,24 @@ function SortableRow({ stat, onRefresh, onEdit, onDelete }: SortableRowProps) {
     <tr ref={setNodeRef} style={style} className={isDragging ? "dragging" : ""} {...rowProps}>
       <td className="drag-handle" {...handleProps} title="Drag to reorder">☰</td>
       <td data-label="Name">{target.name}</td>
-      <td className="mono" data-label={target.probe_type === "ping" ? "Host" : "Host:Port"}>
-        {target.probe_type === "ping" ? `ping ${target.host}` : `${target.host}:${target.port}`}
+      <td className="mono" data-label={target.probe_type === "ping" ? "Host" : target.probe_type === "http" ? "URL" : "Host:Port"}>
+        {target.probe_type === "ping"
+          ? `ping ${target.host}`
+          : target.probe_type === "http" && target.url
+            ? target.url
+            : `${target.host}:${target.port}`}
       </td>
       <td data-label="Health">
         <span className={`pill ${health}`}>
           {health.toUpperCase()}
         </span>
       </td>
-      <td data-label="Last">
+      <td data-label="Last" title={lastResult?.warning || lastResult?.error || undefined}>
         {lastResult
           ? lastResult.ok
-            ? fmtMs(lastResult.latency_ms)
+            ? lastResult.ping && lastResult.ping.loss_pct > 0
+              ? `${fmtMs(lastResult.latency_ms)} · ${Math.round(lastResult.ping.loss_pct)}% loss`
+              : fmtMs(lastResult.latency_ms)
             : `FAIL`
           : "—"}
       </td>
@@ -141,7 +147,8 @@ function SortableRow({ stat, onRefresh, onEdit, onDelete }: SortableRowProps) {
 
 export default function App() {
   const [targets, setTargets] = useState<Target[]>([]);
-  const [results, setResults] = useState<Map<string, ProbeResult[]>>(new Map());
+  const [stats, setStats] = useState<Map<string, TargetStats>>(new Map());
+  const [windowMs, setWindowMs] = useState(DEFAULT_WINDOW_MS);
   const [editingTarget, setEditingTarget] = useState<Target | null>(null);
   const [isAdding, setIsAdding] = useState(false);
   const [dragOver, setDragOver] = useState(false);
@@ -152,6 +159,9 @@ export default function App() {
   const [showInfo, setShowInfo] = useState(false);
   const [showSplash, setShowSplash] = useState(true); // Mobile splash screen
   const [deleteConfirm, setDeleteConfirm] = useState<{ id: string; name: string } | null>(null);
+  const [editErrors, setEditErrors] = useState<TargetError[]>([]);
+  // Errors for a whole list that was rejected outside the editor (saved file, import).
+  const [listNotice, setListNotice] = useState<{ message: string; targets: Target[]; errors: TargetError[] } | null>(null);
   const fileInputRef = useRef<HTMLInputElement>(null);
 
   // dnd-kit sensors - pointer for mouse, touch for mobile
@@ -170,6 +180,24 @@ export default function App() {
   const mobileSensors = useSensors(touchSensor);
   const desktopSensors = useSensors(pointerSensor);
 
+  const applySnapshot = useCallback((snapshot: StatsSnapshot) => {
+    setStats(new Map(snapshot.targets.map((s) => [s.id, s])));
+    setWindowMs(snapshot.window_ms);
+  }, []);
+
+  // Clears the backend's stats for one target (or all) and shows the result right away.
+  // Alert rules lose their inputs too, so this is only for results that no longer apply.
+  const resetStats = useCallback(async (id?: string) => {
+    await invoke("reset_stats", { id: id ?? null });
+    applySnapshot(await invoke<StatsSnapshot>("get_stats"));
+  }, [applySnapshot]);
+
+  // Starts the displayed stats afresh; alerts and incidents keep their history
+  const clearView = useCallback(async (id?: string) => {
+    await invoke("clear_stats_view", { id: id ?? null });
+    applySnapshot(await invoke<StatsSnapshot>("get_stats"));
+  }, [applySnapshot]);
+
   // Load targets and storage info on mount
   useEffect(() => {
     (async () => {
@@ -180,14 +208,38 @@ export default function App() {
       setStorageMode(info.mode);
       setStoragePath(info.path);
       const loaded = await loadTargets();
-      setTargets(loaded);
-      await invoke("set_targets", { targets: loaded });
+      let applied = loaded;
+      try {
+        await invoke("set_targets", { targets: loaded });
+      } catch (errors) {
+        // Probe the valid targets rather than none, and say which were skipped.
+        const rejected = Array.isArray(errors) ? (errors as TargetError[]) : [];
+        const bad = new Set(rejected.map((e) => e.index));
+        applied = loaded.filter((_, i) => !bad.has(i));
+        try {
+          await invoke("set_targets", { targets: applied });
+        } catch {
+          applied = [];
+          await invoke("set_targets", { targets: applied });
+        }
+        setListNotice({
+          message:
+            applied.length > 0
+              ? `Skipped ${loaded.length - applied.length} saved target(s) that failed validation. The next change to the list overwrites the saved file.`
+              : "Saved targets failed validation; none are being probed. The next change to the list overwrites the saved file.",
+          targets: loaded,
+          errors: rejected,
+        });
+      }
+      setTargets(applied);
+      await invoke("set_stats_window", { windowMs: savedWindowMs() });
+      applySnapshot(await invoke<StatsSnapshot>("get_stats"));
       // If no targets, dismiss splash immediately (no probes will fire)
       if (loaded.length === 0) {
         setShowSplash(false);
       }
     })();
-  }, []);
+  }, [applySnapshot]);
 
   // Splash screen timeout (max 2.5 seconds)
   useEffect(() => {
@@ -204,8 +256,8 @@ export default function App() {
 
     const handleVisibilityChange = () => {
       if (!document.hidden) {
-        // App came to foreground - clear old stats so fresh probes show accurate state
-        setResults(new Map());
+        // App came to foreground - hide old stats so fresh probes show accurate state
+        clearView();
       }
     };
 
@@ -213,24 +265,12 @@ export default function App() {
     return () => {
       document.removeEventListener("visibilitychange", handleVisibilityChange);
     };
-  }, [isMobile]);
+  }, [isMobile, clearView]);
 
-  // Listen for probe updates
+  // Hide splash screen on first probe result (mobile only)
   useEffect(() => {
-    const unlisten = listen<ProbeResult>("probe:update", (event) => {
-      const result = event.payload;
-      // Hide splash screen on first probe result (mobile only)
+    const unlisten = listen<ProbeResult>("probe:update", () => {
       setShowSplash(false);
-      setResults((prev) => {
-        const next = new Map(prev);
-        const arr = next.get(result.id) || [];
-        arr.push(result);
-        // Prune old results
-        const cutoff = Date.now() - WINDOW_MS;
-        const pruned = arr.filter((r) => r.timestamp >= cutoff);
-        next.set(result.id, pruned);
-        return next;
-      });
     });
 
     return () => {
@@ -238,6 +278,17 @@ export default function App() {
     };
   }, []);
 
+  // Stats are aggregated in the backend and pushed periodically
+  useEffect(() => {
+    const unlisten = listen<StatsSnapshot>("stats:update", (event) => {
+      applySnapshot(event.payload);
+    });
+
+    return () => {
+      unlisten.then((fn) => fn());
+    };
+  }, [applySnapshot]);
+
   // HTML5 file drop handlers (desktop only - separate from row reordering)
   const handleFileDragOver = (e: React.DragEvent) => {
     if (isMobile) return;
@@ -267,25 +318,38 @@ export default function App() {
 
     try {
       const txt = await jsonFile.text();
-      const imported = parseTargetsJson(txt);
-      if (imported && imported.length > 0) {
-        setTargets(imported);
-        await saveTargets(imported);
-        await invoke("set_targets", { targets: imported });
-        setResults(new Map());
-      }
+      await importTargets(parseTargetsJson(txt));
     } catch (e) {
       console.error("Failed to import:", e);
     }
   };
 
-  const handleSaveTargets = useCallback(async (newTargets: Target[]) => {
+  // The backend validates the list first; nothing is saved if it's rejected.
+  const handleSaveTargets = useCallback(async (newTargets: Target[]): Promise<TargetError[]> => {
+    try {
+      await invoke("set_targets", { targets: newTargets });
+    } catch (errors) {
+      return errors as TargetError[];
+    }
     setTargets(newTargets);
     await saveTargets(newTargets);
-    await invoke("set_targets", { targets: newTargets });
+    return [];
   }, []);
 
+  // An import replaces the whole list, or nothing if any entry is rejected.
+  const importTargets = async (imported: Target[] | null) => {
+    if (!imported || imported.length === 0) return;
+    const errors = await handleSaveTargets(imported);
+    if (errors.length > 0) {
+      setListNotice({ message: "Import rejected; the current targets are unchanged.", targets: imported, errors });
+      return;
+    }
+    setListNotice(null);
+    await resetStats();
+  };
+
   const handleAddTarget = () => {
+    setEditErrors([]);
     setEditingTarget({ id: generateId(), name: "", host: "", port: 443, probe_type: "tcp" });
     setIsAdding(true);
   };
@@ -298,6 +362,7 @@ export default function App() {
   };
 
   const handleEditTarget = (target: Target) => {
+    setEditErrors([]);
     setEditingTarget({ ...target });
     setIsAdding(false);
   };
@@ -312,11 +377,6 @@ export default function App() {
     if (!deleteConfirm) return;
     const newTargets = targets.filter((t) => t.id !== deleteConfirm.id);
     await handleSaveTargets(newTargets);
-    setResults((prev) => {
-      const next = new Map(prev);
-      next.delete(deleteConfirm.id);
-      return next;
-    });
     setDeleteConfirm(null);
   };
 
@@ -332,6 +392,7 @@ export default function App() {
 
     let newTargets: Target[];
     let shouldClearStats = false;
+    setEditErrors([]);
 
     if (isAdding) {
       newTargets = [...targets, targetToSave];
@@ -344,28 +405,30 @@ export default function App() {
       newTargets = targets.map((t) => (t.id === targetToSave.id ? targetToSave : t));
     }
 
-    if (shouldClearStats) {
-      setResults((prev) => {
-        const next = new Map(prev);
-        next.delete(targetToSave.id);
-        return next;
-      });
+    const errors = await handleSaveTargets(newTargets);
+    if (errors.length > 0) {
+      setEditErrors(errors);
+      return;
     }
 
-    await handleSaveTargets(newTargets);
+    if (shouldClearStats) {
+      await resetStats(targetToSave.id);
+    }
     setEditingTarget(null);
   };
 
   const handleRefresh = (id: string) => {
-    setResults((prev) => {
-      const next = new Map(prev);
-      next.delete(id);
-      return next;
-    });
+    clearView(id);
   };
 
   const handleRefreshAll = () => {
-    setResults(new Map());
+    clearView();
+  };
+
+  const handleWindowChange = async (ms: number) => {
+    localStorage.setItem(WINDOW_STORAGE_KEY, String(ms));
+    await invoke("set_stats_window", { windowMs: ms });
+    applySnapshot(await invoke<StatsSnapshot>("get_stats"));
   };
 
   // dnd-kit drag end handler
@@ -382,6 +445,7 @@ export default function App() {
   };
 
   const handleCancelEdit = () => {
+    setEditErrors([]);
     setEditingTarget(null);
   };
 
@@ -394,11 +458,7 @@ export default function App() {
 
     try {
       const txt = await readTextFile(path);
-      const imported = parseTargetsJson(txt);
-      if (imported && imported.length > 0) {
-        await handleSaveTargets(imported);
-        setResults(new Map());
-      }
+      await importTargets(parseTargetsJson(txt));
     } catch (e) {
       console.error("Failed to import:", e);
     }
@@ -419,20 +479,6 @@ export default function App() {
     }
   };
 
-  // Compute stats for each target
-  const stats: TargetStats[] = targets.map((target) => {
-    const targetResults = results.get(target.id) || [];
-    const okResults = targetResults.filter((r) => r.ok);
-    const successRate = targetResults.length > 0 ? okResults.length / targetResults.length : null;
-    const latencies = okResults.map((r) => r.latency_ms);
-    const average = latencies.length > 0 ? latencies.reduce((a, b) => a + b, 0) / latencies.length : null;
-    const p90 = quantile(latencies, 0.90);
-    const lastResult = targetResults.length > 0 ? targetResults[targetResults.length - 1] : null;
-    const health = computeHealth(successRate, average, p90);
-
-    return { target, results: targetResults, successRate, average, p90, lastResult, health };
-  });
-
   return (
     <div
       className={`app ${dragOver ? "drag-over" : ""}`}
@@ -468,6 +514,22 @@ export default function App() {
         </div>
       </header>
 
+      {listNotice && (
+        <div className="list-notice">
+          <div className="list-notice-header">
+            <span>{listNotice.message}</span>
+            <button onClick={() => setListNotice(null)} title="Dismiss">×</button>
+          </div>
+          <ul className="form-errors">
+            {listNotice.errors.map((e) => (
+              <li key={`${e.index}-${e.field}`}>
+                {listNotice.targets[e.index]?.name || e.id}: {e.field}: {e.error}
+              </li>
+            ))}
+          </ul>
+        </div>
+      )}
+
       {dragOver && <div className="drop-overlay">Drop JSON file to import</div>}
 
       {editingTarget && (
@@ -532,6 +594,52 @@ export default function App() {
                 />
               </div>
             )}
+            <div className="form-group">
+              <label>Interval (ms)</label>
+              <input
+                type="number"
+                inputMode="numeric"
+                autoComplete="off"
+                value={editingTarget.interval_ms ?? ""}
+                onChange={(e) =>
+                  setEditingTarget({ ...editingTarget, interval_ms: parseInt(e.target.value) || undefined })
+                }
+                placeholder="5000"
+              />
+            </div>
+            <div className="form-group">
+              <label>Timeout (ms)</label>
+              <input
+                type="number"
+                inputMode="numeric"
+                autoComplete="off"
+                value={editingTarget.timeout_ms ?? ""}
+                onChange={(e) =>
+                  setEditingTarget({ ...editingTarget, timeout_ms: parseInt(e.target.value) || undefined })
+                }
+                placeholder="2000"
+              />
+            </div>
+            <div className="form-group checkbox">
+              <label title="Desktop notifications, plus webhooks, command hooks and email that don't list their own targets">
+                <input
+                  type="checkbox"
+                  checked={editingTarget.notify ?? false}
+                  onChange={(e) => setEditingTarget({ ...editingTarget, notify: e.target.checked })}
+                />
+                Notify when down or recovered (desktop, webhooks, hooks, email)
+              </label>
+            </div>
+            {editErrors.length > 0 && (
+              <ul className="form-errors">
+                {editErrors.map((e) => (
+                  <li key={`${e.index}-${e.field}`}>
+                    {e.id !== editingTarget.id && `${targets[e.index]?.name || e.id}: `}
+                    {e.field}: {e.error}
+                  </li>
+                ))}
+              </ul>
+            )}
             <div className="modal-actions">
               <button onClick={handleCancelEdit}>Cancel</button>
               <button className="primary" onClick={handleSaveEdit}>
@@ -586,15 +694,16 @@ export default function App() {
                     <th>Last</th>
                     <th>Avg</th>
                     <th>p90</th>
-                    <th>Success (5m)</th>
+                    <th>Success ({fmtWindow(windowMs)})</th>
                     <th>Actions</th>
                   </tr>
                 </thead>
                 <tbody>
-                  {stats.map((stat) => (
+                  {targets.map((target) => (
                     <SortableRow
-                      key={stat.target.id}
-                      stat={stat}
+                      key={target.id}
+                      target={target}
+                      stat={stats.get(target.id)}
                       onRefresh={handleRefresh}
                       onEdit={handleEditTarget}
                       onDelete={handleDeleteTarget}
@@ -609,7 +718,22 @@ export default function App() {
 
       <footer>
         <div className="footer-left">
-          <span>v1.0.7 • {isMobile ? "Probing every 5s while open" : "Probing every 5s"} • Window: 5min</span>
+          <span>
+            v1.0.7 •{" "}
+            {targets.length > 0 && <>Probing {fmtCadence(targets)}{isMobile && " while open"} • </>}
+            Window:{" "}
+            <select
+              className="window-select"
+              value={windowMs}
+              onChange={(e) => handleWindowChange(parseInt(e.target.value))}
+            >
+              {WINDOW_OPTIONS_MS.map((ms) => (
+                <option key={ms} value={ms}>
+                  {fmtWindow(ms)}
+                </option>
+              ))}
+            </select>
+          </span>
           {!isMobile && (
             <span className="storage-info">
               {storageMode === "portable"
@@ -628,7 +752,7 @@ export default function App() {
           <h3>How It Works</h3>
           <ul>
             <li><strong>Probing:</strong> {isIOS ? "TCP connect test at 5-second intervals while app is open" : isMobile ? "TCP connect or ICMP ping at 5-second intervals while app is open" : "TCP connect or ICMP ping test every 5 seconds per target"}</li>
-            <li><strong>Stats:</strong> Calculated over a 5-minute rolling window</li>
+            <li><strong>Stats:</strong> Calculated over a rolling window (5 minutes by default, adjustable in the footer)</li>
             <li><strong>Health:</strong> Based on success rate, average latency, and p90 latency</li>
           </ul>
 
@@ -1,25 +1,312 @@
+export type AddressPolicy = "first" | "ipv4" | "ipv6" | "happy_eyeballs" | "all";
+
 export interface Target {
   id: string;
   name: string;
   host: string;
   port: number;
-  probe_type: "tcp" | "ping";
+  probe_type: "tcp" | "ping" | "http" | "tls" | "dns" | "udp" | "banner";
+  interval_ms?: number;
+  timeout_ms?: number;
+  address_policy?: AddressPolicy;
+  resolver?: string;
+  resolve_cache_ms?: number;
+  bind_address?: string;
+  bind_interface?: string;
+  down_after?: number;
+  up_after?: number;
+  /** Desktop notifications, and webhooks, hooks and email without a target list. */
+  notify?: boolean;
+  url?: string;
+  http_method?: "GET" | "HEAD";
+  expected_status?: number[];
+  body_contains?: string;
+  body_regex?: string;
+  sni?: string;
+  cert_warn_days?: number;
+  dns_name?: string;
+  dns_record_type?: "A" | "AAAA" | "CNAME" | "MX" | "TXT";
+  dns_expect?: string[];
+  ping_count?: number;
+  ping_interval_ms?: number;
+  udp_payload?: string;
+  udp_hex?: boolean;
+  udp_expect_prefix?: string;
+  udp_expect_regex?: string;
+  banner_send?: string;
+  banner_expect?: string;
+}
+
+export interface CertInfo {
+  subject: string;
+  issuer: string;
+  sans: string[];
+  not_after: number;
+  days_until_expiry: number;
+  hostname_match: boolean;
+  chain_valid: boolean;
+  chain_error?: string;
+  chain: string[];
 }
 
+export interface PingStats {
+  sent: number;
+  received: number;
+  loss_pct: number;
+  min_ms: number;
+  avg_ms: number;
+  max_ms: number;
+  mdev_ms: number;
+  jitter_ms: number;
+}
+
+export type ErrorKind =
+  | "invalid_config"
+  | "dns_nxdomain"
+  | "dns_timeout"
+  | "dns_failure"
+  | "bind_failed"
+  | "connect_refused"
+  | "connect_timeout"
+  | "host_unreachable"
+  | "port_unreachable"
+  | "permission_denied"
+  | "timeout"
+  | "tls_handshake"
+  | "cert_expired"
+  | "http_status"
+  | "protocol_mismatch"
+  | "protocol_error"
+  | "io"
+  | "internal";
+
 export interface ProbeResult {
   id: string;
   ok: boolean;
   latency_ms: number;
-  error: string | null;
+  error?: string;
+  error_kind?: ErrorKind;
   timestamp: number;
+  total_ms: number;
+  dns_ms?: number;
+  connect_ms?: number;
+  tls_ms?: number;
+  first_byte_ms?: number;
+  http_status?: number;
+  warning?: string;
+  cert?: CertInfo;
+  dns_rcode?: string;
+  dns_answers?: string[];
+  ping?: PingStats;
+  banner_ms?: number;
+  banner?: string;
+  address?: string;
+  members?: ProbeResult[];
+  /** How many of `members` failed; set whenever `members` is. */
+  failed_members?: number;
 }
 
+/** One problem with one field, as returned by a rejected `set_targets`. */
+export interface TargetError {
+  index: number;
+  id: string;
+  field: string;
+  error: string;
+}
+
+export type Health =
+  | "optimal"
+  | "great"
+  | "good"
+  | "warn"
+  | "bad"
+  | "down"
+  | "flapping"
+  | "unknown";
+
+/** Rolling aggregates for one target, computed by the backend. */
 export interface TargetStats {
-  target: Target;
-  results: ProbeResult[];
-  successRate: number | null;
-  average: number | null;
-  p90: number | null;
-  lastResult: ProbeResult | null;
-  health: "optimal" | "great" | "good" | "warn" | "bad" | "down" | "unknown";
+  id: string;
+  samples: number;
+  success_rate: number | null;
+  avg_ms: number | null;
+  p50_ms: number | null;
+  p90_ms: number | null;
+  p99_ms: number | null;
+  health: Health;
+  last: ProbeResult | null;
+}
+
+/** Payload of `get_stats` and the `stats:update` event. */
+export interface StatsSnapshot {
+  window_ms: number;
+  targets: TargetStats[];
+}
+
+/** One rolled-up period of a target's history. Latencies cover successful results only. */
+export interface HistoryBucket {
+  timestamp: number;
+  count: number;
+  failures: number;
+  min_ms: number | null;
+  avg_ms: number | null;
+  max_ms: number | null;
+  p50_ms: number | null;
+  p90_ms: number | null;
+  p99_ms: number | null;
+}
+
+export type Resolution = "raw" | "1m" | "1h" | "1d";
+
+/** Payload of `query_history`: raw results or buckets, depending on `resolution`. */
+export interface HistoryPage {
+  resolution: Resolution;
+  results?: ProbeResult[];
+  buckets?: HistoryBucket[];
+}
+
+/** A period during which a target was down; `ended` is absent while open. */
+export interface Incident {
+  target_id: string;
+  started: number;
+  ended?: number;
+  duration_ms?: number;
+  error_kind?: ErrorKind;
+  error?: string;
+}
+
+export type AlertMetric =
+  | "success_rate"
+  | "avg_ms"
+  | "p50_ms"
+  | "p90_ms"
+  | "p99_ms"
+  | "consecutive_failures"
+  | "failed_members";
+
+export type Severity = "info" | "warning" | "critical";
+
+/** "`metric` `op` `threshold` over `window_ms`, for `for_ms`"; applies to all targets when `targets` is empty. */
+export interface AlertRule {
+  id: string;
+  name?: string;
+  targets?: string[];
+  metric: AlertMetric;
+  op: ">" | ">=" | "<" | "<=";
+  threshold: number;
+  window_ms?: number;
+  for_ms?: number;
+  severity?: Severity;
+  resolve_threshold?: number;
+  resolve_for_ms?: number;
 }
+
+/** Payload of `alert:firing` and `alert:resolved`. */
+export interface Alert {
+  rule_id: string;
+  rule_name: string;
+  target_id: string;
+  severity: Severity;
+  metric: AlertMetric;
+  value: number;
+  threshold: number;
+  started: number;
+  fired: number;
+  resolved?: number;
+}
+
+/** One entry of the event log, as returned by `query_events`. */
+export type TargetEvent = { timestamp: number; target_id: string } & (
+  | { kind: "incident_opened" | "incident_closed"; data: Incident }
+  | { kind: "flapping_started" | "flapping_stopped"; data: { change_rate: number } }
+  | { kind: "alert_firing" | "alert_resolved"; data: Alert }
+  | { kind: "health"; data: { from: Health; to: Health } }
+  | { kind: "webhook_delivery"; data: WebhookDelivery }
+  | { kind: "hook_run"; data: HookRun }
+  | { kind: "email_digest"; data: EmailDigest }
+);
+
+export interface NotificationSettings {
+  desktop: boolean;
+  quiet_period_ms: number;
+}
+
+export interface Webhook {
+  id: string;
+  url: string;
+  /** Values are `********` when read; sent back that way they're kept. */
+  headers?: Record<string, string>;
+  /** JSON with `{{variable}}` placeholders; the plain details when absent. */
+  template?: string;
+  /** Only these targets; targets with `notify` set when empty. */
+  targets?: string[];
+  enabled: boolean;
+}
+
+/** Returned by `test_webhook` and logged as `webhook_delivery` events. */
+export interface WebhookDelivery {
+  webhook_id: string;
+  url: string;
+  event: string;
+  ok: boolean;
+  attempts: number;
+  status?: number;
+  error?: string;
+}
+
+/** A program run on down/up; gets the details as `CP_*` variables. */
+export interface CommandHook {
+  id: string;
+  program: string;
+  args?: string[];
+  /** Only these targets; targets with `notify` set when empty. */
+  targets?: string[];
+  on_down: boolean;
+  on_up: boolean;
+  timeout_ms: number;
+  enabled: boolean;
+}
+
+/** Returned by `test_hook` and logged as `hook_run` events. */
+export interface HookRun {
+  hook_id: string;
+  program: string;
+  event: string;
+  ok: boolean;
+  exit_code?: number;
+  duration_ms: number;
+  stdout: string;
+  stderr: string;
+  error?: string;
+}
+
+export type SmtpSecurity = "starttls" | "tls" | "none";
+
+export interface EmailSettings {
+  enabled: boolean;
+  host: string;
+  /** 587, 465 or 25 by `security` when absent. */
+  port?: number;
+  security: SmtpSecurity;
+  username: string;
+  /** `********` when read; sending that or "" back keeps the stored one. */
+  password: string;
+  from: string;
+  to: string[];
+  /** Only these targets; targets with `notify` set when empty. */
+  targets?: string[];
+  batch_ms: number;
+}
+
+/** Returned by `test_email` and logged as `email_digest` events. */
+export interface EmailDigest {
+  to: string[];
+  subject: string;
+  notices: number;
+  ok: boolean;
+  attempts: number;
+  error?: string;
+}
+
+/** Days of history kept per resolution; 0 keeps it forever. */
+export type Retention = Record<Resolution, number>;